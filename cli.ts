@@ -8,10 +8,10 @@ import {
   toml,
 } from "./deps.ts";
 
-import { Prompts } from "./prompts.ts";
+import { Compiler, isOlderVersion, KOTTOC_VERSION, kottocVersion, Prompts } from "./prompts.ts";
 import { Internal } from "./errors.ts";
 import {buildPrompts, makeController, urlFromModuleSpecifier} from "./mod.ts";
-import { runCargoInstall } from "./utils.ts";
+import { downloadWasm, runCargoInstall } from "./utils.ts";
 import * as log from "./log.ts";
 
 const SEMVER = "0.1.0";
@@ -181,25 +181,37 @@ try running:
   const source_url = urlFromModuleSpecifier(args.path);
 
   let prompts;
-  if (args.prompts === undefined) {
-    const temp_dir = await Deno.makeTempDir({
-      prefix: "kotto-",
-    });
-
-    const prompts_url = await buildPrompts({
-      source_url,
-      work_dir: temp_dir
-    });
-
-    prompts = await Prompts.fromBuiltUrl(prompts_url)
-
-    await Deno.remove(prompts_url)
-
-    // Note: ensure { recursive: true } is *not* passed to this; the prompts files should
-    // be deleted before we get here, otherwise this is an error.
-    await Deno.remove(temp_dir)
-  } else {
+  if (args.prompts !== undefined) {
     prompts = await Prompts.fromBuiltUrl(urlFromModuleSpecifier(args.prompts));
+  } else {
+    const version = await kottocVersion();
+    if (version === undefined || isOlderVersion(version, KOTTOC_VERSION)) {
+      // Without `kottoc serve`, prompts are built to disk
+      log.eprint(
+        `kottoc ${version ?? "(unknown version)"} is older than this runtime's (${KOTTOC_VERSION}): run \`kotto upgrade\``,
+        "kotto",
+        "yellow",
+      );
+      const temp_dir = await Deno.makeTempDir({
+        prefix: "kotto-",
+      });
+      try {
+        prompts = await Prompts.fromBuiltUrl(await buildPrompts({
+          source_url,
+          work_dir: temp_dir,
+        }));
+      } finally {
+        // Along with the declarations written next to the prompts
+        await Deno.remove(temp_dir, { recursive: true });
+      }
+    } else {
+      const compiler = new Compiler();
+      try {
+        prompts = await compiler.compile(source_url);
+      } finally {
+        await compiler.close();
+      }
+    }
   }
 
   const ctl = await makeController({
@@ -291,6 +303,8 @@ async function upgrade() {
   log.info("installing kottoc...");
   // TODO upgrade this cli too
   await runCargoInstall();
+  log.info("installing kottoc for WebAssembly...");
+  await downloadWasm();
   log.info("kottoc is installed 🎉");
 }
 
@@ -72,6 +72,8 @@ Each of your prompts must be of the following valid JSON form:
    ]
 }
 
+Arguments may also be given by name, as an object (e.g. { "query": "kotto", "limit": 10 }) instead of an array.
+
 You must make sure that the function you are calling accepts the arguments you give it.
 
 Let's begin!`;
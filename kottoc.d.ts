@@ -0,0 +1,73 @@
+// Generated by kottoc (`UPDATE_DECLARATIONS=1 cargo test -p kottoc`), do not edit
+export type PromptTy = "plain_text" | "ts";
+export type PromptAstTy = "method_decl" | "class_decl" | "class_prop" | "type_alias_decl" | "interface_decl" | "var_decl" | "fn_decl";
+export type PromptId = string;
+export type ContextId<Id extends string = PromptId> = `${PromptAstTy}.${Id}`;
+
+export type Coercion = { "to": "number" } | { "to": "boolean" } | { "to": "string" } | { "to": "date" } | { "to": "enum", values: Array<string>, } | { "to": "array", elem?: Coercion, } | { "to": "object", props: { [key in string]?: Coercion }, };
+
+export type PromptParam = { 
+/**
+ * The name of the parameter, or `argN` if it is the destructured `N`th
+ * one (counting from 0). Parameters without a name (when `argN` is the
+ * name of another) can only be given positionally.
+ */
+name?: string, 
+/**
+ * Whether the parameter may be left out, which is also the case of
+ * parameters with a default value
+ */
+optional?: boolean, 
+/**
+ * Whether the parameter has a default value
+ */
+default?: boolean, 
+/**
+ * Whether this is a rest parameter, which takes the remaining arguments
+ */
+rest?: boolean, coerce?: Coercion, };
+
+export type PromptNode<Id extends string = PromptId> = { type: PromptTy, fmt: string, id: Id, ast_ty: PromptAstTy | null, context?: Array<ContextId<Id>>, 
+/**
+ * The parameters of method and function declarations
+ */
+params?: Array<PromptParam>, };
+
+export type Source = { 
+/**
+ * The URL of the module if remote, otherwise its path relative to the
+ * root or output directory the prompts were built for (e.g.
+ * `../src/agent.ts`), so that builds do not depend on where they are made.
+ * Prompts compiled in memory keep the URLs of local modules too.
+ */
+specifier: string, 
+/**
+ * The SHA-256 of the source of the module
+ */
+hash: string, 
+/**
+ * The namespace that the ids of the module's prompts are qualified with,
+ * in a bundle
+ */
+namespace?: string, 
+/**
+ * The builtin functions that the module opts in to (e.g. `Deno.remove`),
+ * unknown for modules built before they were recorded
+ */
+builtins: Array<string> | null, };
+
+export type Meta = { format: number, 
+/**
+ * The version of kottoc that wrote (or migrated) the module
+ */
+kottoc: string, 
+/**
+ * The modules the prompts were compiled from
+ */
+sources: Array<Source>, 
+/**
+ * A fingerprint of the options the prompts were compiled with, unknown
+ * for migrated modules
+ */
+options: string | null, };
+
@@ -1,10 +1,123 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use deno_ast::ModuleSpecifier;
+
+/// Parses a module given as a URL or a path.
+fn module_specifier(path: &str) -> Result<ModuleSpecifier, String> {
+    kottoc::inputs::module_specifier(path).map_err(|err| err.to_string())
+}
 
 #[derive(Parser, Debug)]
+#[command(version, args_conflicts_with_subcommands = true)]
 pub struct Flags {
-    pub paths: Vec<deno_ast::ModuleSpecifier>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    #[command(flatten)]
+    pub compile: CompileFlags,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Upgrade prompts modules built by previous versions to the current format, in place
+    Migrate {
+        paths: Vec<PathBuf>
+    },
+    /// Show a prompt of a module, along with the declarations in its context
+    Inspect {
+        /// The module: a URL or a path
+        #[clap(value_parser = module_specifier)]
+        path: ModuleSpecifier,
+        /// The id of the prompt (e.g. `Agent.search`)
+        id: String,
+        /// Print JSON, for tooling
+        #[clap(long)]
+        json: bool,
+        #[command(flatten)]
+        options: OptionFlags,
+    },
+    /// Run a language server over stdio, previewing the prompts of the modules
+    /// being edited
+    Lsp {
+        #[command(flatten)]
+        options: OptionFlags,
+    },
+    /// Compile modules on request, keeping their prompts in memory across
+    /// builds
+    Serve {
+        /// Serve JSON-RPC requests over stdin and stdout
+        #[clap(long, required = true)]
+        stdio: bool,
+    },
+    /// Compare the prompts of two builds, exiting with 1 if they differ (and 2
+    /// on errors)
+    Diff {
+        /// The old build: a module, or a prompts module (`.prompts.js`), as a
+        /// URL or a path
+        #[clap(value_parser = module_specifier)]
+        old: ModuleSpecifier,
+        /// The new build, of the same kind
+        #[clap(value_parser = module_specifier, conflicts_with = "rev", required_unless_present = "rev")]
+        new: Option<ModuleSpecifier>,
+        /// Compare OLD at the git revision REV with OLD in the working tree,
+        /// or (if given twice) at the second revision
+        #[clap(long, value_name = "REV")]
+        rev: Vec<String>,
+        /// Print JSON, for tooling
+        #[clap(long)]
+        json: bool,
+        #[command(flatten)]
+        options: OptionFlags,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct CompileFlags {
+    /// The modules to compile: URLs, paths, directories (searched for `.ts`,
+    /// `.tsx`, `.js` and `.mts` modules) or glob patterns (e.g. `agents/**/*.ts`)
+    pub paths: Vec<String>,
+    /// Do not search the paths matching PATTERN (in addition to the ones
+    /// excluded by `.gitignore` files and the `exclude` of `deno.json`)
+    #[clap(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+    /// Only compile the modules found in directories (or by patterns) that
+    /// import kotto or expose methods with `@use`
+    #[clap(long)]
+    pub agents: bool,
     #[clap(short)]
-    pub output: Option<PathBuf>
+    pub output: Option<PathBuf>,
+    #[command(flatten)]
+    pub options: OptionFlags,
+    /// Compile all the modules into a single prompts module (`bundle.prompts.js`),
+    /// in which ids are qualified by the names of their modules
+    #[clap(long)]
+    pub bundle: bool,
+    /// Mirror the tree of the modules relative to ROOT in the output directory
+    #[clap(long, conflicts_with = "bundle")]
+    pub root: Option<PathBuf>,
+    /// Check that the files in the output directory are up to date, without
+    /// writing anything, exiting with 1 if they are not (and 2 on errors)
+    #[clap(long, requires = "output")]
+    pub check: bool,
+    /// Compile up to N modules at a time (defaults to the number of threads
+    /// available)
+    #[clap(short, long, value_name = "N")]
+    pub jobs: Option<usize>,
+}
+
+/// The options that change what is compiled.
+#[derive(Args, Debug)]
+pub struct OptionFlags {
+    /// Emit instantiated forms of generic type aliases used in signatures
+    /// (builtin utility types such as `Pick` always are)
+    #[clap(long)]
+    pub expand_generics: bool,
+    /// Only emit the declarations that the methods exposed with `@use` and the
+    /// exported functions refer to
+    #[clap(long)]
+    pub tree_shake: bool,
+    /// Inline the type aliases whose definitions are at most SIZE characters
+    /// long into signatures
+    #[clap(long, value_name = "SIZE")]
+    pub inline_aliases: Option<usize>,
 }
@@ -0,0 +1,31 @@
+/** The response to a request, with its body read as text */
+interface FetchResponse {
+    status: number;
+    ok: boolean;
+    headers: Record<string, string>;
+    body: string;
+}
+
+/** Sends an HTTP request to `url` */
+declare function fetch(url: string, init?: {
+    method?: string;
+    headers?: Record<string, string>;
+    body?: string;
+}): Promise<FetchResponse>;
+
+declare namespace Deno {
+    /** The current working directory */
+    export function cwd(): string;
+
+    /** Reads the whole file at `path` as UTF-8 text */
+    export function readTextFile(path: string): Promise<string>;
+
+    /** Writes `data` to the file at `path`, replacing its contents */
+    export function writeTextFile(path: string, data: string): Promise<void>;
+
+    /** Creates the directory at `path` */
+    export function mkdir(path: string, options?: { recursive?: boolean }): Promise<void>;
+
+    /** Removes the file or (empty) directory at `path` */
+    export function remove(path: string): Promise<void>;
+}
@@ -0,0 +1,69 @@
+use deno_ast::ModuleSpecifier;
+use deno_ast::swc::common::comments::SingleThreadedComments;
+
+/// The version of the bundled declarations. Bump it whenever they change, as
+/// the prompts built with them change too.
+pub const BUILTINS_VERSION: u32 = 1;
+
+/// Declarations of the builtin types (e.g. `Date`, `Pick` or `Response`) that
+/// names which do not resolve to any declaration of the compiled modules fall
+/// back to.
+///
+/// These are only added to the context of the prompts that refer to them, so
+/// they are kept short: they tell the model what a value looks like (as JSON)
+/// rather than document the whole API.
+pub const TYPES: &str = include_str!("types.d.ts");
+
+pub fn types_specifier() -> ModuleSpecifier {
+    ModuleSpecifier::parse(&format!("kotto:builtins@{}/types.d.ts", BUILTINS_VERSION)).unwrap()
+}
+
+/// Declarations of the builtin functions (e.g. `fetch` or `Deno.readTextFile`)
+/// that a module can expose to its agents, see [`requested_functions`].
+///
+/// The runtime calls them on the agent's behalf, converting what they return
+/// to JSON, so their signatures describe the converted values.
+pub const FUNCTIONS: &str = include_str!("functions.d.ts");
+
+/// The comment that opts a module in to builtin functions, followed by the
+/// names of these (e.g. `// kotto:builtins fetch Deno.readTextFile`). A
+/// namespace (e.g. `Deno`) stands for all of its functions. Names that are not
+/// declared in [`FUNCTIONS`] fail the compilation.
+pub const PRAGMA: &str = "kotto:builtins";
+
+pub fn functions_specifier() -> ModuleSpecifier {
+    ModuleSpecifier::parse(&format!("kotto:builtins@{}/functions.d.ts", BUILTINS_VERSION)).unwrap()
+}
+
+/// Whether `specifier` is that of the bundled declarations.
+pub fn is_builtin(specifier: &ModuleSpecifier) -> bool {
+    specifier.scheme() == "kotto"
+}
+
+/// The names of the builtin functions that the pragmas among `comments` opt in to.
+pub fn requested_functions(comments: &SingleThreadedComments) -> Vec<String> {
+    let (leading, trailing) = comments.borrow_all();
+
+    let mut names = Vec::new();
+    for comment in leading.values().chain(trailing.values()).flatten() {
+        let Some(list) = comment.text.trim().strip_prefix(PRAGMA) else {
+            continue
+        };
+        if list.starts_with(|c: char| !c.is_whitespace()) {
+            continue
+        }
+        names.extend(
+            list.split(|c: char| c.is_whitespace() || c == ',')
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+        );
+    }
+    names
+}
+
+/// Whether `name` (e.g. `Deno.readTextFile`) is one of the `requested` functions.
+pub fn is_requested(requested: &[String], name: &str) -> bool {
+    requested.iter().any(|requested| {
+        name == requested || name.strip_prefix(requested.as_str()).map(|rest| rest.starts_with('.')).unwrap_or(false)
+    })
+}
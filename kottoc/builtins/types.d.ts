@@ -0,0 +1,74 @@
+/** A point in time, as an ISO 8601 string (e.g. "2023-08-01T12:00:00.000Z") */
+interface Date {
+    toISOString(): string;
+}
+
+/** An absolute URL, as a string (e.g. "https://example.com/path?query=value") */
+interface URL {
+    href: string;
+    origin: string;
+    pathname: string;
+    search: string;
+}
+
+/** An object whose keys are `K` and whose values are `T` */
+type Record<K extends string | number | symbol, T> = {
+    [P in K]: T;
+};
+
+/** `T`, with all of its properties optional */
+type Partial<T> = {
+    [P in keyof T]?: T[P];
+};
+
+/** `T`, with all of its properties required */
+type Required<T> = {
+    [P in keyof T]-?: T[P];
+};
+
+/** `T`, with all of its properties readonly */
+type Readonly<T> = {
+    readonly [P in keyof T]: T[P];
+};
+
+/** `T`, with only the properties `K` */
+type Pick<T, K extends keyof T> = {
+    [P in K]: T[P];
+};
+
+/** `T`, without the properties `K` */
+type Omit<T, K extends string | number | symbol> = Pick<T, Exclude<keyof T, K>>;
+
+/** The members of the union `T` that are not assignable to `U` */
+type Exclude<T, U> = T extends U ? never : T;
+
+/** The members of the union `T` that are assignable to `U` */
+type Extract<T, U> = T extends U ? T : never;
+
+/** The eventual result (of type `T`) of an asynchronous operation */
+interface Promise<T> {
+    then<R>(onfulfilled: (value: T) => R): Promise<R>;
+}
+
+/** HTTP headers, by (case-insensitive) name */
+interface Headers {
+    get(name: string): string | null;
+}
+
+/** An HTTP request */
+interface Request {
+    method: string;
+    url: string;
+    headers: Headers;
+    text(): Promise<string>;
+    json(): Promise<unknown>;
+}
+
+/** An HTTP response */
+interface Response {
+    status: number;
+    ok: boolean;
+    headers: Headers;
+    text(): Promise<string>;
+    json(): Promise<unknown>;
+}
@@ -0,0 +1,140 @@
+use std::collections::{BTreeMap, HashSet};
+
+use deno_ast::ModuleSpecifier;
+
+use crate::{ast, builtins};
+use crate::common::DUMMY_SP;
+use crate::eval::TypeEvaluator;
+use crate::prompts::Coercion;
+
+/// Derives how the runtime may repair a value given by the model for the
+/// (evaluated) type `ty`, e.g. `"42"` for a `number`.
+pub fn coercion_of(evaluator: &TypeEvaluator, ty: &ast::TsType) -> Option<Coercion> {
+    CoercionFinder::new(evaluator).find(ty)
+}
+
+struct CoercionFinder<'e, 'g> {
+    evaluator: &'e TypeEvaluator<'g>,
+    /// The declarations being looked into, so that recursive types terminate
+    visiting: HashSet<(ModuleSpecifier, ast::Id)>,
+}
+
+impl<'e, 'g> CoercionFinder<'e, 'g> {
+    fn new(evaluator: &'e TypeEvaluator<'g>) -> Self {
+        Self {
+            evaluator,
+            visiting: HashSet::new()
+        }
+    }
+
+    fn find(&mut self, ty: &ast::TsType) -> Option<Coercion> {
+        use ast::TsKeywordTypeKind::*;
+
+        match ty {
+            ast::TsType::TsKeywordType(keyword) => match keyword.kind {
+                TsNumberKeyword => Some(Coercion::Number),
+                TsBooleanKeyword => Some(Coercion::Boolean),
+                TsStringKeyword => Some(Coercion::String),
+                _ => None
+            },
+            ast::TsType::TsParenthesizedType(parenthesized) => self.find(&parenthesized.type_ann),
+            ast::TsType::TsTypeOperator(ast::TsTypeOperator { op: ast::TsTypeOperatorOp::ReadOnly, type_ann, .. }) => {
+                self.find(type_ann)
+            }
+            ast::TsType::TsArrayType(array) => Some(Coercion::Array {
+                elem: self.find(&array.elem_type).map(Box::new)
+            }),
+            ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type)) => {
+                // `T | null` and `T | undefined` coerce like `T`
+                let types: Vec<&ast::TsType> = union_type.types.iter()
+                    .map(|ty| &**ty)
+                    .filter(|ty| !matches!(ty, ast::TsType::TsKeywordType(ast::TsKeywordType {
+                        kind: TsNullKeyword | TsUndefinedKeyword,
+                        ..
+                    })))
+                    .collect();
+
+                if let [ty] = types.as_slice() {
+                    return self.find(ty)
+                }
+
+                let values = types.iter()
+                    .map(|ty| match ty {
+                        ast::TsType::TsLitType(ast::TsLitType { lit: ast::TsLit::Str(str), .. }) => Some(str.value.to_string()),
+                        _ => None
+                    })
+                    .collect::<Option<Vec<String>>>()?;
+                Some(Coercion::Enum { values })
+            }
+            ast::TsType::TsTypeLit(type_lit) => self.find_members(&type_lit.members),
+            ast::TsType::TsTypeRef(type_ref) => {
+                let type_args = type_ref.type_params.as_ref().map(|type_params| type_params.params.as_slice()).unwrap_or_default();
+                if let (ast::TsEntityName::Ident(ident), [elem_type]) = (&type_ref.type_name, type_args) {
+                    if &*ident.sym == "Array" || &*ident.sym == "ReadonlyArray" {
+                        return Some(Coercion::Array {
+                            elem: self.find(elem_type).map(Box::new)
+                        })
+                    }
+                }
+
+                let decl = self.evaluator.resolve(type_ref)?;
+                if decl.module.specifier == builtins::types_specifier() {
+                    return match &*decl.id.0 {
+                        "Date" => Some(Coercion::Date),
+                        "URL" => Some(Coercion::String),
+                        _ => None
+                    }
+                }
+
+                let key = (decl.module.specifier.clone(), decl.id.clone());
+                if !self.visiting.insert(key.clone()) {
+                    return None
+                }
+
+                let filtered_module = &decl.module.filtered_module;
+                let coercion = if let Some(type_alias_decl) = filtered_module.type_alias_decls.get(&decl.id) {
+                    match type_alias_decl.type_params {
+                        Some(_) => None,
+                        None => self.find(&self.evaluator.evaluate(&decl.module, &type_alias_decl.type_ann))
+                    }
+                } else if let Some(interface_decl) = filtered_module.interface_decls.get(&decl.id) {
+                    let type_lit = ast::TsType::TsTypeLit(ast::TsTypeLit {
+                        span: DUMMY_SP,
+                        members: interface_decl.body.body.clone()
+                    });
+                    self.find(&self.evaluator.evaluate(&decl.module, &type_lit))
+                } else {
+                    None
+                };
+
+                self.visiting.remove(&key);
+                coercion
+            }
+            _ => None
+        }
+    }
+
+    fn find_members(&mut self, members: &[ast::TsTypeElement]) -> Option<Coercion> {
+        let mut props = BTreeMap::new();
+        for member in members {
+            let ast::TsTypeElement::TsPropertySignature(property) = member else {
+                continue
+            };
+            let key = match &*property.key {
+                ast::Expr::Ident(ident) if !property.computed => ident.sym.to_string(),
+                ast::Expr::Lit(ast::Lit::Str(str)) => str.value.to_string(),
+                _ => continue
+            };
+            if let Some(coercion) = property.type_ann.as_ref().and_then(|type_ann| self.find(&type_ann.type_ann)) {
+                props.insert(key, coercion);
+            }
+        }
+
+        // Objects are only worth repairing if some of their properties are
+        if props.is_empty() {
+            None
+        } else {
+            Some(Coercion::Object { props })
+        }
+    }
+}
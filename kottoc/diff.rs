@@ -0,0 +1,587 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use deno_ast::ModuleSpecifier;
+use serde::Serialize;
+
+use crate::{ast, format, inline, inspect, tasks, visit};
+use crate::{AnyError, anyhow};
+use crate::graph::{self, ModuleGraph};
+use crate::prompts::{Prompt, PromptAstType, PromptParam, Prompts};
+use crate::signature::Param;
+use crate::tasks::{CompileOptions, PROMPTS_EXT};
+
+use visit::{Visit, VisitWith};
+
+/// The prompts of the build `specifier`: a module, which is compiled, or a
+/// prompts module (ending with `.prompts.js`), which is read as is.
+async fn load(specifier: &ModuleSpecifier, options: &CompileOptions) -> Result<Prompts, AnyError> {
+    if specifier.path().ends_with(&format!(".{}", PROMPTS_EXT)) {
+        let text = graph::fetch_module_source(specifier).await?;
+        return format::read_prompts(&text).map_err(|err| anyhow!("{}: {}", specifier, err))
+    }
+
+    let mut module_graph = ModuleGraph::default();
+    let (_, prompts, _) = tasks::compile_module(&mut module_graph, specifier, options).await?;
+    Ok(prompts)
+}
+
+fn git(dir: &Path, args: &[&str]) -> Result<String, AnyError> {
+    let output = Command::new("git").arg("-C").arg(dir).args(args).output()
+        .map_err(|err| anyhow!("cannot run git: {}", err))?;
+    if !output.status.success() {
+        return Err(anyhow!("git {}: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()))
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// A checkout of a revision of a git repository, in a temporary worktree that
+/// is removed once dropped.
+struct Checkout {
+    repository: PathBuf,
+    dir: PathBuf,
+}
+
+/// The number of checkouts made by this process, which tells their
+/// directories apart (e.g. when comparing a revision with itself).
+static CHECKOUTS: AtomicUsize = AtomicUsize::new(0);
+
+impl Checkout {
+    fn new(repository: &Path, rev: &str) -> Result<Self, AnyError> {
+        let dir = std::env::temp_dir().join(format!(
+            "kottoc-diff-{}-{}-{}",
+            std::process::id(),
+            CHECKOUTS.fetch_add(1, Ordering::Relaxed),
+            rev.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        ));
+        // Left over by a process that had the same id but did not get to
+        // remove it (e.g. as it was killed)
+        if dir.exists() {
+            let _ = git(repository, &["worktree", "remove", "--force", &dir.to_string_lossy()]);
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+        }
+        // Forgets the worktrees whose directories are gone
+        git(repository, &["worktree", "prune"])?;
+        git(repository, &["worktree", "add", "--quiet", "--detach", &dir.to_string_lossy(), rev])?;
+        Ok(Self {
+            repository: repository.to_path_buf(),
+            dir
+        })
+    }
+}
+
+impl Drop for Checkout {
+    fn drop(&mut self) {
+        let _ = git(&self.repository, &["worktree", "remove", "--force", &self.dir.to_string_lossy()]);
+    }
+}
+
+/// The prompts of `specifier` (a local module or prompts module) at the git
+/// revision `rev`, which is checked out along with the rest of its repository
+/// for the imports of the module to resolve.
+async fn load_at(specifier: &ModuleSpecifier, rev: &str, options: &CompileOptions) -> Result<Prompts, AnyError> {
+    let path = specifier.to_file_path()
+        .map_err(|_| anyhow!("not a local path: {}", specifier))?
+        .canonicalize()?;
+    let repository = PathBuf::from(git(path.parent().unwrap_or(&path), &["rev-parse", "--show-toplevel"])?)
+        .canonicalize()?;
+    let relative = path.strip_prefix(&repository)?;
+
+    let checkout = Checkout::new(&repository, rev)?;
+    let specifier = ModuleSpecifier::from_file_path(checkout.dir.join(relative))
+        .map_err(|_| anyhow!("not a local path: {}", relative.display()))?;
+    load(&specifier, options).await
+        .map_err(|err| anyhow!("at {}: {}", rev, err))
+}
+
+/// The key prompts are matched by across builds: their node type and id,
+/// without syntax contexts (which shift as unrelated code changes). It is the
+/// form that context ids take once stripped.
+fn key_of(prompt: &Prompt) -> String {
+    format!("{}.{}", inspect::ast_ty_name(prompt), inspect::plain_id(&prompt.id))
+}
+
+/// The prompts of a build, by their keys.
+fn by_key(prompts: &Prompts) -> BTreeMap<String, &Prompt> {
+    let mut by_key = BTreeMap::new();
+    for prompt in &prompts.0 {
+        by_key.entry(key_of(prompt)).or_insert(prompt);
+    }
+    by_key
+}
+
+/// The keys of the declarations in the closure of the context of `prompt`.
+fn context_closure(prompts: &Prompts, prompt: &Prompt) -> BTreeSet<String> {
+    let mut closure = BTreeSet::new();
+    let mut seen = HashSet::new();
+    let mut to_visit: Vec<&str> = prompt.context.iter().map(|id| &**id).collect();
+    while let Some(context_id) = to_visit.pop() {
+        if !seen.insert(context_id) {
+            continue
+        }
+        closure.insert(inspect::plain_id(context_id));
+        if let Some(referenced) = inspect::find_context(prompts, context_id) {
+            to_visit.extend(referenced.context.iter().map(|id| &**id));
+        }
+    }
+    closure
+}
+
+/// Finds the first function of a program.
+struct FunctionVisitor<'m>(&'m mut Option<ast::Function>);
+
+impl<'m> Visit for FunctionVisitor<'m> {
+    fn visit_function(&mut self, n: &ast::Function) {
+        if self.0.is_none() {
+            *self.0 = Some(n.clone());
+        }
+    }
+}
+
+/// The type of each parameter of the method or function `prompt` declares,
+/// and its return type, as written in its declaration.
+async fn signature_of(prompt: &Prompt) -> Option<(Vec<Option<String>>, Option<String>)> {
+    let source = match prompt.ast_ty {
+        Some(PromptAstType::MethodDecl) => format!("declare class _ {{ {} }}", prompt.fmt.0),
+        Some(PromptAstType::FnDecl) => prompt.fmt.0.clone(),
+        _ => return None
+    };
+    let parsed_source = tasks::parse_module("signature.ts".to_string(), source).await.ok()?;
+
+    let mut function = None;
+    parsed_source.module().visit_with(&mut FunctionVisitor(&mut function));
+    let function = function?;
+
+    let param_types = function.params.iter()
+        .map(|param| Param::from_param(param).type_ann.map(|type_ann| inline::type_text(&type_ann)))
+        .collect();
+    let return_type = function.return_type.as_ref().map(|type_ann| inline::type_text(&type_ann.type_ann));
+    Some((param_types, return_type))
+}
+
+/// A parameter, as compared across builds.
+#[derive(Serialize, PartialEq)]
+struct ParamSignature {
+    name: Option<String>,
+    #[serde(rename = "type")]
+    ty: Option<String>,
+    optional: bool,
+    default: bool,
+    rest: bool,
+    coerce: Option<serde_json::Value>,
+}
+
+impl ParamSignature {
+    fn new(param: &PromptParam, ty: Option<String>) -> Self {
+        Self {
+            name: param.name.clone(),
+            ty,
+            optional: param.optional,
+            default: param.default,
+            rest: param.rest,
+            coerce: param.coerce.as_ref().and_then(|coerce| serde_json::to_value(coerce).ok())
+        }
+    }
+
+    fn describe(&self) -> String {
+        let mut description = self.name.clone().unwrap_or_else(|| "(destructured)".to_string());
+        if self.rest {
+            description.insert_str(0, "...");
+        }
+        if self.optional {
+            description.push('?');
+        }
+        if let Some(ty) = &self.ty {
+            description.push_str(&format!(": {}", ty));
+        }
+        if self.default {
+            description.push_str(" (with a default)");
+        }
+        description
+    }
+}
+
+async fn param_signatures(prompt: &Prompt) -> (Vec<ParamSignature>, Option<String>) {
+    let (param_types, return_type) = signature_of(prompt).await.unwrap_or_default();
+    let params = prompt.params.iter()
+        .enumerate()
+        .map(|(i, param)| ParamSignature::new(param, param_types.get(i).cloned().flatten()))
+        .collect();
+    (params, return_type)
+}
+
+/// How the parameter at `position` (starting from 1) changed.
+#[derive(Serialize)]
+struct ParamChange {
+    position: usize,
+    old: Option<ParamSignature>,
+    new: Option<ParamSignature>,
+    /// What changed, e.g. `type: string -> number`
+    changes: Vec<String>,
+}
+
+fn compare_params(old: Vec<ParamSignature>, new: Vec<ParamSignature>) -> Vec<ParamChange> {
+    let len = old.len().max(new.len());
+    let mut old = old.into_iter();
+    let mut new = new.into_iter();
+
+    let mut param_changes = Vec::new();
+    for position in 1..=len {
+        let (old, new) = (old.next(), new.next());
+        let mut changes = Vec::new();
+        match (&old, &new) {
+            (Some(old), Some(new)) if old == new => continue,
+            (Some(old), Some(new)) => {
+                let mut compare = |what: &str, old: String, new: String| {
+                    if old != new {
+                        changes.push(format!("{}: {} -> {}", what, old, new));
+                    }
+                };
+                let or_none = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_string());
+                compare("name", or_none(&old.name), or_none(&new.name));
+                compare("type", or_none(&old.ty), or_none(&new.ty));
+                compare("optional", old.optional.to_string(), new.optional.to_string());
+                compare("default", old.default.to_string(), new.default.to_string());
+                compare("rest", old.rest.to_string(), new.rest.to_string());
+                compare("coercion", or_none(&old.coerce.as_ref().map(|coerce| coerce.to_string())), or_none(&new.coerce.as_ref().map(|coerce| coerce.to_string())));
+            }
+            (None, Some(new)) => changes.push(format!("added {}", new.describe())),
+            (Some(old), None) => changes.push(format!("removed {}", old.describe())),
+            (None, None) => continue
+        }
+        param_changes.push(ParamChange { position, old, new, changes });
+    }
+    param_changes
+}
+
+/// How a prompt present in both builds changed.
+#[derive(Serialize, Default)]
+struct Change {
+    key: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    params: Vec<ParamChange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    returns: Option<(Option<String>, Option<String>)>,
+    /// The declarations that entered the closure of the context
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    context_added: Vec<String>,
+    /// The declarations that left the closure of the context
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    context_removed: Vec<String>,
+    /// The declarations of the closure of the context that changed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    context_changed: Vec<String>,
+    /// The old and new text of the declaration, if it changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fmt: Option<(String, String)>,
+}
+
+impl Change {
+    fn is_empty(&self) -> bool {
+        self.params.is_empty()
+            && self.returns.is_none()
+            && self.context_added.is_empty()
+            && self.context_removed.is_empty()
+            && self.context_changed.is_empty()
+            && self.fmt.is_none()
+    }
+}
+
+/// The differences between two builds.
+#[derive(Serialize, Default)]
+struct Diff {
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<Change>,
+}
+
+impl Diff {
+    fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+async fn compare(old_prompts: &Prompts, new_prompts: &Prompts) -> Diff {
+    let old = by_key(old_prompts);
+    let new = by_key(new_prompts);
+
+    let mut diff = Diff {
+        added: new.keys().filter(|key| !old.contains_key(*key)).cloned().collect(),
+        removed: old.keys().filter(|key| !new.contains_key(*key)).cloned().collect(),
+        ..Default::default()
+    };
+
+    for (key, old_prompt) in &old {
+        let Some(new_prompt) = new.get(key) else {
+            continue
+        };
+        let mut change = Change {
+            key: key.clone(),
+            ..Default::default()
+        };
+
+        let (old_params, old_return) = param_signatures(old_prompt).await;
+        let (new_params, new_return) = param_signatures(new_prompt).await;
+        change.params = compare_params(old_params, new_params);
+        if old_return != new_return {
+            change.returns = Some((old_return, new_return));
+        }
+
+        let old_closure = context_closure(old_prompts, old_prompt);
+        let new_closure = context_closure(new_prompts, new_prompt);
+        change.context_added = new_closure.difference(&old_closure).cloned().collect();
+        change.context_removed = old_closure.difference(&new_closure).cloned().collect();
+        change.context_changed = old_closure.intersection(&new_closure)
+            .filter(|context_key| match (old.get(*context_key), new.get(*context_key)) {
+                (Some(old_decl), Some(new_decl)) => old_decl.fmt.0 != new_decl.fmt.0,
+                _ => false
+            })
+            .cloned()
+            .collect();
+
+        if old_prompt.fmt.0 != new_prompt.fmt.0 {
+            change.fmt = Some((old_prompt.fmt.0.clone(), new_prompt.fmt.0.clone()));
+        }
+
+        if !change.is_empty() {
+            diff.changed.push(change);
+        }
+    }
+    diff
+}
+
+/// The lines of `old` and `new`, prefixed with `-` if they were removed, `+`
+/// if they were added, and a space if they were kept.
+fn line_diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // The lengths of the longest common subsequences of the suffixes
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            lines.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            lines.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+fn print_diff(diff: &Diff) {
+    for key in &diff.added {
+        println!("+ {}", key);
+    }
+    for key in &diff.removed {
+        println!("- {}", key);
+    }
+    for change in &diff.changed {
+        println!("~ {}", change.key);
+        for param_change in &change.params {
+            let name = param_change.old.as_ref()
+                .or(param_change.new.as_ref())
+                .and_then(|param| param.name.as_deref())
+                .unwrap_or("(destructured)");
+            println!("    param {} ({}): {}", param_change.position, name, param_change.changes.join(", "));
+        }
+        if let Some((old, new)) = &change.returns {
+            let or_none = |value: &Option<String>| value.clone().unwrap_or_else(|| "none".to_string());
+            println!("    returns: {} -> {}", or_none(old), or_none(new));
+        }
+        for key in &change.context_added {
+            println!("    context: + {}", key);
+        }
+        for key in &change.context_removed {
+            println!("    context: - {}", key);
+        }
+        for key in &change.context_changed {
+            println!("    context: ~ {}", key);
+        }
+        if let Some((old, new)) = &change.fmt {
+            for line in line_diff(old, new) {
+                println!("    {}", line);
+            }
+        }
+    }
+
+    if diff.is_empty() {
+        println!("no changes");
+    } else {
+        println!(
+            "{} added, {} removed, {} changed",
+            diff.added.len(),
+            diff.removed.len(),
+            diff.changed.len()
+        );
+    }
+}
+
+/// Compares the prompts of two builds, returning whether they differ.
+///
+/// The builds are `old` and `new` or, given git revisions `revs`, `old` at the
+/// first revision and `old` at the second one (or in the working tree).
+pub async fn diff(
+    old: &ModuleSpecifier,
+    new: Option<&ModuleSpecifier>,
+    revs: &[String],
+    as_json: bool,
+    options: &CompileOptions
+) -> Result<bool, AnyError> {
+    let (old_prompts, new_prompts) = match (new, revs) {
+        (Some(new), []) => (load(old, options).await?, load(new, options).await?),
+        (None, [rev]) => (load_at(old, rev, options).await?, load(old, options).await?),
+        (None, [old_rev, new_rev]) => (load_at(old, old_rev, options).await?, load_at(old, new_rev, options).await?),
+        (None, []) => return Err(anyhow!("nothing to compare {} with: give another build or a git revision", old)),
+        (Some(_), _) => return Err(anyhow!("git revisions can only be given to compare a single module")),
+        (None, _) => return Err(anyhow!("at most two git revisions can be compared"))
+    };
+
+    let diff = compare(&old_prompts, &new_prompts).await;
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        print_diff(&diff);
+    }
+    Ok(!diff.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    async fn prompts_of(source: &str) -> Prompts {
+        let specifier = ModuleSpecifier::parse("file:///agent.ts").unwrap();
+        let mut module_graph = ModuleGraph::with_sources(HashMap::from([(specifier.clone(), source.to_string())]));
+        let (_, prompts, _) = tasks::compile_module(&mut module_graph, &specifier, &CompileOptions::default()).await.unwrap();
+        prompts
+    }
+
+    async fn compare_sources(old: &str, new: &str) -> Diff {
+        compare(&prompts_of(old).await, &prompts_of(new).await).await
+    }
+
+    #[tokio::test]
+    async fn compare_added_removed_and_changed() {
+        let diff = compare_sources(
+            "interface A {}\ninterface B {}\n",
+            "interface A { x: number }\ninterface C {}\n"
+        ).await;
+        assert_eq!(diff.added, ["interface_decl.C"]);
+        assert_eq!(diff.removed, ["interface_decl.B"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].key, "interface_decl.A");
+        assert!(diff.changed[0].fmt.is_some());
+
+        let same = "interface A {}\nexport function f(a: A): void {}\n";
+        assert!(compare_sources(same, same).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn compare_param_changes() {
+        let diff = compare_sources(
+            "export function search(query: string, limit?: number): string[] { return []; }\n",
+            "export function search(query: number, ...tags: string[]): string[] { return []; }\n"
+        ).await;
+        let change = &diff.changed[0];
+        assert_eq!(change.key, "fn_decl.search");
+        assert!(change.returns.is_none());
+
+        let params: Vec<(usize, &Vec<String>)> = change.params.iter()
+            .map(|param_change| (param_change.position, &param_change.changes))
+            .collect();
+        assert_eq!(params, [
+            (1, &vec![
+                "type: string -> number".to_string(),
+                r#"coercion: {"to":"string"} -> {"to":"number"}"#.to_string()
+            ]),
+            (2, &vec![
+                "name: limit -> tags".to_string(),
+                "type: number -> string[]".to_string(),
+                "rest: false -> true".to_string(),
+                r#"coercion: {"to":"number"} -> {"elem":{"to":"string"},"to":"array"}"#.to_string()
+            ])
+        ]);
+    }
+
+    #[test]
+    fn compare_params_added_and_removed() {
+        let param = |name: &str, ty: &str| ParamSignature::new(
+            &PromptParam { name: Some(name.to_string()), ..PromptParam::default() },
+            Some(ty.to_string())
+        );
+        let changes = compare_params(vec![param("a", "string")], vec![param("a", "string"), param("b", "number")]);
+        assert_eq!(changes.len(), 1);
+        assert_eq!((changes[0].position, &changes[0].changes), (2, &vec!["added b: number".to_string()]));
+
+        let changes = compare_params(vec![param("a", "string")], Vec::new());
+        assert_eq!(changes[0].changes, ["removed a: string"]);
+    }
+
+    #[tokio::test]
+    async fn compare_context_closures() {
+        let old = prompts_of("type Q = string;\ninterface Item { q: Q }\nexport function find(item: Item): void {}\n").await;
+        let find = old.0.iter().find(|prompt| &*prompt.id == "find#2").unwrap();
+        assert_eq!(
+            context_closure(&old, find).into_iter().collect::<Vec<_>>(),
+            ["interface_decl.Item", "type_alias_decl.Q"]
+        );
+
+        let new = prompts_of("type Q = number;\ninterface Item { q: Q; tag: Tag }\ntype Tag = string;\nexport function find(item: Item): void {}\n").await;
+        let diff = compare(&old, &new).await;
+        let change = diff.changed.iter().find(|change| change.key == "fn_decl.find").unwrap();
+        assert_eq!(change.context_added, ["type_alias_decl.Tag"]);
+        assert!(change.context_removed.is_empty());
+        assert_eq!(change.context_changed, ["interface_decl.Item", "type_alias_decl.Q"]);
+        assert!(change.fmt.is_none());
+    }
+
+    #[test]
+    fn diff_lines() {
+        assert_eq!(line_diff("a\nb\nc", "a\nc\nd"), ["  a", "- b", "  c", "+ d"]);
+        assert_eq!(line_diff("", "a"), ["+ a"]);
+        assert_eq!(line_diff("a", "a"), ["  a"]);
+    }
+
+    #[tokio::test]
+    async fn diff_tells_whether_builds_differ() {
+        let dir = std::env::temp_dir().join(format!("kottoc-diff-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, source: &str| {
+            std::fs::write(dir.join(name), source).unwrap();
+            ModuleSpecifier::from_file_path(dir.join(name)).unwrap()
+        };
+        let a = write("a.ts", "interface A {}\n");
+        let b = write("b.ts", "interface A { x: number }\n");
+        let options = CompileOptions::default();
+
+        assert!(diff(&a, Some(&b), &[], true, &options).await.unwrap());
+        assert!(!diff(&a, Some(&a), &[], true, &options).await.unwrap());
+        assert!(diff(&a, None, &[], true, &options).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,14 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
 use std::io::Write;
 use std::ops::{Deref, DerefMut};
 use std::rc::Rc;
 
-use deno_ast::swc::common::SourceMap;
+use deno_ast::swc::common::{BytePos, SourceMap};
+use deno_ast::swc::common::comments::Comment;
 
-use crate::codegen;
+use crate::ast;
+use crate::codegen::{self, Node};
 use crate::common;
 
 pub struct Emitter<'a, W: Write>(
@@ -45,4 +49,95 @@ impl<'a, W: Write> Emitter<'a, W> {
         self.comments = Some(comments);
         self
     }
-}
\ No newline at end of file
+}
+/// Gives the emitter read-only access to comments: codegen takes comments out
+/// as it emits them, which would strip them from nodes emitted more than once
+/// (e.g. inherited members). Taken positions are remembered instead, so each
+/// comment is still only emitted once per node.
+pub struct ReadOnlyComments<'a> {
+    inner: &'a dyn common::comments::Comments,
+    taken_leading: RefCell<HashSet<BytePos>>,
+    taken_trailing: RefCell<HashSet<BytePos>>,
+}
+
+impl<'a> ReadOnlyComments<'a> {
+    pub fn new(inner: &'a dyn common::comments::Comments) -> Self {
+        Self {
+            inner,
+            taken_leading: RefCell::default(),
+            taken_trailing: RefCell::default()
+        }
+    }
+}
+
+/// Pragmas (e.g. `// kotto:builtins fetch`) are meant for kottoc, not the model.
+fn without_pragmas(comments: Vec<Comment>) -> Vec<Comment> {
+    comments.into_iter()
+        .filter(|comment| !comment.text.trim_start().starts_with("kotto:"))
+        .collect()
+}
+
+impl<'a> common::comments::Comments for ReadOnlyComments<'a> {
+    fn add_leading(&self, _: BytePos, _: Comment) {}
+
+    fn add_leading_comments(&self, _: BytePos, _: Vec<Comment>) {}
+
+    fn has_leading(&self, pos: BytePos) -> bool {
+        !self.taken_leading.borrow().contains(&pos) && self.inner.has_leading(pos)
+    }
+
+    fn move_leading(&self, _: BytePos, _: BytePos) {}
+
+    fn take_leading(&self, pos: BytePos) -> Option<Vec<Comment>> {
+        if self.taken_leading.borrow_mut().insert(pos) {
+            self.get_leading(pos)
+        } else {
+            None
+        }
+    }
+
+    fn get_leading(&self, pos: BytePos) -> Option<Vec<Comment>> {
+        self.inner.get_leading(pos).map(without_pragmas)
+    }
+
+    fn add_trailing(&self, _: BytePos, _: Comment) {}
+
+    fn add_trailing_comments(&self, _: BytePos, _: Vec<Comment>) {}
+
+    fn has_trailing(&self, pos: BytePos) -> bool {
+        !self.taken_trailing.borrow().contains(&pos) && self.inner.has_trailing(pos)
+    }
+
+    fn move_trailing(&self, _: BytePos, _: BytePos) {}
+
+    fn take_trailing(&self, pos: BytePos) -> Option<Vec<Comment>> {
+        if self.taken_trailing.borrow_mut().insert(pos) {
+            self.get_trailing(pos)
+        } else {
+            None
+        }
+    }
+
+    fn get_trailing(&self, pos: BytePos) -> Option<Vec<Comment>> {
+        self.inner.get_trailing(pos).map(without_pragmas)
+    }
+
+    fn add_pure_comment(&self, _: BytePos) {}
+}
+
+/// `ty` followed by the comment `note` (e.g. `number /* default: 10 */`).
+///
+/// Codegen has no place for comments right after types, so the type is
+/// written out ahead of time and the result stands for it as a reference
+/// named with the text: this is for copies of signatures that are about to
+/// be emitted, as it would be taken for a reference by anything else.
+pub fn noted_type(ty: &ast::TsType, note: &str) -> ast::TsType {
+    let mut buf = Vec::new();
+    ty.emit_with(&mut Emitter::new(&mut buf)).unwrap();
+    let text = format!("{} /* {} */", String::from_utf8(buf).unwrap(), note);
+    ast::TsType::TsTypeRef(ast::TsTypeRef {
+        span: common::DUMMY_SP,
+        type_name: ast::TsEntityName::Ident(ast::Ident::new(text.into(), common::DUMMY_SP)),
+        type_params: None
+    })
+}
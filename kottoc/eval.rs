@@ -0,0 +1,546 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use deno_ast::swc::common::EqIgnoreSpan;
+
+use crate::ast;
+use crate::common::DUMMY_SP;
+use crate::filter::TypeName;
+use crate::graph::{Module, ModuleGraph, Resolved};
+
+/// How many aliases deep types are expanded, so that recursive ones terminate.
+const MAX_DEPTH: usize = 16;
+
+type Bindings = HashMap<ast::Id, ast::TsType>;
+
+/// Evaluates types the way the TypeScript compiler would (for the simple
+/// cases): generic aliases are instantiated, and `keyof`, indexed access,
+/// mapped and conditional types are computed when their operands are known.
+/// This turns e.g. `Pick<Order, "id">` into `{ id: string }`.
+///
+/// References to (non-generic) declarations are kept as they are, but are
+/// rewritten to the id of the declaration they resolve to, so that they can
+/// still be looked up once taken out of the module they appear in.
+pub struct TypeEvaluator<'g> {
+    module_graph: &'g ModuleGraph,
+    resolved: RefCell<HashMap<ast::Id, Resolved>>,
+}
+
+impl<'g> TypeEvaluator<'g> {
+    /// Evaluation only looks into the modules that `module_graph` has already
+    /// loaded, which is the case of everything the (resolved) closure of the
+    /// evaluated types refers to.
+    pub fn new(module_graph: &'g ModuleGraph) -> Self {
+        Self {
+            module_graph,
+            resolved: RefCell::default()
+        }
+    }
+
+    pub fn evaluate(&self, module: &Rc<Module>, ty: &ast::TsType) -> ast::TsType {
+        self.eval(module, ty, &Bindings::new(), 0)
+    }
+
+    /// The declaration that an evaluated type reference refers to.
+    pub fn resolve(&self, type_ref: &ast::TsTypeRef) -> Option<Resolved> {
+        let ident = type_ref.type_name.as_ident()?;
+        self.resolved.borrow().get(&ident.to_id()).cloned()
+    }
+
+    /// The properties of an evaluated object type: object literals, references
+    /// to object types and interfaces, and intersections of these.
+    fn properties_at(&self, ty: &ast::TsType, depth: usize) -> Option<Vec<ast::TsPropertySignature>> {
+        if depth > MAX_DEPTH {
+            return None
+        }
+
+        match ty {
+            ast::TsType::TsTypeLit(type_lit) => Some(
+                type_lit.members.iter()
+                    .filter_map(|member| member.as_ts_property_signature().cloned())
+                    .collect()
+            ),
+            ast::TsType::TsParenthesizedType(parenthesized) => self.properties_at(&parenthesized.type_ann, depth),
+            ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsIntersectionType(intersection)) => {
+                let mut properties = Vec::new();
+                for ty in &intersection.types {
+                    properties.extend(self.properties_at(ty, depth + 1)?);
+                }
+                Some(properties)
+            }
+            ast::TsType::TsTypeRef(type_ref) => {
+                let decl = self.resolve(type_ref)?;
+                let filtered_module = &decl.module.filtered_module;
+                if let Some(type_alias_decl) = filtered_module.type_alias_decls.get(&decl.id) {
+                    if type_alias_decl.type_params.is_some() {
+                        return None
+                    }
+                    let ty = self.eval(&decl.module, &type_alias_decl.type_ann, &Bindings::new(), depth + 1);
+                    self.properties_at(&ty, depth + 1)
+                } else if let Some(interface_decl) = filtered_module.interface_decls.get(&decl.id) {
+                    let type_lit = ast::TsType::TsTypeLit(ast::TsTypeLit {
+                        span: DUMMY_SP,
+                        members: interface_decl.body.body.clone()
+                    });
+                    let ty = self.eval(&decl.module, &type_lit, &Bindings::new(), depth + 1);
+                    self.properties_at(&ty, depth + 1)
+                } else {
+                    None
+                }
+            }
+            _ => None
+        }
+    }
+
+    fn lookup(&self, module: &Rc<Module>, type_name: &TypeName) -> Option<Resolved> {
+        if type_name.path.is_empty() {
+            if let Some(decl) = self.resolved.borrow().get(&type_name.root) {
+                return Some(decl.clone())
+            }
+        }
+        self.module_graph.resolve_loaded(module, type_name)
+    }
+
+    fn eval(&self, module: &Rc<Module>, ty: &ast::TsType, bindings: &Bindings, depth: usize) -> ast::TsType {
+        let eval = |ty: &ast::TsType| self.eval(module, ty, bindings, depth);
+
+        match ty {
+            ast::TsType::TsTypeRef(type_ref) => self.eval_type_ref(module, type_ref, bindings, depth),
+            ast::TsType::TsParenthesizedType(parenthesized) => eval(&parenthesized.type_ann),
+            ast::TsType::TsTypeOperator(operator) => {
+                let operand = eval(&operator.type_ann);
+                if operator.op == ast::TsTypeOperatorOp::KeyOf {
+                    if let Some(properties) = self.properties_at(&operand, depth) {
+                        return union(properties.iter().filter_map(property_key).map(|key| string_lit(&key)).collect())
+                    }
+                }
+                ast::TsType::TsTypeOperator(ast::TsTypeOperator {
+                    type_ann: Box::new(operand),
+                    ..operator.clone()
+                })
+            }
+            ast::TsType::TsIndexedAccessType(indexed_access) => {
+                let obj_type = eval(&indexed_access.obj_type);
+                let index_type = eval(&indexed_access.index_type);
+
+                if let (Some(properties), Some(keys)) = (self.properties_at(&obj_type, depth), literal_keys(&index_type)) {
+                    let types: Option<Vec<ast::TsType>> = keys.iter()
+                        .map(|key| {
+                            let property = properties.iter().find(|property| property_key(property).as_ref() == Some(key))?;
+                            Some(property.type_ann.as_ref().map(|type_ann| (*type_ann.type_ann).clone()).unwrap_or_else(unknown))
+                        })
+                        .collect();
+                    if let Some(types) = types {
+                        return union(types)
+                    }
+                }
+
+                ast::TsType::TsIndexedAccessType(ast::TsIndexedAccessType {
+                    obj_type: Box::new(obj_type),
+                    index_type: Box::new(index_type),
+                    ..indexed_access.clone()
+                })
+            }
+            ast::TsType::TsMappedType(mapped) => self.eval_mapped(module, mapped, bindings, depth)
+                .unwrap_or_else(|| {
+                    // e.g. `Record<string, T>`, which has no keys to map
+                    let mut mapped = mapped.clone();
+                    mapped.type_param.constraint = mapped.type_param.constraint.map(|constraint| Box::new(eval(&constraint)));
+                    mapped.type_ann = mapped.type_ann.map(|type_ann| Box::new(eval(&type_ann)));
+                    ast::TsType::TsMappedType(mapped)
+                }),
+            ast::TsType::TsConditionalType(conditional) => self.eval_conditional(module, conditional, bindings, depth)
+                .unwrap_or_else(|| ty.clone()),
+            ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type)) => {
+                union(union_type.types.iter().map(|ty| eval(ty)).collect())
+            }
+            ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsIntersectionType(intersection)) => {
+                ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsIntersectionType(ast::TsIntersectionType {
+                    span: intersection.span,
+                    types: intersection.types.iter().map(|ty| Box::new(eval(ty))).collect()
+                }))
+            }
+            ast::TsType::TsTypeLit(type_lit) => {
+                let members = type_lit.members.iter()
+                    .map(|member| match member {
+                        ast::TsTypeElement::TsPropertySignature(property) => {
+                            let mut property = property.clone();
+                            if let Some(type_ann) = &mut property.type_ann {
+                                *type_ann.type_ann = eval(&type_ann.type_ann);
+                            }
+                            ast::TsTypeElement::TsPropertySignature(property)
+                        }
+                        _ => member.clone()
+                    })
+                    .collect();
+                ast::TsType::TsTypeLit(ast::TsTypeLit {
+                    span: type_lit.span,
+                    members
+                })
+            }
+            ast::TsType::TsArrayType(array) => ast::TsType::TsArrayType(ast::TsArrayType {
+                span: array.span,
+                elem_type: Box::new(eval(&array.elem_type))
+            }),
+            ast::TsType::TsTupleType(tuple) => ast::TsType::TsTupleType(ast::TsTupleType {
+                span: tuple.span,
+                elem_types: tuple.elem_types.iter()
+                    .map(|elem| ast::TsTupleElement {
+                        ty: Box::new(eval(&elem.ty)),
+                        ..elem.clone()
+                    })
+                    .collect()
+            }),
+            _ => ty.clone()
+        }
+    }
+
+    fn eval_type_ref(&self, module: &Rc<Module>, type_ref: &ast::TsTypeRef, bindings: &Bindings, depth: usize) -> ast::TsType {
+        if let (ast::TsEntityName::Ident(ident), None) = (&type_ref.type_name, &type_ref.type_params) {
+            if let Some(bound) = bindings.get(&ident.to_id()) {
+                return bound.clone()
+            }
+        }
+
+        let type_args: Vec<ast::TsType> = type_ref.type_params.iter()
+            .flat_map(|type_params| &type_params.params)
+            .map(|ty| self.eval(module, ty, bindings, depth))
+            .collect();
+
+        let mut evaluated = ast::TsTypeRef {
+            span: type_ref.span,
+            type_name: type_ref.type_name.clone(),
+            type_params: type_ref.type_params.as_ref().map(|type_params| Box::new(ast::TsTypeParamInstantiation {
+                span: type_params.span,
+                params: type_args.iter().cloned().map(Box::new).collect()
+            }))
+        };
+
+        let type_name = TypeName::from_entity_name(&type_ref.type_name);
+        let Some(decl) = self.lookup(module, &type_name) else {
+            return ast::TsType::TsTypeRef(evaluated)
+        };
+
+        if let Some(type_alias_decl) = decl.module.filtered_module.type_alias_decls.get(&decl.id) {
+            if let (Some(type_params), true) = (&type_alias_decl.type_params, depth < MAX_DEPTH) {
+                let mut alias_bindings = Bindings::new();
+                for (i, type_param) in type_params.params.iter().enumerate() {
+                    let type_arg = match (type_args.get(i), &type_param.default) {
+                        (Some(type_arg), _) => type_arg.clone(),
+                        (None, Some(default)) => self.eval(&decl.module, default, &alias_bindings, depth + 1),
+                        (None, None) => unknown()
+                    };
+                    alias_bindings.insert(type_param.name.to_id(), type_arg);
+                }
+                return self.eval(&decl.module, &type_alias_decl.type_ann, &alias_bindings, depth + 1)
+            }
+        }
+
+        // Keep the name as written (e.g. `Api.Request`), but make it refer to
+        // the declaration it resolved to
+        let mut name = type_name.root.0.to_string();
+        for member in &type_name.path {
+            name.push('.');
+            name.push_str(member);
+        }
+        let canonical: ast::Id = (name.into(), decl.id.1);
+        evaluated.type_name = ast::TsEntityName::Ident(ast::Ident::from(canonical.clone()));
+        self.resolved.borrow_mut().insert(canonical, decl);
+
+        ast::TsType::TsTypeRef(evaluated)
+    }
+
+    fn eval_mapped(&self, module: &Rc<Module>, mapped: &ast::TsMappedType, bindings: &Bindings, depth: usize) -> Option<ast::TsType> {
+        if mapped.name_type.is_some() {
+            return None
+        }
+
+        let constraint = mapped.type_param.constraint.as_ref()?;
+        let keys = literal_keys(&self.eval(module, constraint, bindings, depth))?;
+
+        // `[P in keyof T]` (and `[P in K]: T[P]`, as in `Pick`) keeps the
+        // modifiers of the properties of `T`
+        let source = match (&**constraint, mapped.type_ann.as_deref()) {
+            (ast::TsType::TsTypeOperator(ast::TsTypeOperator { op: ast::TsTypeOperatorOp::KeyOf, type_ann, .. }), _) => {
+                self.properties_at(&self.eval(module, type_ann, bindings, depth), depth)
+            }
+            (_, Some(ast::TsType::TsIndexedAccessType(indexed_access))) if matches!(
+                &*indexed_access.index_type,
+                ast::TsType::TsTypeRef(ast::TsTypeRef { type_name: ast::TsEntityName::Ident(ident), .. })
+                    if ident.to_id() == mapped.type_param.name.to_id()
+            ) => {
+                self.properties_at(&self.eval(module, &indexed_access.obj_type, bindings, depth), depth)
+            }
+            _ => None
+        };
+
+        let members = keys.iter()
+            .map(|key| {
+                let source = source.iter().flatten().find(|property| property_key(property).as_ref() == Some(key));
+
+                let mut bindings = bindings.clone();
+                bindings.insert(mapped.type_param.name.to_id(), string_lit(key));
+                let ty = mapped.type_ann.as_ref()
+                    .map(|type_ann| self.eval(module, type_ann, &bindings, depth))
+                    .unwrap_or_else(unknown);
+
+                let modifier = |modifier: Option<ast::TruePlusMinus>, source: bool| match modifier {
+                    Some(ast::TruePlusMinus::True) | Some(ast::TruePlusMinus::Plus) => true,
+                    Some(ast::TruePlusMinus::Minus) => false,
+                    None => source
+                };
+
+                ast::TsTypeElement::TsPropertySignature(property_signature(
+                    key,
+                    ty,
+                    modifier(mapped.optional, source.map(|property| property.optional).unwrap_or(false)),
+                    modifier(mapped.readonly, source.map(|property| property.readonly).unwrap_or(false))
+                ))
+            })
+            .collect();
+
+        Some(ast::TsType::TsTypeLit(ast::TsTypeLit {
+            span: DUMMY_SP,
+            members
+        }))
+    }
+
+    fn eval_conditional(&self, module: &Rc<Module>, conditional: &ast::TsConditionalType, bindings: &Bindings, depth: usize) -> Option<ast::TsType> {
+        let check_type = self.eval(module, &conditional.check_type, bindings, depth);
+        let extends_type = self.eval(module, &conditional.extends_type, bindings, depth);
+
+        // Conditional types distribute over unions when checking a naked type parameter
+        let type_param = match &*conditional.check_type {
+            ast::TsType::TsTypeRef(ast::TsTypeRef { type_name: ast::TsEntityName::Ident(ident), type_params: None, .. })
+                if bindings.contains_key(&ident.to_id()) => Some(ident.to_id()),
+            _ => None
+        };
+
+        let members = match (&type_param, &check_type) {
+            (Some(_), ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type))) => {
+                union_type.types.iter().map(|ty| (**ty).clone()).collect()
+            }
+            _ => vec![check_type]
+        };
+
+        let mut results = Vec::new();
+        for member in members {
+            let branch = if is_assignable(&member, &extends_type)? {
+                &conditional.true_type
+            } else {
+                &conditional.false_type
+            };
+
+            let mut bindings = bindings.clone();
+            if let Some(type_param) = &type_param {
+                bindings.insert(type_param.clone(), member);
+            }
+            results.push(self.eval(module, branch, &bindings, depth));
+        }
+
+        Some(union(results))
+    }
+}
+
+fn unknown() -> ast::TsType {
+    ast::TsType::TsKeywordType(ast::TsKeywordType {
+        span: DUMMY_SP,
+        kind: ast::TsKeywordTypeKind::TsUnknownKeyword
+    })
+}
+
+fn string_lit(value: &str) -> ast::TsType {
+    ast::TsType::TsLitType(ast::TsLitType {
+        span: DUMMY_SP,
+        lit: ast::TsLit::Str(ast::Str {
+            span: DUMMY_SP,
+            value: value.into(),
+            raw: None
+        })
+    })
+}
+
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().map(|first| first.is_alphabetic() || first == '_' || first == '$').unwrap_or(false)
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+fn property_key(property: &ast::TsPropertySignature) -> Option<String> {
+    if property.computed {
+        return None
+    }
+    match &*property.key {
+        ast::Expr::Ident(ident) => Some(ident.sym.to_string()),
+        ast::Expr::Lit(ast::Lit::Str(str)) => Some(str.value.to_string()),
+        ast::Expr::Lit(ast::Lit::Num(num)) => Some(num.value.to_string()),
+        _ => None
+    }
+}
+
+fn property_signature(key: &str, ty: ast::TsType, optional: bool, readonly: bool) -> ast::TsPropertySignature {
+    let key = if is_identifier(key) {
+        ast::Expr::Ident(ast::Ident::new(key.into(), DUMMY_SP))
+    } else {
+        ast::Expr::Lit(ast::Lit::Str(ast::Str {
+            span: DUMMY_SP,
+            value: key.into(),
+            raw: None
+        }))
+    };
+
+    ast::TsPropertySignature {
+        span: DUMMY_SP,
+        readonly,
+        key: Box::new(key),
+        computed: false,
+        optional,
+        init: None,
+        params: Vec::new(),
+        type_ann: Some(Box::new(ast::TsTypeAnn {
+            span: DUMMY_SP,
+            type_ann: Box::new(ty)
+        })),
+        type_params: None
+    }
+}
+
+/// The (property) keys that a union of literal types stands for.
+fn literal_keys(ty: &ast::TsType) -> Option<Vec<String>> {
+    match ty {
+        ast::TsType::TsLitType(ast::TsLitType { lit: ast::TsLit::Str(str), .. }) => Some(vec![str.value.to_string()]),
+        ast::TsType::TsLitType(ast::TsLitType { lit: ast::TsLit::Number(num), .. }) => Some(vec![num.value.to_string()]),
+        ast::TsType::TsKeywordType(ast::TsKeywordType { kind: ast::TsKeywordTypeKind::TsNeverKeyword, .. }) => Some(Vec::new()),
+        ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type)) => {
+            let mut keys = Vec::new();
+            for ty in &union_type.types {
+                keys.extend(literal_keys(ty)?);
+            }
+            Some(keys)
+        }
+        _ => None
+    }
+}
+
+/// Builds the union of `types`, flattening nested unions and dropping `never`
+/// and duplicates.
+fn union(types: Vec<ast::TsType>) -> ast::TsType {
+    let mut members: Vec<ast::TsType> = Vec::new();
+    for ty in types {
+        let flattened = match ty {
+            ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type)) => {
+                union_type.types.into_iter().map(|ty| *ty).collect()
+            }
+            ast::TsType::TsKeywordType(ast::TsKeywordType { kind: ast::TsKeywordTypeKind::TsNeverKeyword, .. }) => Vec::new(),
+            ty => vec![ty]
+        };
+        for ty in flattened {
+            if !members.iter().any(|member| member.eq_ignore_span(&ty)) {
+                members.push(ty);
+            }
+        }
+    }
+
+    match members.len() {
+        0 => ast::TsType::TsKeywordType(ast::TsKeywordType {
+            span: DUMMY_SP,
+            kind: ast::TsKeywordTypeKind::TsNeverKeyword
+        }),
+        1 => members.pop().unwrap(),
+        _ => ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(ast::TsUnionType {
+            span: DUMMY_SP,
+            types: members.into_iter().map(Box::new).collect()
+        }))
+    }
+}
+
+/// Whether `ty` is assignable to `to`, when that can be decided without a
+/// type checker.
+fn is_assignable(ty: &ast::TsType, to: &ast::TsType) -> Option<bool> {
+    use ast::TsKeywordTypeKind::*;
+
+    if ty.eq_ignore_span(to) {
+        return Some(true)
+    }
+
+    match (ty, to) {
+        (_, ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type))) => {
+            let mut assignable = Some(false);
+            for member in &union_type.types {
+                match is_assignable(ty, member) {
+                    Some(true) => return Some(true),
+                    Some(false) => {}
+                    None => assignable = None
+                }
+            }
+            assignable
+        }
+        (_, ast::TsType::TsKeywordType(ast::TsKeywordType { kind: TsAnyKeyword | TsUnknownKeyword, .. })) => Some(true),
+        (ast::TsType::TsLitType(_), ast::TsType::TsLitType(_)) => Some(false),
+        (ast::TsType::TsLitType(lit), ast::TsType::TsKeywordType(keyword)) => Some(matches!(
+            (&lit.lit, keyword.kind),
+            (ast::TsLit::Str(_) | ast::TsLit::Tpl(_), TsStringKeyword)
+                | (ast::TsLit::Number(_), TsNumberKeyword)
+                | (ast::TsLit::Bool(_), TsBooleanKeyword)
+                | (ast::TsLit::BigInt(_), TsBigIntKeyword)
+        )),
+        (ast::TsType::TsKeywordType(_), ast::TsType::TsKeywordType(_)) => Some(false),
+        (ast::TsType::TsKeywordType(_), ast::TsType::TsLitType(_)) => Some(false),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use deno_ast::ModuleSpecifier;
+
+    use super::*;
+    use crate::codegen::Node;
+    use crate::emit::Emitter;
+
+    const ORDER: &str = "interface Order { id: string; total?: number; paid: boolean }\n";
+
+    /// Evaluates the alias `T` declared (after `Order`) in `source`.
+    async fn evaluate(source: &str) -> String {
+        let specifier = ModuleSpecifier::parse("file:///test.ts").unwrap();
+        let mut module_graph = ModuleGraph::default();
+        let module = module_graph.load_source(&specifier, format!("{}{}", ORDER, source)).await.unwrap();
+        module_graph.builtins().await.unwrap();
+
+        let type_alias_decl = module.filtered_module.type_alias_decls.values()
+            .find(|type_alias_decl| &*type_alias_decl.id.sym == "T")
+            .unwrap();
+        let ty = TypeEvaluator::new(&module_graph).evaluate(&module, &type_alias_decl.type_ann);
+
+        let mut buf = Vec::new();
+        ty.emit_with(&mut Emitter::new(&mut buf)).unwrap();
+        String::from_utf8(buf).unwrap().split_whitespace().collect::<Vec<_>>().join(" ")
+    }
+
+    #[tokio::test]
+    async fn pick() {
+        assert_eq!(evaluate(r#"type T = Pick<Order, "id">;"#).await, "{ id: string; }");
+        assert_eq!(evaluate(r#"type T = Pick<Order, "id" | "total">;"#).await, "{ id: string; total?: number; }");
+    }
+
+    #[tokio::test]
+    async fn omit() {
+        assert_eq!(evaluate(r#"type T = Omit<Order, "total" | "paid">;"#).await, "{ id: string; }");
+    }
+
+    #[tokio::test]
+    async fn partial() {
+        assert_eq!(evaluate("type T = Partial<Order>;").await, "{ id?: string; total?: number; paid?: boolean; }");
+    }
+
+    #[tokio::test]
+    async fn record() {
+        assert_eq!(evaluate(r#"type T = Record<"a" | "b", number>;"#).await, "{ a: number; b: number; }");
+    }
+
+    #[tokio::test]
+    async fn keyof() {
+        assert_eq!(evaluate("type T = keyof Order;").await, r#""id" | "total" | "paid""#);
+    }
+}
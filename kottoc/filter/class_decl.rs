@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Deref;
 
 use crate::CanPush;
 use crate::{ast, visit};
+use crate::common::Span;
 
-use crate::filter::{TypeRef, TypeRefVisitor};
+use crate::filter::{TypeParamVisitor, TypeRef, TypeRefVisitor};
 
 #[derive(Debug)]
 pub struct ClassDecl {
@@ -12,16 +13,52 @@ pub struct ClassDecl {
     pub class_members: HashMap<ast::PropName, ClassMember>,
 }
 
+impl ClassDecl {
+    /// The members of the class, in the order they are declared in.
+    pub fn members_in_order(&self) -> Vec<(&ast::PropName, &ClassMember)> {
+        let mut members: Vec<(&ast::PropName, &ClassMember)> = self.class_members.iter().collect();
+        members.sort_by_key(|(_, class_member)| class_member.span().lo);
+        members
+    }
+}
+
 #[derive(Debug)]
 pub enum ClassMember {
     Method(ClassMethod),
     Prop(ClassProp),
 }
 
+/// How the name of a member reads in messages, e.g. `"search-all"` or
+/// `[key]`. Only members named by identifiers are supported.
+pub fn member_name_text(prop_name: &ast::PropName) -> String {
+    match prop_name {
+        ast::PropName::Ident(ident) => ident.sym.to_string(),
+        ast::PropName::Str(str) => format!("{:?}", &*str.value),
+        ast::PropName::Num(num) => num.value.to_string(),
+        ast::PropName::BigInt(big_int) => big_int.value.to_string(),
+        ast::PropName::Computed(_) => "[computed]".to_string()
+    }
+}
+
+impl ClassMember {
+    pub fn span(&self) -> Span {
+        match self {
+            Self::Method(class_method) => class_method.span,
+            Self::Prop(class_prop) => class_prop.span
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ClassMethod {
     pub class_method: ast::ClassMethod,
-    pub type_refs: Vec<TypeRef>
+    /// The parameters as declared, before [`normalize_params`](crate::filter::normalize_params)
+    pub params: Vec<ast::Param>,
+    /// The literal defaults of the parameters, see [`normalize_params`](crate::filter::normalize_params)
+    pub defaults: Vec<Option<String>>,
+    pub type_refs: Vec<TypeRef>,
+    /// Whether the method is decorated with `@use`, i.e. exposed to the model
+    pub used: bool,
 }
 
 impl Deref for ClassMethod {
@@ -32,6 +69,23 @@ impl Deref for ClassMethod {
     }
 }
 
+/// Whether `decorator` is kotto's `use`, imported as is (`@use`) or through a
+/// namespace (e.g. `@kotto.use`), and applied as is or called (`@use()`).
+fn is_use_decorator(decorator: &ast::Decorator) -> bool {
+    match &*decorator.expr {
+        ast::Expr::Call(ast::CallExpr { callee: ast::Callee::Expr(callee), .. }) => is_use(callee),
+        expr => is_use(expr)
+    }
+}
+
+fn is_use(expr: &ast::Expr) -> bool {
+    match expr {
+        ast::Expr::Ident(ident) => &*ident.sym == "use",
+        ast::Expr::Member(member) => matches!(&member.prop, ast::MemberProp::Ident(ident) if &*ident.sym == "use"),
+        _ => false
+    }
+}
+
 #[derive(Debug)]
 pub struct ClassProp(pub ast::ClassProp);
 
@@ -53,6 +107,11 @@ impl<'m, C> visit::Visit for ClassDeclVisitor<'m, C>
     fn visit_class_decl(&mut self, n: &ast::ClassDecl) {
         let mut class_members = HashMap::new();
 
+        let mut class_type_params = HashSet::new();
+        if let Some(type_params) = &n.class.type_params {
+            TypeParamVisitor(&mut class_type_params).visit_ts_type_param_decl(type_params);
+        }
+
         for class_member in &n.class.body {
             match class_member {
                 ast::ClassMember::Method(class_method) => {
@@ -61,6 +120,12 @@ impl<'m, C> visit::Visit for ClassDeclVisitor<'m, C>
                     let mut type_refs = Vec::new();
                     TypeRefVisitor(&mut type_refs).visit_class_method(&class_method);
 
+                    let mut type_params = class_type_params.clone();
+                    TypeParamVisitor(&mut type_params).visit_class_method(&class_method);
+                    type_refs.retain(|type_ref| !type_ref.is_bound_by(&type_params));
+
+                    let used = class_method.function.decorators.iter().any(is_use_decorator);
+
                     // Trim the unnecessary stuff
                     class_method.function.body = None;
                     class_method.function.decorators.clear();
@@ -68,8 +133,11 @@ impl<'m, C> visit::Visit for ClassDeclVisitor<'m, C>
                     class_members.insert(
                         class_method.key.clone(),
                         ClassMember::Method(ClassMethod {
+                            params: class_method.function.params.clone(),
+                            defaults: Vec::new(),
                             class_method,
-                            type_refs
+                            type_refs,
+                            used
                         })
                     );
                 }
@@ -1,14 +1,22 @@
+use std::collections::HashSet;
 use std::ops::Deref;
 
 use crate::CanPush;
 use crate::{ast, visit};
 
-use crate::filter::{TypeRef, TypeRefVisitor};
+use crate::filter::{TypeParamVisitor, TypeRef, TypeRefVisitor};
 
 #[derive(Debug)]
 pub struct FnDecl {
     pub fn_decl: ast::FnDecl,
-    pub type_refs: Vec<TypeRef>
+    /// The parameters as declared, before [`normalize_params`](crate::filter::normalize_params)
+    pub params: Vec<ast::Param>,
+    /// The literal defaults of the parameters, see [`normalize_params`](crate::filter::normalize_params)
+    pub defaults: Vec<Option<String>>,
+    pub type_refs: Vec<TypeRef>,
+    /// Whether the module exports the function, which makes it a root when
+    /// tree shaking
+    pub exported: bool
 }
 
 impl Deref for FnDecl {
@@ -28,14 +36,21 @@ impl<'m, C> visit::Visit for FnDeclVisitor<'m, C>
 {
     fn visit_fn_decl(&mut self, n: &ast::FnDecl) {
         let mut type_refs = Vec::new();
-        TypeRefVisitor(&mut type_refs).visit_fn_decl(&n);
+        TypeRefVisitor(&mut type_refs).visit_fn_decl(n);
+
+        let mut type_params = HashSet::new();
+        TypeParamVisitor(&mut type_params).visit_fn_decl(n);
+        type_refs.retain(|type_ref| !type_ref.is_bound_by(&type_params));
 
         let mut fn_decl = n.clone();
         fn_decl.function.body = None;
         fn_decl.function.decorators.clear();
         self.0.push(FnDecl {
+            params: fn_decl.function.params.clone(),
+            defaults: Vec::new(),
             fn_decl,
-            type_refs
+            type_refs,
+            exported: false
         });
     }
 }
@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+
+use crate::CanPush;
+use crate::{ast, visit};
+
+/// The name under which a binding is exported by the module it is imported from.
+#[derive(Debug, Clone)]
+pub enum ImportName {
+    Named(String),
+    Default,
+    Namespace,
+}
+
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub local: ast::Ident,
+    pub src: String,
+    pub name: ImportName,
+}
+
+impl CanPush<Import> for HashMap<ast::Id, Import> {
+    fn push(&mut self, item: Import) {
+        HashMap::insert(self, item.local.to_id(), item);
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportVisitor<'m, C>(pub &'m mut C);
+
+impl<'m, C> visit::Visit for ImportVisitor<'m, C>
+where
+    C: CanPush<Import>
+{
+    fn visit_import_decl(&mut self, n: &ast::ImportDecl) {
+        let src = n.src.value.to_string();
+
+        for specifier in &n.specifiers {
+            let (local, name) = match specifier {
+                ast::ImportSpecifier::Named(named) => {
+                    let name = match &named.imported {
+                        Some(ast::ModuleExportName::Ident(ident)) => ident.sym.to_string(),
+                        Some(ast::ModuleExportName::Str(str)) => str.value.to_string(),
+                        None => named.local.sym.to_string()
+                    };
+                    (&named.local, ImportName::Named(name))
+                }
+                ast::ImportSpecifier::Default(default) => (&default.local, ImportName::Default),
+                ast::ImportSpecifier::Namespace(namespace) => (&namespace.local, ImportName::Namespace)
+            };
+
+            self.0.push(Import {
+                local: local.clone(),
+                src: src.clone(),
+                name
+            });
+        }
+    }
+}
+
+/// Finds the declaration that a module exports as its default export (e.g.
+/// `export default interface Config {}` or `export { Config as default }`).
+#[derive(Debug)]
+pub struct DefaultExportVisitor<'m>(pub &'m mut Option<ast::Id>);
+
+impl<'m> visit::Visit for DefaultExportVisitor<'m> {
+    fn visit_export_default_decl(&mut self, n: &ast::ExportDefaultDecl) {
+        let ident = match &n.decl {
+            ast::DefaultDecl::TsInterfaceDecl(interface_decl) => Some(&interface_decl.id),
+            ast::DefaultDecl::Class(class_expr) => class_expr.ident.as_ref(),
+            ast::DefaultDecl::Fn(fn_expr) => fn_expr.ident.as_ref()
+        };
+        if let Some(ident) = ident {
+            *self.0 = Some(ident.to_id());
+        }
+    }
+
+    fn visit_export_default_expr(&mut self, n: &ast::ExportDefaultExpr) {
+        if let ast::Expr::Ident(ident) = &*n.expr {
+            *self.0 = Some(ident.to_id());
+        }
+    }
+
+    fn visit_named_export(&mut self, n: &ast::NamedExport) {
+        // Re-exports are declared by other modules
+        if n.src.is_some() {
+            return
+        }
+        for specifier in &n.specifiers {
+            if let ast::ExportSpecifier::Named(ast::ExportNamedSpecifier {
+                orig: ast::ModuleExportName::Ident(orig),
+                exported: Some(ast::ModuleExportName::Ident(exported)),
+                ..
+            }) = specifier {
+                if &*exported.sym == "default" {
+                    *self.0 = Some(orig.to_id());
+                }
+            }
+        }
+    }
+}
+
+/// Collects the ids of the declarations that a module exports, under their
+/// own name (`export function f`) or another (`export { f as g }`).
+#[derive(Debug)]
+pub struct ExportVisitor<'m, C>(pub &'m mut C);
+
+impl<'m, C> visit::Visit for ExportVisitor<'m, C>
+    where
+        C: CanPush<ast::Id>
+{
+    fn visit_export_decl(&mut self, n: &ast::ExportDecl) {
+        match &n.decl {
+            ast::Decl::Fn(fn_decl) => self.0.push(fn_decl.ident.to_id()),
+            ast::Decl::Class(class_decl) => self.0.push(class_decl.ident.to_id()),
+            _ => {}
+        }
+    }
+
+    fn visit_export_default_decl(&mut self, n: &ast::ExportDefaultDecl) {
+        let mut default_export = None;
+        DefaultExportVisitor(&mut default_export).visit_export_default_decl(n);
+        if let Some(id) = default_export {
+            self.0.push(id);
+        }
+    }
+
+    fn visit_named_export(&mut self, n: &ast::NamedExport) {
+        // Re-exports are declared by other modules
+        if n.src.is_some() {
+            return
+        }
+        for specifier in &n.specifiers {
+            if let ast::ExportSpecifier::Named(ast::ExportNamedSpecifier { orig: ast::ModuleExportName::Ident(orig), .. }) = specifier {
+                self.0.push(orig.to_id());
+            }
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use crate::CanPush;
+use crate::{ast, visit};
+
+impl CanPush<InterfaceDecl> for HashMap<ast::Id, InterfaceDecl> {
+    fn push(&mut self, item: InterfaceDecl) {
+        HashMap::insert(self, item.id.to_id(), item);
+    }
+}
+
+#[derive(Debug)]
+pub struct InterfaceDecl(pub ast::TsInterfaceDecl);
+
+impl Deref for InterfaceDecl {
+    type Target = ast::TsInterfaceDecl;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Debug)]
+pub struct InterfaceVisitor<'m, C>(pub &'m mut C);
+
+impl<'m, C> visit::Visit for InterfaceVisitor<'m, C>
+where
+    C: CanPush<InterfaceDecl>
+{
+    fn visit_ts_interface_decl(&mut self, n: &ast::TsInterfaceDecl) {
+        self.0.push(InterfaceDecl(n.clone()));
+    }
+}
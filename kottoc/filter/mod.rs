@@ -9,41 +9,124 @@ use crate::CanPush;
 use visit::Visit;
 
 mod type_alias_decl;
+mod interface_decl;
 mod class_decl;
 mod type_ref;
 mod fn_decl;
+mod import_decl;
+mod namespace_decl;
+mod var_decl;
+mod params;
 
 pub use type_alias_decl::{TypeAliasDecl, TypeAliasVisitor};
-pub use class_decl::{ClassDecl, ClassDeclVisitor, ClassMember};
-pub use type_ref::{TypeRef, TypeRefVisitor};
+pub use interface_decl::{InterfaceDecl, InterfaceVisitor};
+pub use import_decl::{DefaultExportVisitor, ExportVisitor, Import, ImportName, ImportVisitor};
+pub use class_decl::{member_name_text, ClassDecl, ClassDeclVisitor, ClassMember};
+pub use type_ref::{entity_name_of, TypeName, TypeParamVisitor, TypeRef, TypeRefVisitor};
+pub use namespace_decl::{Namespaces, NamespaceVisitor};
+pub use var_decl::{VarDecl, VarDeclVisitor};
 pub use fn_decl::FnDecl;
+pub use params::{default_type, normalize_params, note_defaults};
 use crate::filter::fn_decl::FnDeclVisitor;
 
 #[derive(Debug, Default)]
 pub struct FilteredModule {
     pub type_alias_decls: HashMap<ast::Id, TypeAliasDecl>,
+    pub interface_decls: HashMap<ast::Id, InterfaceDecl>,
     pub class_decls: Vec<ClassDecl>,
-    pub fn_decls: Vec<FnDecl>
+    pub fn_decls: Vec<FnDecl>,
+    pub var_decls: HashMap<ast::Id, VarDecl>,
+    pub imports: HashMap<ast::Id, Import>,
+    /// The declaration that the module exports as its default export
+    pub default_export: Option<ast::Id>,
+    pub namespaces: Namespaces
 }
 
 impl FilteredModule {
-    pub fn find_closure_of_type_refs<'i, I: 'i>(&self, refs: I) -> HashSet<ast::Id>
+    /// Finds the declarations of this module that `refs` transitively refer to.
+    ///
+    /// Names that cannot be resolved within the module (e.g. imports) are part
+    /// of the closure as they are, for the caller to resolve.
+    pub fn find_closure_of_type_refs<'i, I>(&self, refs: I) -> HashSet<TypeName>
     where
-        I: IntoIterator<Item = &'i TypeRef>
+        I: 'i + IntoIterator<Item = &'i TypeRef>
     {
         let mut closure = HashSet::new();
+        let mut type_params = HashSet::new();
         let mut to_visit: Vec<TypeRef> = refs.into_iter().cloned().collect();
         while let Some(next) = to_visit.pop() {
-            if let Some(next_id) = next.type_name.clone().ident().as_ref().map(ast::Ident::to_id) {
-                if closure.insert(next_id.clone()) {
-                    if let Some(next_decl) = self.type_alias_decls.get(&next_id) {
-                        TypeRefVisitor(&mut to_visit).visit_ts_type_alias_decl(next_decl);
+            if next.is_bound_by(&type_params) {
+                continue
+            }
+            let next_name = next.name();
+            let Some(next_id) = self.namespaces.lookup(&next_name.root, &next_name.path) else {
+                closure.insert(next_name);
+                continue
+            };
+            if closure.insert(TypeName { value: next_name.value, ..next_id.clone().into() }) {
+                if next_name.value {
+                    if let Some(next_decl) = self.var_decls.get(&next_id) {
+                        TypeRefVisitor(&mut to_visit).visit_var_decl(next_decl);
                     }
+                } else if let Some(next_decl) = self.type_alias_decls.get(&next_id) {
+                    TypeParamVisitor(&mut type_params).visit_ts_type_alias_decl(next_decl);
+                    TypeRefVisitor(&mut to_visit).visit_ts_type_alias_decl(next_decl);
+                } else if let Some(next_decl) = self.interface_decls.get(&next_id) {
+                    TypeParamVisitor(&mut type_params).visit_ts_interface_decl(next_decl);
+                    TypeRefVisitor(&mut to_visit).visit_ts_interface_decl(next_decl);
                 }
             }
         }
         closure
     }
+
+    pub fn find_class_decl(&self, id: &ast::Id) -> Option<&ClassDecl> {
+        self.class_decls.iter().find(|class_decl| class_decl.class_decl.ident.to_id() == *id)
+    }
+
+    /// Finds the top-level declaration that a module exports under `name`.
+    pub fn find_exported(&self, name: &str) -> Option<ast::Id> {
+        self.type_alias_decls.keys()
+            .chain(self.interface_decls.keys())
+            .chain(self.namespaces.decls.keys())
+            .chain(self.var_decls.keys())
+            .cloned()
+            .chain(self.class_decls.iter().map(|class_decl| class_decl.class_decl.ident.to_id()))
+            .find(|id| &*id.0 == name && !self.namespaces.paths.contains_key(id))
+    }
+
+    /// The name of `ident` (a declaration of this module, or an instance of
+    /// one), qualified by the namespaces enclosing the declaration `id`.
+    pub fn scoped_name(&self, id: &ast::Id, ident: &ast::Ident) -> String {
+        let mut scoped_name = String::new();
+        for namespace in self.namespaces.paths.get(id).into_iter().flatten() {
+            scoped_name.push_str(&format!("{}.", namespace));
+        }
+        scoped_name.push_str(&format!("{}", ident));
+        scoped_name
+    }
+
+    /// The prompt id (prefixed with its `ast_ty`) under which a declaration
+    /// of this module is emitted, if there is one. `value` is whether `id`
+    /// names a value (classes and constants) rather than a type (classes,
+    /// interfaces and type aliases).
+    pub fn context_id_of(&self, id: &ast::Id, value: bool) -> Option<String> {
+        let ast_ty = if self.find_class_decl(id).is_some() {
+            "class_decl"
+        } else if value {
+            if !self.var_decls.contains_key(id) {
+                return None
+            }
+            "var_decl"
+        } else if self.type_alias_decls.contains_key(id) {
+            "type_alias_decl"
+        } else if self.interface_decls.contains_key(id) {
+            "interface_decl"
+        } else {
+            return None
+        };
+        Some(format!("{}.{}", ast_ty, self.scoped_name(id, &ast::Ident::from(id.clone()))))
+    }
 }
 
 impl<T> CanPush<T> for Vec<T> {
@@ -61,16 +144,24 @@ impl<T: Hash + Eq> CanPush<T> for HashSet<T> {
 #[derive(Debug)]
 pub struct FilterParams {
     enable_type_alias_decls: bool,
+    enable_interface_decls: bool,
     enable_class_decls: bool,
-    enable_fn_decls: bool
+    enable_fn_decls: bool,
+    enable_var_decls: bool,
+    enable_imports: bool,
+    enable_namespaces: bool
 }
 
 impl Default for FilterParams {
     fn default() -> Self {
         Self {
             enable_type_alias_decls: true,
+            enable_interface_decls: true,
             enable_class_decls: true,
-            enable_fn_decls: true
+            enable_fn_decls: true,
+            enable_var_decls: true,
+            enable_imports: true,
+            enable_namespaces: true
         }
     }
 }
@@ -82,12 +173,46 @@ pub async fn run_filters(params: FilterParams, module: &ast::Module) -> Result<F
         TypeAliasVisitor(&mut result.type_alias_decls).visit_module(module);
     }
 
+    if params.enable_interface_decls {
+        InterfaceVisitor(&mut result.interface_decls).visit_module(module);
+    }
+
     if params.enable_class_decls {
         ClassDeclVisitor(&mut result.class_decls).visit_module(module);
     }
 
     if params.enable_fn_decls {
         FnDeclVisitor(&mut result.fn_decls).visit_module(module);
+
+        let mut exported = HashSet::new();
+        ExportVisitor(&mut exported).visit_module(module);
+        for fn_decl in &mut result.fn_decls {
+            fn_decl.exported = exported.contains(&fn_decl.ident.to_id());
+        }
+    }
+
+    for fn_decl in &mut result.fn_decls {
+        fn_decl.defaults = normalize_params(&mut fn_decl.fn_decl.function);
+    }
+    for class_decl in &mut result.class_decls {
+        for class_member in class_decl.class_members.values_mut() {
+            if let ClassMember::Method(class_method) = class_member {
+                class_method.defaults = normalize_params(&mut class_method.class_method.function);
+            }
+        }
+    }
+
+    if params.enable_var_decls {
+        VarDeclVisitor(&mut result.var_decls).visit_module(module);
+    }
+
+    if params.enable_imports {
+        ImportVisitor(&mut result.imports).visit_module(module);
+        DefaultExportVisitor(&mut result.default_export).visit_module(module);
+    }
+
+    if params.enable_namespaces {
+        NamespaceVisitor(&mut result.namespaces, Vec::new()).visit_module(module);
     }
 
     Ok(result)
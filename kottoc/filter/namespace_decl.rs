@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::{ast, visit};
+
+#[derive(Debug)]
+pub struct Namespace {
+    /// The exported members of the namespace, by name
+    pub members: HashMap<String, ast::Id>,
+}
+
+#[derive(Debug, Default)]
+pub struct Namespaces {
+    pub decls: HashMap<ast::Id, Namespace>,
+    /// The namespaces enclosing each declaration that is nested in one,
+    /// outermost first
+    pub paths: HashMap<ast::Id, Vec<ast::Ident>>,
+}
+
+impl Namespaces {
+    /// Looks up `path` (e.g. `["Request"]` for `Api.Request`) from the namespace `root`.
+    pub fn lookup<'a, I>(&self, root: &ast::Id, path: I) -> Option<ast::Id>
+    where
+        I: IntoIterator<Item = &'a String>
+    {
+        let mut current = root.clone();
+        for name in path {
+            current = self.decls.get(&current)?.members.get(name)?.clone();
+        }
+        Some(current)
+    }
+}
+
+#[derive(Debug)]
+pub struct NamespaceVisitor<'m>(pub &'m mut Namespaces, pub Vec<ast::Ident>);
+
+impl<'m> NamespaceVisitor<'m> {
+    fn visit_namespace(&mut self, ident: &ast::Ident, body: Option<&ast::TsNamespaceBody>) {
+        if !self.1.is_empty() {
+            self.0.paths.insert(ident.to_id(), self.1.clone());
+        }
+
+        // Declarations of the same namespace are merged
+        self.0.decls.entry(ident.to_id()).or_insert_with(|| Namespace {
+            members: HashMap::new()
+        });
+
+        self.1.push(ident.clone());
+
+        match body {
+            Some(ast::TsNamespaceBody::TsModuleBlock(block)) => {
+                for item in &block.body {
+                    let (decl, exported) = match item {
+                        ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportDecl(export_decl)) => (&export_decl.decl, true),
+                        ast::ModuleItem::Stmt(ast::Stmt::Decl(decl)) => (decl, false),
+                        _ => continue
+                    };
+
+                    let member = match decl {
+                        ast::Decl::Class(class_decl) => &class_decl.ident,
+                        ast::Decl::Fn(fn_decl) => &fn_decl.ident,
+                        ast::Decl::TsInterface(interface_decl) => &interface_decl.id,
+                        ast::Decl::TsTypeAlias(type_alias_decl) => &type_alias_decl.id,
+                        ast::Decl::TsEnum(enum_decl) => &enum_decl.id,
+                        ast::Decl::TsModule(module_decl) => match &module_decl.id {
+                            ast::TsModuleName::Ident(ident) => {
+                                self.visit_namespace(ident, module_decl.body.as_ref());
+                                ident
+                            }
+                            ast::TsModuleName::Str(_) => continue
+                        },
+                        ast::Decl::Var(var_decl) => {
+                            for declarator in &var_decl.decls {
+                                if let ast::Pat::Ident(binding) = &declarator.name {
+                                    self.0.paths.insert(binding.id.to_id(), self.1.clone());
+                                    if exported {
+                                        self.add_member(ident, &binding.id);
+                                    }
+                                }
+                            }
+                            continue
+                        }
+                        ast::Decl::Using(_) => continue
+                    };
+
+                    self.0.paths.insert(member.to_id(), self.1.clone());
+                    if exported {
+                        self.add_member(ident, member);
+                    }
+                }
+            }
+            Some(ast::TsNamespaceBody::TsNamespaceDecl(nested)) => {
+                // `namespace A.B {}` implicitly exports `B` from `A`
+                self.visit_namespace(&nested.id, Some(&nested.body));
+                self.add_member(ident, &nested.id);
+            }
+            None => {}
+        }
+
+        self.1.pop();
+    }
+
+    fn add_member(&mut self, namespace: &ast::Ident, member: &ast::Ident) {
+        if let Some(namespace) = self.0.decls.get_mut(&namespace.to_id()) {
+            namespace.members.insert(member.sym.to_string(), member.to_id());
+        }
+    }
+}
+
+impl<'m> visit::Visit for NamespaceVisitor<'m> {
+    fn visit_ts_module_decl(&mut self, n: &ast::TsModuleDecl) {
+        // `declare module "foo" {}` declares an (ambient) module, not a namespace
+        if let ast::TsModuleName::Ident(ident) = &n.id {
+            self.visit_namespace(ident, n.body.as_ref());
+        }
+    }
+}
@@ -0,0 +1,91 @@
+use crate::{ast, emit};
+use crate::codegen::Node;
+use crate::common::DUMMY_SP;
+use crate::filter::var_decl::infer_type;
+
+/// The source of `expr`, if it is a literal (e.g. `10`, `-1` or `"asc"`).
+fn literal_text(expr: &ast::Expr) -> Option<String> {
+    let is_literal = match expr {
+        ast::Expr::Lit(ast::Lit::Str(_) | ast::Lit::Num(_) | ast::Lit::Bool(_) | ast::Lit::Null(_) | ast::Lit::BigInt(_)) => true,
+        ast::Expr::Unary(ast::UnaryExpr { op: ast::UnaryOp::Minus, arg, .. }) => matches!(&**arg, ast::Expr::Lit(ast::Lit::Num(_))),
+        ast::Expr::Tpl(tpl) => tpl.exprs.is_empty(),
+        _ => false
+    };
+    if !is_literal {
+        return None
+    }
+
+    let mut buf = Vec::new();
+    expr.emit_with(&mut emit::Emitter::new(&mut buf)).ok()?;
+    String::from_utf8(buf).ok()
+}
+
+/// The type of a parameter without one, as inferred from its default value
+/// `expr` (e.g. `number` for `10`).
+pub fn default_type(expr: &ast::Expr) -> ast::TsType {
+    match infer_type(expr, false) {
+        // Not what the model knows about
+        ast::TsType::TsTypeQuery(_) => ast::TsType::TsKeywordType(ast::TsKeywordType {
+            span: DUMMY_SP,
+            kind: ast::TsKeywordTypeKind::TsUnknownKeyword
+        }),
+        ty => ty
+    }
+}
+
+/// Rewrites the parameters of `function` that have a default value into
+/// optional ones: their default is an arbitrary expression (which may refer to
+/// things the model does not know about), so only literal defaults are kept.
+/// Parameters without a type get the type of their default.
+///
+/// Returns the literal defaults by parameter, which [`note_defaults`] shows
+/// as comments once the signature is emitted.
+pub fn normalize_params(function: &mut ast::Function) -> Vec<Option<String>> {
+    let mut defaults = Vec::new();
+    for param in &mut function.params {
+        let ast::Pat::Assign(assign) = &param.pat else {
+            defaults.push(None);
+            continue
+        };
+        defaults.push(literal_text(&assign.right));
+
+        let mut pat = (*assign.left).clone();
+        let (optional, type_ann) = match &mut pat {
+            ast::Pat::Ident(binding) => (&mut binding.id.optional, &mut binding.type_ann),
+            ast::Pat::Object(object) => (&mut object.optional, &mut object.type_ann),
+            ast::Pat::Array(array) => (&mut array.optional, &mut array.type_ann),
+            _ => continue
+        };
+
+        *optional = true;
+        if type_ann.is_none() {
+            *type_ann = Some(Box::new(ast::TsTypeAnn {
+                span: DUMMY_SP,
+                type_ann: Box::new(default_type(&assign.right))
+            }));
+        }
+
+        param.pat = pat;
+    }
+    defaults
+}
+
+/// Writes the `defaults` found by [`normalize_params`] after the types of the
+/// parameters of `function` (e.g. `limit?: number /* default: 10 */`), which
+/// is about to be emitted.
+pub fn note_defaults(function: &mut ast::Function, defaults: &[Option<String>]) {
+    for (param, default) in function.params.iter_mut().zip(defaults) {
+        let Some(default) = default else {
+            continue
+        };
+        let type_ann = match &mut param.pat {
+            ast::Pat::Ident(binding) => &mut binding.type_ann,
+            ast::Pat::Object(object) => &mut object.type_ann,
+            ast::Pat::Array(array) => &mut array.type_ann,
+            _ => continue
+        };
+        if let Some(type_ann) = type_ann {
+            *type_ann.type_ann = emit::noted_type(&type_ann.type_ann, &format!("default: {}", default));
+        }
+    }
+}
@@ -4,6 +4,8 @@ use std::ops::Deref;
 use crate::CanPush;
 use crate::{ast, visit};
 
+use visit::VisitMut;
+
 impl CanPush<TypeAliasDecl> for HashMap<ast::Id, TypeAliasDecl> {
     fn push(&mut self, item: TypeAliasDecl) {
         HashMap::insert(self, item.id.to_id(), item);
@@ -21,6 +23,88 @@ impl Deref for TypeAliasDecl {
     }
 }
 
+impl TypeAliasDecl {
+    /// Substitutes `type_args` for the type parameters of this (generic) alias.
+    ///
+    /// The instantiated alias is named after the instantiation (e.g. `Page<Item>`)
+    /// and has no type parameters left. Only type arguments that are keywords, plain
+    /// references or (unions of) literals are supported, as the name has to be
+    /// usable as a prompt id.
+    pub fn instantiate(&self, type_args: &ast::TsTypeParamInstantiation) -> Option<ast::TsTypeAliasDecl> {
+        let type_params = self.type_params.as_ref()?;
+
+        let arg_names = type_args.params.iter()
+            .map(|type_arg| type_arg_name(type_arg))
+            .collect::<Option<Vec<String>>>()?;
+
+        let mut substitutions = HashMap::new();
+        for (i, type_param) in type_params.params.iter().enumerate() {
+            let type_arg = type_args.params.get(i).or(type_param.default.as_ref())?;
+            substitutions.insert(type_param.name.to_id(), (**type_arg).clone());
+        }
+
+        let mut instance = self.0.clone();
+        instance.type_params = None;
+        instance.id.sym = format!("{}<{}>", self.id.sym, arg_names.join(", ")).into();
+        TypeParamSubstitution(&substitutions).visit_mut_ts_type(&mut instance.type_ann);
+
+        Some(instance)
+    }
+}
+
+fn type_arg_name(type_arg: &ast::TsType) -> Option<String> {
+    match type_arg {
+        ast::TsType::TsKeywordType(keyword) => keyword_name(keyword.kind),
+        ast::TsType::TsTypeRef(ast::TsTypeRef { type_name: ast::TsEntityName::Ident(ident), type_params: None, .. }) => {
+            Some(ident.sym.to_string())
+        }
+        // Context ids are split on "."
+        ast::TsType::TsLitType(ast::TsLitType { lit: ast::TsLit::Str(str), .. }) if !str.value.contains('.') => {
+            Some(format!("{:?}", &*str.value))
+        }
+        ast::TsType::TsLitType(ast::TsLitType { lit: ast::TsLit::Number(num), .. }) if num.value.fract() == 0.0 => {
+            Some(num.value.to_string())
+        }
+        ast::TsType::TsLitType(ast::TsLitType { lit: ast::TsLit::Bool(bool), .. }) => Some(bool.value.to_string()),
+        ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type)) => {
+            let names = union_type.types.iter()
+                .map(|ty| type_arg_name(ty))
+                .collect::<Option<Vec<String>>>()?;
+            Some(names.join(" | "))
+        }
+        _ => None
+    }
+}
+
+fn keyword_name(kind: ast::TsKeywordTypeKind) -> Option<String> {
+    let name = match kind {
+        ast::TsKeywordTypeKind::TsStringKeyword => "string",
+        ast::TsKeywordTypeKind::TsNumberKeyword => "number",
+        ast::TsKeywordTypeKind::TsBooleanKeyword => "boolean",
+        ast::TsKeywordTypeKind::TsBigIntKeyword => "bigint",
+        ast::TsKeywordTypeKind::TsUnknownKeyword => "unknown",
+        ast::TsKeywordTypeKind::TsAnyKeyword => "any",
+        ast::TsKeywordTypeKind::TsNullKeyword => "null",
+        ast::TsKeywordTypeKind::TsUndefinedKeyword => "undefined",
+        _ => return None
+    };
+    Some(name.to_string())
+}
+
+struct TypeParamSubstitution<'s>(&'s HashMap<ast::Id, ast::TsType>);
+
+impl<'s> VisitMut for TypeParamSubstitution<'s> {
+    fn visit_mut_ts_type(&mut self, n: &mut ast::TsType) {
+        if let ast::TsType::TsTypeRef(ast::TsTypeRef { type_name: ast::TsEntityName::Ident(ident), type_params: None, .. }) = n {
+            if let Some(substitution) = self.0.get(&ident.to_id()) {
+                *n = substitution.clone();
+                return
+            }
+        }
+        visit::visit_mut_ts_type(self, n)
+    }
+}
+
 #[derive(Debug)]
 pub struct TypeAliasVisitor<'m, C>(pub &'m mut C);
 
@@ -1,16 +1,88 @@
+use std::collections::HashSet;
 use std::ops::Deref;
 
 use crate::CanPush;
 use crate::{ast, visit};
 
+/// A reference to a declaration, qualified by the namespaces it is a member of
+/// (e.g. `Api.Request` is `Request` in the namespace `Api`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TypeName {
+    pub root: ast::Id,
+    pub path: Vec<String>,
+    /// Whether the name is that of a value (as in `typeof Mode`) rather than
+    /// of a type. A constant and a type may share a name, and so an id.
+    pub value: bool,
+}
+
+impl TypeName {
+    pub fn from_entity_name(entity_name: &ast::TsEntityName) -> Self {
+        match entity_name {
+            ast::TsEntityName::Ident(ident) => ident.to_id().into(),
+            ast::TsEntityName::TsQualifiedName(qualified_name) => {
+                let mut type_name = Self::from_entity_name(&qualified_name.left);
+                type_name.path.push(qualified_name.right.sym.to_string());
+                type_name
+            }
+        }
+    }
+}
+
+impl From<ast::Id> for TypeName {
+    fn from(root: ast::Id) -> Self {
+        Self {
+            root,
+            path: Vec::new(),
+            value: false
+        }
+    }
+}
+
+/// The entity name referred to by an expression such as `Base` or `Api.Base`
+/// (in `extends` and `implements` clauses).
+pub fn entity_name_of(expr: &ast::Expr) -> Option<ast::TsEntityName> {
+    match expr {
+        ast::Expr::Ident(ident) => Some(ast::TsEntityName::Ident(ident.clone())),
+        ast::Expr::Member(ast::MemberExpr { obj, prop: ast::MemberProp::Ident(prop), .. }) => {
+            Some(ast::TsEntityName::TsQualifiedName(Box::new(ast::TsQualifiedName {
+                left: entity_name_of(obj)?,
+                right: prop.clone()
+            })))
+        }
+        _ => None
+    }
+}
+
 #[derive(Debug, Clone)]
-pub struct TypeRef(pub ast::TsTypeRef);
+pub struct TypeRef {
+    pub type_ref: ast::TsTypeRef,
+    /// Whether this is a `typeof` query, which refers to a value
+    pub query: bool,
+}
+
+impl TypeRef {
+    pub fn name(&self) -> TypeName {
+        TypeName {
+            value: self.query,
+            ..TypeName::from_entity_name(&self.type_name)
+        }
+    }
+
+    pub fn id(&self) -> Option<ast::Id> {
+        self.type_name.as_ident().map(ast::Ident::to_id)
+    }
+
+    /// Whether this refers to one of `type_params` rather than to a declaration.
+    pub fn is_bound_by(&self, type_params: &HashSet<ast::Id>) -> bool {
+        self.id().map(|id| type_params.contains(&id)).unwrap_or(false)
+    }
+}
 
 impl Deref for TypeRef {
     type Target = ast::TsTypeRef;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.type_ref
     }
 }
 
@@ -22,8 +94,54 @@ impl<'m, C> visit::Visit for TypeRefVisitor<'m, C>
         C: CanPush<TypeRef>
 {
     fn visit_ts_type_ref(&mut self, n: &ast::TsTypeRef) {
-        self.0.push(TypeRef(n.clone()));
+        self.0.push(TypeRef { type_ref: n.clone(), query: false });
         visit::visit_ts_type_ref(self, n)
     }
+
+    fn visit_ts_expr_with_type_args(&mut self, n: &ast::TsExprWithTypeArgs) {
+        // `extends`/`implements` clauses refer to types through expressions
+        if let Some(type_name) = entity_name_of(&n.expr) {
+            self.0.push(TypeRef {
+                type_ref: ast::TsTypeRef {
+                    span: n.span,
+                    type_name,
+                    type_params: n.type_args.clone()
+                },
+                query: false
+            });
+        }
+        visit::visit_ts_expr_with_type_args(self, n)
+    }
+
+    fn visit_ts_type_query(&mut self, n: &ast::TsTypeQuery) {
+        // `typeof X` refers to the declaration of the value `X`
+        if let ast::TsTypeQueryExpr::TsEntityName(type_name) = &n.expr_name {
+            self.0.push(TypeRef {
+                type_ref: ast::TsTypeRef {
+                    span: n.span,
+                    type_name: type_name.clone(),
+                    type_params: n.type_args.clone()
+                },
+                query: true
+            });
+        }
+        visit::visit_ts_type_query(self, n)
+    }
 }
 
+
+/// Collects the ids of the type parameters declared within a node. Scope
+/// analysis gives each of them its own syntax context, so they never collide
+/// with declarations of the same name.
+#[derive(Debug)]
+pub struct TypeParamVisitor<'m, C>(pub &'m mut C);
+
+impl<'m, C> visit::Visit for TypeParamVisitor<'m, C>
+    where
+        C: CanPush<ast::Id>
+{
+    fn visit_ts_type_param(&mut self, n: &ast::TsTypeParam) {
+        self.0.push(n.name.to_id());
+        visit::visit_ts_type_param(self, n)
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use crate::CanPush;
+use crate::{ast, visit};
+use crate::common::DUMMY_SP;
+
+impl CanPush<VarDecl> for HashMap<ast::Id, VarDecl> {
+    fn push(&mut self, item: VarDecl) {
+        HashMap::insert(self, item.ident.to_id(), item);
+    }
+}
+
+/// A `const` declaration, stripped of its initializer: only its (declared or
+/// inferred) type is kept.
+#[derive(Debug)]
+pub struct VarDecl {
+    pub var_decl: ast::VarDecl,
+    pub ident: ast::Ident,
+}
+
+impl VarDecl {
+    pub fn type_ann(&self) -> Option<&ast::TsType> {
+        self.var_decl.decls.first()?
+            .name.as_ident()?
+            .type_ann.as_deref()
+            .map(|type_ann| &*type_ann.type_ann)
+    }
+}
+
+impl Deref for VarDecl {
+    type Target = ast::VarDecl;
+
+    fn deref(&self) -> &Self::Target {
+        &self.var_decl
+    }
+}
+
+fn keyword(kind: ast::TsKeywordTypeKind) -> ast::TsType {
+    ast::TsType::TsKeywordType(ast::TsKeywordType {
+        span: DUMMY_SP,
+        kind
+    })
+}
+
+/// Infers the type of a value from its initializer, the way TypeScript would
+/// for the simple (literal) cases. With `as const`, literals keep their literal
+/// types and objects/arrays become readonly.
+pub fn infer_type(expr: &ast::Expr, as_const: bool) -> ast::TsType {
+    match expr {
+        ast::Expr::Paren(paren) => infer_type(&paren.expr, as_const),
+        ast::Expr::TsConstAssertion(assertion) => infer_type(&assertion.expr, true),
+        ast::Expr::TsAs(ts_as) => (*ts_as.type_ann).clone(),
+        ast::Expr::TsSatisfies(satisfies) => infer_type(&satisfies.expr, as_const),
+        ast::Expr::Lit(lit) => match lit {
+            ast::Lit::Str(str) if as_const => ast::TsType::TsLitType(ast::TsLitType {
+                span: DUMMY_SP,
+                lit: ast::TsLit::Str(str.clone())
+            }),
+            ast::Lit::Num(num) if as_const => ast::TsType::TsLitType(ast::TsLitType {
+                span: DUMMY_SP,
+                lit: ast::TsLit::Number(num.clone())
+            }),
+            ast::Lit::Bool(bool) if as_const => ast::TsType::TsLitType(ast::TsLitType {
+                span: DUMMY_SP,
+                lit: ast::TsLit::Bool(*bool)
+            }),
+            ast::Lit::Str(_) => keyword(ast::TsKeywordTypeKind::TsStringKeyword),
+            ast::Lit::Num(_) => keyword(ast::TsKeywordTypeKind::TsNumberKeyword),
+            ast::Lit::Bool(_) => keyword(ast::TsKeywordTypeKind::TsBooleanKeyword),
+            ast::Lit::BigInt(_) => keyword(ast::TsKeywordTypeKind::TsBigIntKeyword),
+            ast::Lit::Null(_) => keyword(ast::TsKeywordTypeKind::TsNullKeyword),
+            _ => keyword(ast::TsKeywordTypeKind::TsUnknownKeyword)
+        },
+        ast::Expr::Unary(ast::UnaryExpr { op: ast::UnaryOp::Minus, arg, .. }) => match &**arg {
+            ast::Expr::Lit(ast::Lit::Num(num)) if as_const => ast::TsType::TsLitType(ast::TsLitType {
+                span: DUMMY_SP,
+                lit: ast::TsLit::Number(ast::Number {
+                    span: num.span,
+                    value: -num.value,
+                    raw: None
+                })
+            }),
+            ast::Expr::Lit(ast::Lit::Num(_)) => keyword(ast::TsKeywordTypeKind::TsNumberKeyword),
+            _ => keyword(ast::TsKeywordTypeKind::TsUnknownKeyword)
+        },
+        ast::Expr::Tpl(_) => keyword(ast::TsKeywordTypeKind::TsStringKeyword),
+        // Refer to other constants rather than inlining them
+        ast::Expr::Ident(ident) => ast::TsType::TsTypeQuery(ast::TsTypeQuery {
+            span: DUMMY_SP,
+            expr_name: ast::TsTypeQueryExpr::TsEntityName(ast::TsEntityName::Ident(ident.clone())),
+            type_args: None
+        }),
+        ast::Expr::Array(array) => {
+            let elem_types: Vec<ast::TsType> = array.elems.iter()
+                .map(|elem| match elem {
+                    Some(ast::ExprOrSpread { spread: None, expr }) => infer_type(expr, as_const),
+                    _ => keyword(ast::TsKeywordTypeKind::TsUnknownKeyword)
+                })
+                .collect();
+
+            if as_const {
+                ast::TsType::TsTypeOperator(ast::TsTypeOperator {
+                    span: DUMMY_SP,
+                    op: ast::TsTypeOperatorOp::ReadOnly,
+                    type_ann: Box::new(ast::TsType::TsTupleType(ast::TsTupleType {
+                        span: DUMMY_SP,
+                        elem_types: elem_types.into_iter()
+                            .map(|ty| ast::TsTupleElement {
+                                span: DUMMY_SP,
+                                label: None,
+                                ty: Box::new(ty)
+                            })
+                            .collect()
+                    }))
+                })
+            } else {
+                let elem_type = match elem_types.split_first() {
+                    Some((first, rest)) if rest.iter().all(|ty| ty == first) => first.clone(),
+                    _ => keyword(ast::TsKeywordTypeKind::TsUnknownKeyword)
+                };
+                ast::TsType::TsArrayType(ast::TsArrayType {
+                    span: DUMMY_SP,
+                    elem_type: Box::new(elem_type)
+                })
+            }
+        }
+        ast::Expr::Object(object) => {
+            let members = object.props.iter()
+                .filter_map(|prop| match prop {
+                    ast::PropOrSpread::Prop(prop) => match &**prop {
+                        ast::Prop::KeyValue(key_value) => Some((&key_value.key, infer_type(&key_value.value, as_const))),
+                        _ => None
+                    },
+                    ast::PropOrSpread::Spread(_) => None
+                })
+                .filter_map(|(key, ty)| {
+                    let key = match key {
+                        ast::PropName::Ident(ident) => ast::Expr::Ident(ident.clone()),
+                        ast::PropName::Str(str) => ast::Expr::Lit(ast::Lit::Str(str.clone())),
+                        ast::PropName::Num(num) => ast::Expr::Lit(ast::Lit::Num(num.clone())),
+                        _ => return None
+                    };
+                    Some(ast::TsTypeElement::TsPropertySignature(ast::TsPropertySignature {
+                        span: DUMMY_SP,
+                        readonly: as_const,
+                        key: Box::new(key),
+                        computed: false,
+                        optional: false,
+                        init: None,
+                        params: Vec::new(),
+                        type_ann: Some(Box::new(ast::TsTypeAnn {
+                            span: DUMMY_SP,
+                            type_ann: Box::new(ty)
+                        })),
+                        type_params: None
+                    }))
+                })
+                .collect();
+
+            ast::TsType::TsTypeLit(ast::TsTypeLit {
+                span: DUMMY_SP,
+                members
+            })
+        }
+        _ => keyword(ast::TsKeywordTypeKind::TsUnknownKeyword)
+    }
+}
+
+/// Collects the `const` declarations of a module (and of its namespaces), but
+/// not the ones local to functions or classes.
+#[derive(Debug)]
+pub struct VarDeclVisitor<'m, C>(pub &'m mut C);
+
+impl<'m, C> visit::Visit for VarDeclVisitor<'m, C>
+where
+    C: CanPush<VarDecl>
+{
+    fn visit_var_decl(&mut self, n: &ast::VarDecl) {
+        if n.kind != ast::VarDeclKind::Const {
+            return
+        }
+
+        for declarator in &n.decls {
+            let ast::Pat::Ident(binding) = &declarator.name else {
+                continue
+            };
+
+            let type_ann = match (&binding.type_ann, &declarator.init) {
+                (Some(type_ann), _) => type_ann.type_ann.clone(),
+                (None, Some(init)) => Box::new(infer_type(init, false)),
+                (None, None) => continue
+            };
+
+            let mut binding = binding.clone();
+            binding.type_ann = Some(Box::new(ast::TsTypeAnn {
+                span: DUMMY_SP,
+                type_ann
+            }));
+
+            let var_decl = ast::VarDecl {
+                span: n.span,
+                kind: n.kind,
+                declare: false,
+                decls: vec![ast::VarDeclarator {
+                    span: declarator.span,
+                    name: ast::Pat::Ident(binding.clone()),
+                    init: None,
+                    definite: false
+                }]
+            };
+
+            self.0.push(VarDecl {
+                var_decl,
+                ident: binding.id
+            });
+        }
+    }
+
+    fn visit_function(&mut self, _: &ast::Function) {}
+
+    fn visit_arrow_expr(&mut self, _: &ast::ArrowExpr) {}
+
+    fn visit_class(&mut self, _: &ast::Class) {}
+}
@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
+use ts_rs::TS;
+
+use crate::{AnyError, anyhow};
+use crate::prompts::Prompts;
+
+/// The version of the format of prompts modules, which the runtime checks
+/// before using one.
+///
+/// Compatibility policy: the runtime reads prompts modules of its own format,
+/// warns about older ones (which `kottoc migrate` upgrades in place), and
+/// rejects newer ones. Bump the version whenever the runtime would misread the
+/// modules of the previous one, and add a step to [`MIGRATIONS`].
+///
+/// 1. `ast` (and `validators`), without metadata
+/// 2. `meta`, describing how the module was built
+pub const FORMAT_VERSION: u32 = 2;
+
+/// The metadata of a prompts module, exported as `meta`.
+#[derive(Serialize, Deserialize, TS)]
+pub struct Meta {
+    pub format: u32,
+    /// The version of kottoc that wrote (or migrated) the module
+    pub kottoc: String,
+    /// The modules the prompts were compiled from
+    pub sources: Vec<Source>,
+    /// A fingerprint of the options the prompts were compiled with, unknown
+    /// for migrated modules
+    #[serde(default)]
+    pub options: Option<String>,
+}
+
+/// A module that prompts were compiled from.
+#[derive(Serialize, Deserialize, TS)]
+pub struct Source {
+    /// The URL of the module if remote, otherwise its path relative to the
+    /// root or output directory the prompts were built for (e.g.
+    /// `../src/agent.ts`), so that builds do not depend on where they are made.
+    /// Prompts compiled in memory keep the URLs of local modules too.
+    pub specifier: String,
+    /// The SHA-256 of the source of the module
+    pub hash: String,
+    /// The namespace that the ids of the module's prompts are qualified with,
+    /// in a bundle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub namespace: Option<String>,
+    /// The builtin functions that the module opts in to (e.g. `Deno.remove`),
+    /// unknown for modules built before they were recorded
+    #[serde(default)]
+    pub builtins: Option<Vec<String>>,
+}
+
+impl Meta {
+    pub fn new(sources: Vec<Source>, options: Option<String>) -> Self {
+        Self {
+            format: FORMAT_VERSION,
+            kottoc: env!("CARGO_PKG_VERSION").to_string(),
+            sources,
+            options
+        }
+    }
+}
+
+/// The SHA-256 of `data`, in hexadecimal.
+pub fn hash(data: &[u8]) -> String {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+const META_EXPORT: &str = "export const meta = ";
+const AST_EXPORT: &str = "export const ast = ";
+
+/// Reads the value of the export `export` (e.g. `export const ast = `) of the
+/// prompts module `text`, if it has one.
+fn read_export<T: DeserializeOwned>(text: &str, export: &str) -> Result<Option<T>, AnyError> {
+    let Some(start) = text.find(export) else {
+        return Ok(None)
+    };
+    let mut values = serde_json::Deserializer::from_str(&text[start + export.len()..]).into_iter::<T>();
+    match values.next() {
+        Some(value) => Ok(Some(value?)),
+        None => Err(anyhow!("missing value of the export `{}`", export.trim_end_matches([' ', '='])))
+    }
+}
+
+/// Reads the `meta` export of the prompts module `text`, if it has one.
+fn read_meta(text: &str) -> Result<Option<Meta>, AnyError> {
+    read_export(text, META_EXPORT)
+}
+
+/// Reads the prompts of the prompts module `text`, of any format.
+pub fn read_prompts(text: &str) -> Result<Prompts, AnyError> {
+    read_export(text, AST_EXPORT)?.ok_or_else(|| anyhow!("not a prompts module: missing the `ast` export"))
+}
+
+/// A step upgrading the text of a prompts module from a format to the next.
+type Migration = fn(&str) -> Result<String, AnyError>;
+
+/// The migrations, the first of which upgrades modules of format 1.
+const MIGRATIONS: &[Migration] = &[add_meta];
+
+fn add_meta(text: &str) -> Result<String, AnyError> {
+    let meta = Meta {
+        format: 2,
+        ..Meta::new(Vec::new(), None)
+    };
+
+    // After the reference to the declarations, which has to come first
+    let (head, rest) = match text.strip_prefix("///") {
+        Some(_) => text.split_at(text.find('\n').map(|i| i + 1).unwrap_or(text.len())),
+        None => ("", text)
+    };
+    Ok(format!("{}{}{};\n{}", head, META_EXPORT, serde_json::to_string(&meta)?, rest))
+}
+
+/// Upgrades the prompts module at `path` to the current format, returning the
+/// format it was in.
+pub fn migrate(path: &Path) -> Result<u32, AnyError> {
+    let mut text = std::fs::read_to_string(path)?;
+    let format = read_meta(&text)?.map(|meta| meta.format).unwrap_or(1);
+    if format == 0 || format > FORMAT_VERSION {
+        return Err(anyhow!(
+            "{} is of format {}, unknown to this kottoc (of format {})",
+            path.display(),
+            format,
+            FORMAT_VERSION
+        ))
+    }
+
+    for migration in &MIGRATIONS[(format - 1) as usize..] {
+        text = migration(&text)?;
+    }
+    if format < FORMAT_VERSION {
+        std::fs::write(path, text)?;
+    }
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT_1: &str = "/// <reference types=\"./agent.prompts.d.ts\" />\n\
+        export const ast = [{\"type\": \"ts\", \"fmt\": \"function f();\", \"id\": \"f#2\", \"ast_ty\": \"fn_decl\"}];\n\
+        export const validators = {};\n";
+
+    #[test]
+    fn migrate_round_trip() {
+        let dir = std::env::temp_dir().join(format!("kottoc-migrate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agent.prompts.js");
+        std::fs::write(&path, FORMAT_1).unwrap();
+
+        let migrated_from = migrate(&path).unwrap();
+        let migrated = std::fs::read_to_string(&path).unwrap();
+        let migrated_again_from = migrate(&path).unwrap();
+        let migrated_again = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(migrated_from, 1);
+        // The reference to the declarations stays first
+        assert!(migrated.starts_with("/// <reference types=\"./agent.prompts.d.ts\" />\nexport const meta = "));
+        let meta = read_meta(&migrated).unwrap().unwrap();
+        assert_eq!((meta.format, meta.sources.len(), meta.options), (FORMAT_VERSION, 0, None));
+        assert_eq!(
+            serde_json::to_string(&read_prompts(&migrated).unwrap()).unwrap(),
+            serde_json::to_string(&read_prompts(FORMAT_1).unwrap()).unwrap()
+        );
+
+        // Modules of the current format are left as they are
+        assert_eq!(migrated_again_from, FORMAT_VERSION);
+        assert_eq!(migrated_again, migrated);
+    }
+
+    #[test]
+    fn reject_unknown_formats() {
+        let dir = std::env::temp_dir().join(format!("kottoc-migrate-newer-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agent.prompts.js");
+        let newer = format!("export const meta = {{\"format\": {}, \"kottoc\": \"9.0.0\", \"sources\": []}};\nexport const ast = [];\n", FORMAT_VERSION + 1);
+        std::fs::write(&path, &newer).unwrap();
+
+        let err = migrate(&path).err().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(err.to_string().contains("unknown to this kottoc"), "{}", err);
+        assert_eq!(text, newer);
+        assert!(read_prompts("export const meta = {};\n").is_err());
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+use deno_ast::{ModuleSpecifier, ParsedSource};
+use deno_ast::swc::common::comments::SingleThreadedComments;
+
+use tracing::{event, Level};
+
+use crate::{AnyError, anyhow};
+use crate::{ast, builtins, filter, format};
+use crate::common::BytePos;
+use crate::filter::{FilteredModule, ImportName, TypeName};
+use crate::prompts::PromptSource;
+use crate::tasks::parse_module;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub async fn fetch_module_source(specifier: &ModuleSpecifier) -> Result<String, AnyError> {
+    if specifier.scheme().contains("http") {
+        let resp = reqwest::get(specifier.to_string()).await?;
+        if resp.status() == 200 {
+            Ok(resp.text().await?)
+        } else {
+            Err(anyhow!("could not retrieve {specifier}: {}", resp.status()))
+        }
+    } else {
+        let path = specifier.to_file_path()
+            .map_err(|_| anyhow!("not a local path: {specifier}"))?;
+        Ok(tokio::fs::read_to_string(path).await?)
+    }
+}
+
+/// Modules are fetched by the host of WebAssembly builds, which provides them
+/// to the graph (see [`ModuleGraph::with_sources`]).
+#[cfg(target_arch = "wasm32")]
+pub async fn fetch_module_source(specifier: &ModuleSpecifier) -> Result<String, AnyError> {
+    Err(anyhow!("cannot fetch {specifier}: modules are to be provided by the host"))
+}
+
+pub struct Module {
+    pub specifier: ModuleSpecifier,
+    pub comments: SingleThreadedComments,
+    pub filtered_module: FilteredModule,
+    /// The name that the ids of the prompts of the module are qualified with
+    /// when modules are bundled together (e.g. `agent` for `agent.ts`)
+    pub namespace: Option<String>,
+    /// The SHA-256 of the source of the module
+    pub hash: String,
+    pub text_info: deno_ast::SourceTextInfo,
+}
+
+impl Module {
+    /// Like [`FilteredModule::context_id_of`], qualified by the namespace of
+    /// the module.
+    pub fn context_id_of(&self, id: &ast::Id, value: bool) -> Option<String> {
+        let context_id = self.filtered_module.context_id_of(id, value)?;
+        let (ast_ty, scoped_name) = context_id.split_once('.')?;
+        Some(format!("{}.{}", ast_ty, self.qualified_name(scoped_name)))
+    }
+
+    /// The modules that this one imports from.
+    pub fn dependencies(&self) -> impl Iterator<Item = ModuleSpecifier> + '_ {
+        self.filtered_module.imports.values().filter_map(|import| self.specifier.join(&import.src).ok())
+    }
+
+    /// Where `pos` (e.g. the start of a declaration) is in the module.
+    pub fn source_at(&self, pos: BytePos) -> Option<PromptSource> {
+        let range = self.text_info.range();
+        if pos < range.start.as_byte_pos() || pos > range.end.as_byte_pos() {
+            return None
+        }
+        Some(PromptSource {
+            specifier: self.specifier.to_string(),
+            line: self.text_info.line_index(deno_ast::SourcePos::unsafely_from_byte_pos(pos)) + 1
+        })
+    }
+
+    /// Qualifies `scoped_name` (see [`FilteredModule::scoped_name`]) by the
+    /// namespace of the module, if it has one.
+    pub fn qualified_name(&self, scoped_name: &str) -> String {
+        match &self.namespace {
+            Some(namespace) => format!("{}.{}", namespace, scoped_name),
+            None => scoped_name.to_string()
+        }
+    }
+}
+
+/// The namespace of the module `specifier` in a bundle: its file name, without
+/// extensions, as an identifier.
+fn namespace_of(specifier: &ModuleSpecifier) -> String {
+    let file_name = specifier.path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default();
+    let stem = file_name.split('.').next().unwrap_or_default();
+    let mut namespace: String = stem.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == '$' { c } else { '_' })
+        .collect();
+    if namespace.is_empty() || namespace.starts_with(|c: char| c.is_ascii_digit()) {
+        namespace.insert(0, '_');
+    }
+    namespace
+}
+
+/// A declaration, together with the module it was found in.
+#[derive(Clone)]
+pub struct Resolved {
+    pub module: Rc<Module>,
+    pub id: ast::Id,
+    /// Whether the declaration is that of a value (see [`TypeName::value`])
+    pub value: bool,
+}
+
+impl Resolved {
+    /// See [`Module::context_id_of`].
+    pub fn context_id(&self) -> Option<String> {
+        self.module.context_id_of(&self.id, self.value)
+    }
+}
+
+/// The parsed sources of modules, which graphs share (e.g. those of modules
+/// compiled concurrently) so that the modules they have in common are only
+/// fetched and parsed once.
+#[derive(Clone, Default)]
+pub struct SourceCache(Arc<Mutex<HashMap<ModuleSpecifier, ParsedSource>>>);
+
+impl SourceCache {
+    pub fn get(&self, specifier: &ModuleSpecifier) -> Option<ParsedSource> {
+        self.0.lock().unwrap().get(specifier).cloned()
+    }
+
+    pub fn insert(&self, specifier: ModuleSpecifier, parsed_source: ParsedSource) {
+        self.0.lock().unwrap().insert(specifier, parsed_source);
+    }
+
+    pub fn remove(&self, specifier: &ModuleSpecifier) {
+        self.0.lock().unwrap().remove(specifier);
+    }
+}
+
+/// Modules loaded while compiling, keyed by their specifier so that each is
+/// only fetched and parsed once.
+#[derive(Default)]
+pub struct ModuleGraph {
+    modules: HashMap<ModuleSpecifier, Rc<Module>>,
+    /// The parsed sources of the modules loaded
+    cache: SourceCache,
+    /// The namespaces given to the modules, if they are to be bundled
+    namespaces: Option<HashSet<String>>,
+    /// The sources of the modules, if they are provided rather than fetched
+    sources: Option<HashMap<ModuleSpecifier, String>>,
+    /// The modules that were to be loaded but were not provided
+    missing: HashSet<ModuleSpecifier>,
+}
+
+impl ModuleGraph {
+    /// A graph whose modules are given distinct namespaces, for their prompts
+    /// to be bundled together.
+    pub fn namespaced() -> Self {
+        Self {
+            namespaces: Some(HashSet::new()),
+            ..Self::default()
+        }
+    }
+
+    /// A graph whose modules are loaded from `sources` rather than fetched,
+    /// e.g. when the host fetches them for a WebAssembly build.
+    pub fn with_sources(sources: HashMap<ModuleSpecifier, String>) -> Self {
+        Self {
+            sources: Some(sources),
+            ..Self::default()
+        }
+    }
+
+    /// The graph, with its modules loaded from `cache` when they are in it,
+    /// and added to it otherwise.
+    pub fn with_cache(self, cache: SourceCache) -> Self {
+        Self {
+            cache,
+            ..self
+        }
+    }
+
+    /// The modules that were to be loaded but were not in the sources the
+    /// graph was given (see [`with_sources`](Self::with_sources)).
+    pub fn missing(&self) -> impl Iterator<Item = &ModuleSpecifier> {
+        self.missing.iter()
+    }
+
+    pub async fn add_module(
+        &mut self,
+        specifier: ModuleSpecifier,
+        module_source: String
+    ) -> Result<Rc<Module>, AnyError> {
+        let parsed_source = parse_module(specifier.to_string(), module_source).await?;
+        self.add_parsed(specifier, parsed_source).await
+    }
+
+    /// Like [`add_module`](Self::add_module), with the source parsed already.
+    pub async fn add_parsed(
+        &mut self,
+        specifier: ModuleSpecifier,
+        parsed_source: ParsedSource
+    ) -> Result<Rc<Module>, AnyError> {
+        let hash = format::hash(parsed_source.text_info().text_str().as_bytes());
+        let comments = parsed_source.comments().as_single_threaded();
+        let text_info = parsed_source.text_info().clone();
+
+        let filter_params = filter::FilterParams::default();
+        let filtered_module = filter::run_filters(filter_params, parsed_source.module()).await?;
+
+        // The bundled builtins are shared by all modules
+        let namespace = match &mut self.namespaces {
+            Some(namespaces) if !builtins::is_builtin(&specifier) => {
+                let base = namespace_of(&specifier);
+                let mut namespace = base.clone();
+                let mut n = 1;
+                while !namespaces.insert(namespace.clone()) {
+                    n += 1;
+                    namespace = format!("{}_{}", base, n);
+                }
+                Some(namespace)
+            }
+            _ => None
+        };
+
+        let module = Rc::new(Module {
+            specifier: specifier.clone(),
+            comments,
+            filtered_module,
+            namespace,
+            hash,
+            text_info
+        });
+        self.modules.insert(specifier, module.clone());
+
+        Ok(module)
+    }
+
+    /// The modules loaded so far.
+    pub fn modules(&self) -> impl Iterator<Item = &Rc<Module>> {
+        self.modules.values()
+    }
+
+    /// The module `specifier`, if it is loaded.
+    pub fn get(&self, specifier: &ModuleSpecifier) -> Option<&Rc<Module>> {
+        self.modules.get(specifier)
+    }
+
+    /// Drops the module `specifier` (e.g. as its source changed) along with
+    /// the modules that import it, directly or not, for them to be loaded
+    /// again (and the source of `specifier` fetched again). Returns the
+    /// specifiers of the modules dropped.
+    pub fn invalidate(&mut self, specifier: &ModuleSpecifier) -> HashSet<ModuleSpecifier> {
+        let mut dropped = HashSet::new();
+        if self.modules.contains_key(specifier) {
+            dropped.insert(specifier.clone());
+        }
+        loop {
+            let dependents: Vec<ModuleSpecifier> = self.modules.values()
+                .filter(|module| !dropped.contains(&module.specifier))
+                .filter(|module| module.dependencies().any(|dependency| &dependency == specifier || dropped.contains(&dependency)))
+                .map(|module| module.specifier.clone())
+                .collect();
+            if dependents.is_empty() {
+                break
+            }
+            dropped.extend(dependents);
+        }
+
+        self.modules.retain(|specifier, _| !dropped.contains(specifier));
+        self.cache.remove(specifier);
+        dropped
+    }
+
+    pub async fn load(&mut self, specifier: &ModuleSpecifier) -> Result<Rc<Module>, AnyError> {
+        if let Some(module) = self.modules.get(specifier) {
+            return Ok(module.clone())
+        }
+
+        if let Some(parsed_source) = self.cache.get(specifier) {
+            return self.add_parsed(specifier.clone(), parsed_source).await
+        }
+
+        event!(Level::DEBUG, "loading {}", specifier);
+        let module_source = match &mut self.sources {
+            Some(sources) => match sources.remove(specifier) {
+                Some(module_source) => module_source,
+                None => {
+                    self.missing.insert(specifier.clone());
+                    return Err(anyhow!("{} was not provided", specifier))
+                }
+            },
+            None => fetch_module_source(specifier).await?
+        };
+        let parsed_source = parse_module(specifier.to_string(), module_source).await?;
+        self.cache.insert(specifier.clone(), parsed_source.clone());
+        self.add_parsed(specifier.clone(), parsed_source).await
+    }
+
+    /// Like [`load`](Self::load), with the source of the module fetched
+    /// already.
+    pub async fn load_source(&mut self, specifier: &ModuleSpecifier, module_source: String) -> Result<Rc<Module>, AnyError> {
+        if let Some(module) = self.modules.get(specifier) {
+            return Ok(module.clone())
+        }
+        self.add_module(specifier.clone(), module_source).await
+    }
+
+    async fn load_bundled(&mut self, specifier: ModuleSpecifier, module_source: &str) -> Result<Rc<Module>, AnyError> {
+        if let Some(module) = self.modules.get(&specifier) {
+            return Ok(module.clone())
+        }
+        self.add_module(specifier, module_source.to_string()).await
+    }
+
+    /// The module of the bundled builtin declarations.
+    pub async fn builtins(&mut self) -> Result<Rc<Module>, AnyError> {
+        self.load_bundled(builtins::types_specifier(), builtins::TYPES).await
+    }
+
+    /// The module of the bundled builtin function declarations.
+    pub async fn builtin_functions(&mut self) -> Result<Rc<Module>, AnyError> {
+        self.load_bundled(builtins::functions_specifier(), builtins::FUNCTIONS).await
+    }
+
+    /// Resolves a type name, as seen from `module`, to the declaration it
+    /// refers to. Imports (including namespace imports) are followed into the
+    /// module they come from, qualified names into namespaces, and names that
+    /// are neither declared nor imported fall back to the builtin declarations.
+    ///
+    /// Failing to load an imported module is not fatal: it is logged and the
+    /// name is left unresolved.
+    pub async fn resolve(&mut self, module: &Rc<Module>, type_name: &TypeName) -> Option<Resolved> {
+        if let Some(import) = module.filtered_module.imports.get(&type_name.root) {
+            let dependency = match module.specifier.join(&import.src) {
+                Ok(specifier) => self.load(&specifier).await.map(|_| ()),
+                Err(err) => Err(err.into())
+            };
+            if let Err(err) = dependency {
+                event!(Level::WARN, "cannot resolve '{}' from {}: {}", import.local.sym, import.src, err);
+                return None
+            }
+        } else if let Err(err) = self.builtins().await {
+            event!(Level::ERROR, "cannot load builtins: {}", err);
+        }
+
+        self.resolve_loaded(module, type_name)
+    }
+
+    /// Like [`resolve`](Self::resolve), but only looks into the modules that
+    /// have already been loaded.
+    pub fn resolve_loaded(&self, module: &Rc<Module>, type_name: &TypeName) -> Option<Resolved> {
+        let filtered_module = &module.filtered_module;
+        let mut path = type_name.path.iter();
+
+        let root = if filtered_module.context_id_of(&type_name.root, type_name.value).is_some()
+            || filtered_module.namespaces.decls.contains_key(&type_name.root) {
+            Resolved { module: module.clone(), id: type_name.root.clone(), value: type_name.value }
+        } else if let Some(import) = filtered_module.imports.get(&type_name.root) {
+            let dependency = self.modules.get(&module.specifier.join(&import.src).ok()?)?;
+            let id = match &import.name {
+                ImportName::Named(name) => dependency.filtered_module.find_exported(name)?,
+                ImportName::Namespace => dependency.filtered_module.find_exported(path.next()?)?,
+                ImportName::Default => dependency.filtered_module.default_export.clone()?
+            };
+            Resolved { module: dependency.clone(), id, value: type_name.value }
+        } else {
+            let builtins = self.modules.get(&builtins::types_specifier())?;
+            let id = builtins.filtered_module.find_exported(&type_name.root.0)?;
+            Resolved { module: builtins.clone(), id, value: type_name.value }
+        };
+
+        let id = root.module.filtered_module.namespaces.lookup(&root.id, path)?;
+        root.module.filtered_module.context_id_of(&id, type_name.value)?;
+
+        Some(Resolved { module: root.module, id, value: type_name.value })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn invalidate_reloads_source() {
+        let dir = std::env::temp_dir().join(format!("kottoc-graph-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("agent.ts");
+        std::fs::write(dir.join("types.ts"), "export type Query = string;\n").unwrap();
+        std::fs::write(&path, "import { Query } from \"./types.ts\";\nexport type A = Query;\n").unwrap();
+        let types = ModuleSpecifier::from_file_path(dir.join("types.ts")).unwrap();
+        let agent = ModuleSpecifier::from_file_path(&path).unwrap();
+
+        let mut module_graph = ModuleGraph::default();
+        let before = module_graph.load(&agent).await.unwrap().hash.clone();
+        module_graph.load(&types).await.unwrap();
+
+        std::fs::write(&path, "export type A = number;\n").unwrap();
+        // Unchanged but reloaded, as it is cached
+        assert_eq!(module_graph.load(&agent).await.unwrap().hash, before);
+
+        // Modules importing the one invalidated are dropped, not those it imports
+        assert_eq!(module_graph.invalidate(&types), HashSet::from([types.clone(), agent.clone()]));
+        assert_eq!(module_graph.invalidate(&agent), HashSet::new());
+        let after = module_graph.load(&agent).await.unwrap();
+        assert_ne!(after.hash, before);
+        assert_eq!(after.text_info.text_str(), "export type A = number;\n");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
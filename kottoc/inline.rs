@@ -0,0 +1,161 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::{ast, emit, visit};
+use crate::codegen::Node;
+use crate::common::{Span, DUMMY_SP};
+use crate::common::comments::{CommentKind, Comments};
+use crate::filter::{TypeName, TypeRef, TypeRefVisitor};
+use crate::graph::{Module, ModuleGraph};
+
+use visit::{Visit, VisitMut, VisitMutWith};
+
+/// Small type aliases (e.g. `type Id = string`) that signatures refer to, to
+/// be substituted for their references so that the model does not have to
+/// look them up in the context.
+///
+/// Only aliases that are neither generic nor refer to other declarations are
+/// inlined, so that their definition reads the same where they are used.
+pub struct InlinedAliases {
+    /// The types that references are replaced with, by the name they are
+    /// referred to with
+    replacements: HashMap<TypeName, ast::TsType>,
+    /// The names whose references were replaced
+    replaced: HashSet<TypeName>,
+}
+
+impl InlinedAliases {
+    /// Finds the aliases among `type_refs` (which are relative to `module`)
+    /// whose definitions are at most `max_size` characters long.
+    pub async fn find(
+        module_graph: &mut ModuleGraph,
+        module: &Rc<Module>,
+        max_size: usize,
+        type_refs: &[TypeRef]
+    ) -> Self {
+        let mut replacements = HashMap::new();
+        for type_ref in type_refs {
+            let type_name = type_ref.name();
+            if type_ref.type_params.is_some() || replacements.contains_key(&type_name) {
+                continue
+            }
+            let Some(decl) = module_graph.resolve(module, &type_name).await else {
+                continue
+            };
+            let Some(type_alias_decl) = decl.module.filtered_module.type_alias_decls.get(&decl.id) else {
+                continue
+            };
+            if type_alias_decl.type_params.is_some() {
+                continue
+            }
+
+            let mut referenced = Vec::new();
+            TypeRefVisitor(&mut referenced).visit_ts_type(&type_alias_decl.type_ann);
+            if !referenced.is_empty() {
+                continue
+            }
+
+            if type_text(&type_alias_decl.type_ann).len() > max_size {
+                continue
+            }
+
+            // Spans are relative to the module of the alias, and would pick
+            // up comments of the module it is inlined into
+            let mut ty = (*type_alias_decl.type_ann).clone();
+            ty.visit_mut_with(&mut DropSpans);
+            if needs_parens(&ty) {
+                ty = ast::TsType::TsParenthesizedType(ast::TsParenthesizedType {
+                    span: DUMMY_SP,
+                    type_ann: Box::new(ty)
+                });
+            }
+            if let Some(doc) = doc_of(&decl.module.comments, type_alias_decl.span.lo) {
+                ty = emit::noted_type(&ty, &doc);
+            }
+
+            replacements.insert(type_name, ty);
+        }
+        Self {
+            replacements,
+            replaced: HashSet::new()
+        }
+    }
+
+    /// Substitutes the aliases for their references in `node`, a signature
+    /// about to be emitted.
+    pub fn apply<N: VisitMutWith<Self>>(&mut self, node: &mut N) {
+        node.visit_mut_with(self)
+    }
+
+    /// The references among `type_refs` that are left once the aliases are
+    /// substituted.
+    pub fn remaining(&self, type_refs: &[TypeRef]) -> Vec<TypeRef> {
+        type_refs.iter()
+            .filter(|type_ref| type_ref.type_params.is_some() || !self.replaced.contains(&type_ref.name()))
+            .cloned()
+            .collect()
+    }
+}
+
+impl VisitMut for InlinedAliases {
+    fn visit_mut_ts_type(&mut self, n: &mut ast::TsType) {
+        if let ast::TsType::TsTypeRef(type_ref) = n {
+            if type_ref.type_params.is_none() {
+                let type_name = TypeName::from_entity_name(&type_ref.type_name);
+                if let Some(ty) = self.replacements.get(&type_name) {
+                    *n = ty.clone();
+                    self.replaced.insert(type_name);
+                    return
+                }
+            }
+        }
+        n.visit_mut_children_with(self)
+    }
+}
+
+struct DropSpans;
+
+impl VisitMut for DropSpans {
+    fn visit_mut_span(&mut self, n: &mut Span) {
+        *n = DUMMY_SP;
+    }
+}
+
+pub fn type_text(ty: &ast::TsType) -> String {
+    let mut buf = Vec::new();
+    ty.emit_with(&mut emit::Emitter::new(&mut buf)).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+/// Whether `ty` has to be parenthesized where another type is expected (e.g.
+/// the element type of an array).
+fn needs_parens(ty: &ast::TsType) -> bool {
+    matches!(
+        ty,
+        ast::TsType::TsUnionOrIntersectionType(_)
+            | ast::TsType::TsFnOrConstructorType(_)
+            | ast::TsType::TsConditionalType(_)
+            | ast::TsType::TsTypeOperator(_)
+    )
+}
+
+/// The JSDoc of the declaration at `pos`, on a single line.
+fn doc_of(comments: &dyn Comments, pos: crate::common::BytePos) -> Option<String> {
+    let comment = comments.get_leading(pos)?
+        .into_iter()
+        .rev()
+        .find(|comment| comment.kind == CommentKind::Block && comment.text.starts_with('*'))?;
+
+    let doc = comment.text
+        .lines()
+        .map(|line| line.trim().trim_start_matches('*').trim())
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+
+    if doc.is_empty() {
+        None
+    } else {
+        Some(doc)
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use deno_ast::ModuleSpecifier;
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::WalkBuilder;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+use tracing::{event, Level};
+
+use crate::{AnyError, anyhow};
+use crate::filter::ClassMember;
+use crate::graph::{self, Module, ModuleGraph};
+use crate::tasks::PROMPTS_EXT;
+
+/// The extensions of the modules found in directories.
+const MODULE_EXTS: [&str; 4] = ["ts", "tsx", "js", "mts"];
+
+/// The project configuration whose `exclude` patterns and import map are
+/// honored.
+const CONFIG_FILE_NAME: &str = "deno.json";
+
+/// The host serving kotto's `mod.ts`.
+const KOTTO_HOST: &str = "kotto.land";
+
+/// The name that import maps give kotto (e.g. `"kotto": "../kotto/mod.ts"`).
+const KOTTO_IMPORT: &str = "kotto";
+
+/// The matcher of the glob `pattern`, for paths relative to the directory it
+/// is rooted at: `*` and `?` match within a path component, `**` across
+/// components and `[...]` a class of characters.
+fn glob_matcher(pattern: &str) -> Result<GlobMatcher, AnyError> {
+    let glob = GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .backslash_escape(true)
+        .build()
+        .map_err(|err| anyhow!("invalid pattern '{}': {}", pattern, err))?;
+    Ok(glob.compile_matcher())
+}
+
+fn is_glob(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Splits the glob `pattern` into the directory to search, made of its leading
+/// components without wildcards, and the pattern that the paths relative to it
+/// are to match (e.g. `agents` and `**/*.ts` for `agents/**/*.ts`).
+fn split_pattern(pattern: &str) -> (String, String) {
+    let components: Vec<&str> = pattern.split('/').collect();
+    let n = components.iter().position(|component| is_glob(component)).unwrap_or(components.len());
+    (components[..n].join("/"), components[n..].join("/"))
+}
+
+/// The `.gitignore` line of the exclusion `pattern`, which is relative to the
+/// directory it is given in (e.g. `dist` only excludes the `dist` there)
+/// rather than matching at any depth.
+fn anchored(pattern: &str) -> String {
+    let (negation, pattern) = match pattern.strip_prefix('!') {
+        Some(pattern) => ("!", pattern),
+        None => ("", pattern)
+    };
+    let pattern = pattern.strip_prefix("./").unwrap_or(pattern);
+    format!("{}/{}", negation, pattern.trim_start_matches('/'))
+}
+
+/// Patterns excluding paths from the modules found in directories: the
+/// `exclude` of `deno.json` and `--exclude`, each relative to the directory it
+/// is given in. Unlike those of `.gitignore` files, they cannot be included
+/// back by other files.
+#[derive(Clone, Default)]
+struct Excludes(Vec<Gitignore>);
+
+impl Excludes {
+    fn add<'p>(&mut self, base: &Path, patterns: impl IntoIterator<Item = &'p str>) -> Result<(), AnyError> {
+        let mut builder = GitignoreBuilder::new(base);
+        for pattern in patterns {
+            builder.add_line(None, &anchored(pattern))
+                .map_err(|err| anyhow!("invalid pattern '{}': {}", pattern, err))?;
+        }
+        self.0.push(builder.build()?);
+        Ok(())
+    }
+
+    fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.0.iter().any(|gitignore| path.starts_with(gitignore.path()) && gitignore.matched(path, is_dir).is_ignore())
+    }
+}
+
+/// The `deno.json` of the project a directory is in.
+#[derive(Default)]
+struct Config {
+    /// The URL of the file, which the targets of `imports` are relative to
+    specifier: Option<ModuleSpecifier>,
+    exclude: Vec<String>,
+    /// The import map
+    imports: HashMap<String, String>,
+}
+
+impl Config {
+    /// The configuration of the project that `dir` is in, if there is one.
+    fn find(dir: &Path) -> Result<Self, AnyError> {
+        let Some(path) = dir.ancestors()
+            .map(|ancestor| ancestor.join(CONFIG_FILE_NAME))
+            .find(|path| path.is_file()) else {
+            return Ok(Self::default())
+        };
+        let text = std::fs::read_to_string(&path)?;
+        let value: serde_json::Value = serde_json::from_str(&text)
+            .map_err(|err| anyhow!("cannot read {}: {}", path.display(), err))?;
+
+        let exclude = value["exclude"].as_array().into_iter().flatten()
+            .filter_map(|pattern| pattern.as_str().map(String::from))
+            .collect();
+        let imports = value["imports"].as_object().into_iter().flatten()
+            .filter_map(|(key, target)| Some((key.clone(), target.as_str()?.to_string())))
+            .collect();
+        Ok(Self {
+            specifier: ModuleSpecifier::from_file_path(&path).ok(),
+            exclude,
+            imports
+        })
+    }
+
+    /// The base of the `exclude` patterns.
+    fn base(&self) -> Option<PathBuf> {
+        self.specifier.as_ref()?.to_file_path().ok()?.parent().map(Path::to_path_buf)
+    }
+
+    /// Resolves the import `src` of the module `referrer`, bare specifiers
+    /// (e.g. `kotto` or `kotto/mod.ts`) through the import map.
+    fn resolve(&self, referrer: &ModuleSpecifier, src: &str) -> Option<ModuleSpecifier> {
+        let mapped = self.imports.get(src).map(String::from).or_else(|| {
+            // The longest prefix (e.g. `kotto/`) wins
+            self.imports.iter()
+                .filter(|(key, _)| key.ends_with('/') && src.starts_with(key.as_str()))
+                .max_by_key(|(key, _)| key.len())
+                .map(|(key, target)| format!("{}{}", target, &src[key.len()..]))
+        });
+        match (mapped, &self.specifier) {
+            (Some(target), Some(specifier)) => specifier.join(&target).ok(),
+            _ => referrer.join(src).ok()
+        }
+    }
+
+    /// Whether the import `src` is an entry of the import map named after
+    /// kotto (e.g. mapped to a local checkout of it).
+    fn is_kotto_entry(&self, src: &str) -> bool {
+        self.imports.contains_key(src)
+            && (src == KOTTO_IMPORT || src.strip_prefix(KOTTO_IMPORT).is_some_and(|rest| rest.starts_with('/')))
+    }
+}
+
+/// The modules in the directory `root` (whose paths relative to it match
+/// `glob`, if given), in the order of their paths.
+fn walk(root: &Path, excludes: &Excludes, glob: Option<&GlobMatcher>) -> Result<Vec<PathBuf>, AnyError> {
+    let excludes = excludes.clone();
+    let walker = WalkBuilder::new(root)
+        // Only the `.gitignore` files of the directories searched (and of
+        // their parents) are honored
+        .hidden(false)
+        .ignore(false)
+        .git_global(false)
+        .git_exclude(false)
+        .require_git(false)
+        .sort_by_file_path(|a, b| a.cmp(b))
+        .filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|file_type| file_type.is_dir());
+            entry.file_name() != ".git" && !excludes.is_excluded(entry.path(), is_dir)
+        })
+        .build();
+
+    let mut found = Vec::new();
+    for entry in walker {
+        let entry = entry?;
+        let path = entry.path();
+        let selected = entry.file_type().is_some_and(|file_type| file_type.is_file())
+            && is_module(path)
+            && glob.is_none_or(|glob| path.strip_prefix(root).is_ok_and(|relative| glob.is_match(relative)));
+        if selected {
+            found.push(path.to_path_buf());
+        }
+    }
+    Ok(found)
+}
+
+/// Whether the file `path` is a module to compile, rather than (e.g.) a
+/// prompts module or declarations written by kottoc.
+fn is_module(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false
+    };
+    let is_output = name.ends_with(&format!(".{}", PROMPTS_EXT)) || name.ends_with(".d.ts") || name.ends_with(".d.mts");
+    !is_output && path.extension().is_some_and(|ext| MODULE_EXTS.iter().any(|module_ext| ext == *module_ext))
+}
+
+/// Whether `specifier` is kotto's `mod.ts`, as served by kotto.land (e.g.
+/// `https://kotto.land/@0.1.0/mod.ts`).
+fn is_kotto_module(specifier: &ModuleSpecifier) -> bool {
+    specifier.host_str() == Some(KOTTO_HOST) && specifier.path().ends_with("/mod.ts")
+}
+
+/// Whether `module` is an agent: it imports kotto's `mod.ts` (directly, or
+/// through an entry of the import map of `config`) or exposes methods with
+/// `@use`.
+fn is_agent(module: &Module, config: &Config) -> bool {
+    let filtered_module = &module.filtered_module;
+    let uses = filtered_module.class_decls.iter()
+        .flat_map(|class_decl| class_decl.class_members.values())
+        .any(|class_member| matches!(class_member, ClassMember::Method(method) if method.used));
+    uses || filtered_module.imports.values().any(|import| {
+        config.is_kotto_entry(&import.src)
+            || config.resolve(&module.specifier, &import.src).is_some_and(|src| is_kotto_module(&src))
+    })
+}
+
+/// How the paths given on the command line are expanded.
+#[derive(Default)]
+pub struct InputOptions {
+    /// Glob patterns, relative to the current directory, of the paths not to
+    /// search
+    pub exclude: Vec<String>,
+    /// Only select the modules found in directories (or by patterns) that are
+    /// agents (see [`is_agent`])
+    pub agents: bool,
+}
+
+/// Expands the modules to compile given on the command line into their
+/// specifiers: URLs and paths to files are taken as they are, while
+/// directories are searched recursively for modules (`.ts`, `.tsx`, `.js` and
+/// `.mts`) and glob patterns (e.g. `agents/**/*.ts`) select the modules that
+/// they match. The paths excluded by `.gitignore` files, by the `exclude` of
+/// `deno.json` or by `options.exclude` are not searched.
+pub async fn expand(paths: &[String], options: &InputOptions) -> Result<Vec<ModuleSpecifier>, AnyError> {
+    let cwd = std::env::current_dir()?;
+    let config = Config::find(&cwd)?;
+    let mut excludes = Excludes::default();
+    if let Some(base) = config.base() {
+        excludes.add(&base, config.exclude.iter().map(String::as_str))?;
+    }
+    excludes.add(&cwd, options.exclude.iter().map(String::as_str))?;
+
+    let mut specifiers = Vec::new();
+    for path in paths {
+        if let Some(specifier) = url_of(path) {
+            match specifier.to_file_path() {
+                Ok(file_path) if file_path.is_dir() => {
+                    let dir = file_path.to_string_lossy().into_owned();
+                    specifiers.extend(search(&dir, &excludes, &config, options).await?);
+                }
+                _ => specifiers.push(specifier)
+            }
+            continue
+        }
+
+        if is_glob(path) || Path::new(path).is_dir() {
+            specifiers.extend(search(path, &excludes, &config, options).await?);
+        } else {
+            specifiers.push(specifier_of(&cwd.join(path))?);
+        }
+    }
+
+    // Modules given several times (e.g. also found in a directory) are only
+    // compiled once
+    let mut seen = std::collections::HashSet::new();
+    specifiers.retain(|specifier| seen.insert(specifier.clone()));
+    Ok(specifiers)
+}
+
+/// The URL `path`, if it is one rather than a path.
+fn url_of(path: &str) -> Option<ModuleSpecifier> {
+    // Drive letters are not schemes
+    ModuleSpecifier::parse(path).ok().filter(|specifier| specifier.scheme().len() > 1)
+}
+
+/// The module `path`: a URL, or a path relative to the current directory.
+pub fn module_specifier(path: &str) -> Result<ModuleSpecifier, AnyError> {
+    match url_of(path) {
+        Some(specifier) => Ok(specifier),
+        None => specifier_of(&std::env::current_dir()?.join(path))
+    }
+}
+
+fn specifier_of(path: &Path) -> Result<ModuleSpecifier, AnyError> {
+    ModuleSpecifier::from_file_path(normalize(path))
+        .map_err(|_| anyhow!("not a valid path: {}", path.display()))
+}
+
+/// `path`, without `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component)
+        }
+    }
+    normalized
+}
+
+/// The modules in the directory, or matching the glob pattern, `path`.
+async fn search(
+    path: &str,
+    excludes: &Excludes,
+    config: &Config,
+    options: &InputOptions
+) -> Result<Vec<ModuleSpecifier>, AnyError> {
+    let cwd = std::env::current_dir()?;
+
+    // Patterns are searched from the directory of their leading components
+    // without wildcards
+    let (root, glob) = if is_glob(path) {
+        let (root, pattern) = split_pattern(path);
+        (normalize(&cwd.join(root)), Some(glob_matcher(&pattern)?))
+    } else {
+        (normalize(&cwd.join(path)), None)
+    };
+    if !root.is_dir() {
+        return Err(anyhow!("no such directory: {}", root.display()))
+    }
+
+    let paths = walk(&root, excludes, glob.as_ref())?;
+
+    let mut specifiers = Vec::new();
+    let mut module_graph = ModuleGraph::default();
+    for path in paths {
+        let specifier = specifier_of(&path)?;
+        if options.agents {
+            let module = match graph::fetch_module_source(&specifier).await {
+                Ok(source) => module_graph.add_module(specifier.clone(), source).await,
+                Err(err) => Err(err)
+            };
+            match module {
+                Ok(module) if is_agent(&module, config) => {}
+                Ok(_) => continue,
+                Err(err) => {
+                    event!(Level::WARN, "skipping {}: {}", path.display(), err);
+                    continue
+                }
+            }
+        }
+        specifiers.push(specifier);
+    }
+
+    if specifiers.is_empty() {
+        return Err(anyhow!("no modules to compile in {}", path))
+    }
+    Ok(specifiers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_patterns() {
+        assert_eq!(split_pattern("agents/**/*.ts"), ("agents".to_string(), "**/*.ts".to_string()));
+        assert_eq!(split_pattern("a/b/*.ts"), ("a/b".to_string(), "*.ts".to_string()));
+        assert_eq!(split_pattern("*.ts"), ("".to_string(), "*.ts".to_string()));
+        assert_eq!(split_pattern("a/[bc]/d.ts"), ("a".to_string(), "[bc]/d.ts".to_string()));
+    }
+
+    #[test]
+    fn match_globs() {
+        let matches = |pattern: &str, path: &str| glob_matcher(pattern).unwrap().is_match(path);
+        assert!(matches("**/*.ts", "a.ts"));
+        assert!(matches("**/*.ts", "a/b/c.ts"));
+        assert!(matches("*.ts", "a.ts"));
+        assert!(!matches("*.ts", "a/b.ts"));
+        assert!(matches("a/**/b.ts", "a/b.ts"));
+        assert!(matches("a/**/b.ts", "a/x/y/b.ts"));
+        assert!(matches("?.ts", "a.ts"));
+        assert!(!matches("?.ts", "ab.ts"));
+        assert!(matches("[!a].ts", "b.ts"));
+        assert!(!matches("[!a].ts", "a.ts"));
+        assert!(glob_matcher("a/[b.ts").is_err());
+    }
+
+    #[test]
+    fn anchor_excludes() {
+        assert_eq!(anchored("dist"), "/dist");
+        assert_eq!(anchored("./dist/"), "/dist/");
+        assert_eq!(anchored("/dist"), "/dist");
+        assert_eq!(anchored("!dist/keep.ts"), "!/dist/keep.ts");
+    }
+
+    #[test]
+    fn exclude_paths() {
+        let base = Path::new("/project");
+        let mut excludes = Excludes::default();
+        excludes.add(base, ["dist", "out/*", "!out/keep.ts", "build/", "**/*.test.ts"]).unwrap();
+
+        assert!(excludes.is_excluded(&base.join("dist"), true));
+        assert!(!excludes.is_excluded(&base.join("src/dist"), true));
+        assert!(excludes.is_excluded(&base.join("out/a.ts"), false));
+        assert!(!excludes.is_excluded(&base.join("out/keep.ts"), false));
+        assert!(excludes.is_excluded(&base.join("build"), true));
+        assert!(!excludes.is_excluded(&base.join("build"), false));
+        assert!(excludes.is_excluded(&base.join("src/a.test.ts"), false));
+        assert!(!excludes.is_excluded(Path::new("/other/dist"), true));
+    }
+
+    #[test]
+    fn detect_kotto() {
+        let config = Config {
+            specifier: ModuleSpecifier::parse("file:///project/deno.json").ok(),
+            exclude: Vec::new(),
+            imports: HashMap::from([
+                ("kotto".to_string(), "../kotto/mod.ts".to_string()),
+                ("land/".to_string(), "https://kotto.land/".to_string()),
+                ("other".to_string(), "https://example.com/mod.ts".to_string()),
+            ])
+        };
+        let referrer = ModuleSpecifier::parse("file:///project/src/agent.ts").unwrap();
+        let is_kotto = |src: &str| config.is_kotto_entry(src)
+            || config.resolve(&referrer, src).is_some_and(|src| is_kotto_module(&src));
+
+        assert!(is_kotto("https://kotto.land/mod.ts"));
+        assert!(is_kotto("https://kotto.land/@0.1.0/mod.ts"));
+        assert!(is_kotto("kotto"));
+        assert!(is_kotto("land/mod.ts"));
+        assert!(!is_kotto("land/prompts.ts"));
+        assert!(!is_kotto("other"));
+        assert!(!is_kotto("../kotto/mod.ts"));
+        assert!(!is_kotto("https://example.com/kotto/mod.ts"));
+    }
+}
@@ -0,0 +1,176 @@
+use std::collections::HashSet;
+
+use deno_ast::ModuleSpecifier;
+use serde_json::json;
+
+use crate::{AnyError, anyhow};
+use crate::graph::ModuleGraph;
+use crate::prompts::{Prompt, Prompts};
+use crate::tasks::{self, CompileOptions};
+
+/// A rough estimate of the number of tokens `text` amounts to, at about four
+/// characters a token.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// `id` without the syntax contexts of its parts (e.g. `Agent.add` for
+/// `Agent#2.add#0`).
+pub fn plain_id(id: &str) -> String {
+    id.split('.')
+        .map(|part| part.split_once('#').map(|(name, _)| name).unwrap_or(part))
+        .collect::<Vec<&str>>()
+        .join(".")
+}
+
+pub fn ast_ty_name(prompt: &Prompt) -> String {
+    match serde_json::to_value(prompt.ast_ty) {
+        Ok(serde_json::Value::String(ast_ty)) => ast_ty,
+        _ => String::new()
+    }
+}
+
+/// Finds the prompt `id`, given as is or without syntax contexts.
+fn find<'p>(prompts: &'p Prompts, id: &str) -> Result<&'p Prompt, AnyError> {
+    if let Some(prompt) = prompts.0.iter().find(|prompt| &*prompt.id == id) {
+        return Ok(prompt)
+    }
+
+    let matching: Vec<&Prompt> = prompts.0.iter()
+        .filter(|prompt| plain_id(&prompt.id) == id)
+        .collect();
+    match matching.as_slice() {
+        [prompt] => Ok(prompt),
+        [] => Err(anyhow!("no prompt with the id '{}'", id)),
+        _ => Err(anyhow!(
+            "several prompts with the id '{}': {}",
+            id,
+            matching.iter().map(|prompt| &*prompt.id).collect::<Vec<&str>>().join(", ")
+        ))
+    }
+}
+
+/// Finds the prompt that the context id `context_id` (e.g.
+/// `type_alias_decl.Item#2`) refers to.
+pub fn find_context<'p>(prompts: &'p Prompts, context_id: &str) -> Option<&'p Prompt> {
+    let (ast_ty, id) = context_id.split_once('.')?;
+    prompts.0.iter().find(|prompt| &*prompt.id == id && ast_ty_name(prompt) == ast_ty)
+}
+
+/// The prompts in the closure of the context of `prompt`, each once, in the
+/// order they are first referred to.
+pub fn context_closure<'p>(prompts: &'p Prompts, prompt: &Prompt) -> Vec<&'p Prompt> {
+    let mut closure: Vec<&Prompt> = Vec::new();
+    let mut seen = HashSet::new();
+    let mut to_visit: Vec<&str> = prompt.context.iter().rev().map(|id| &**id).collect();
+    while let Some(context_id) = to_visit.pop() {
+        if !seen.insert(context_id) {
+            continue
+        }
+        if let Some(referenced) = find_context(prompts, context_id) {
+            closure.push(referenced);
+            to_visit.extend(referenced.context.iter().rev().map(|id| &**id));
+        }
+    }
+    closure
+}
+
+/// The prompt `prompt` and the closure of its context, as JSON. Prompts that
+/// were already seen are not expanded again.
+fn node(prompts: &Prompts, prompt: &Prompt, seen: &mut HashSet<String>) -> serde_json::Value {
+    let mut context = Vec::new();
+    for context_id in &prompt.context {
+        let Some(referenced) = find_context(prompts, context_id) else {
+            context.push(json!({ "id": &**context_id, "missing": true }));
+            continue
+        };
+        if seen.insert(context_id.to_string()) {
+            context.push(node(prompts, referenced, seen));
+        } else {
+            context.push(json!({ "id": &*referenced.id, "ast_ty": ast_ty_name(referenced), "repeated": true }));
+        }
+    }
+
+    json!({
+        "id": &*prompt.id,
+        "ast_ty": ast_ty_name(prompt),
+        "fmt": &prompt.fmt.0,
+        "tokens": estimate_tokens(&prompt.fmt.0),
+        "source": &prompt.source,
+        "context": context
+    })
+}
+
+fn total_tokens(node: &serde_json::Value) -> u64 {
+    let own = node["tokens"].as_u64().unwrap_or_default();
+    let context = node["context"].as_array().into_iter().flatten().map(total_tokens).sum::<u64>();
+    own + context
+}
+
+fn print_tree(node: &serde_json::Value, prefix: &str) {
+    let Some(context) = node["context"].as_array() else {
+        return
+    };
+    for (i, child) in context.iter().enumerate() {
+        let last = i + 1 == context.len();
+        println!("{}{} {}", prefix, if last { "└─" } else { "├─" }, describe(child));
+        print_tree(child, &format!("{}{}", prefix, if last { "   " } else { "│  " }));
+    }
+}
+
+fn describe(node: &serde_json::Value) -> String {
+    let id = node["id"].as_str().unwrap_or_default();
+    if node["missing"].as_bool() == Some(true) {
+        return format!("{} (missing)", id)
+    }
+
+    let mut description = format!("{} {}", node["ast_ty"].as_str().unwrap_or_default(), id);
+    if node["repeated"].as_bool() == Some(true) {
+        description.push_str(" (see above)");
+        return description
+    }
+    description.push_str(&format!(" ~{} tokens", node["tokens"]));
+    if let (Some(specifier), Some(line)) = (node["source"]["specifier"].as_str(), node["source"]["line"].as_u64()) {
+        description.push_str(&format!(" at {}:{}", specifier, line));
+    }
+    description
+}
+
+/// Prints the prompt `id` of the module `specifier`, along with the closure
+/// of its context, as a tree or (if `as_json`) as JSON.
+pub async fn inspect(
+    specifier: &ModuleSpecifier,
+    id: &str,
+    as_json: bool,
+    options: &CompileOptions
+) -> Result<(), AnyError> {
+    let mut module_graph = ModuleGraph::default();
+    let (_, prompts, _) = tasks::compile_module(&mut module_graph, specifier, options).await?;
+
+    let prompt = find(&prompts, id)?;
+    let mut seen = HashSet::from([format!("{}.{}", ast_ty_name(prompt), &*prompt.id)]);
+    let mut root = node(&prompts, prompt, &mut seen);
+    root["total_tokens"] = json!(total_tokens(&root));
+
+    if as_json {
+        println!("{}", serde_json::to_string_pretty(&root)?);
+        return Ok(())
+    }
+
+    println!("{}", describe(&root));
+    println!();
+    for line in prompt.fmt.0.lines() {
+        println!("    {}", line);
+    }
+    println!();
+    if prompt.context.is_empty() {
+        println!("no context");
+    } else {
+        println!("context:");
+        print_tree(&root, "");
+    }
+    println!();
+    println!("~{} tokens with the context", root["total_tokens"]);
+
+    Ok(())
+}
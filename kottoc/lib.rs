@@ -0,0 +1,42 @@
+pub use anyhow::Error as AnyError;
+pub use anyhow::anyhow;
+
+pub use deno_ast::swc::ast;
+pub use deno_ast::swc::visit;
+pub use deno_ast::swc::codegen;
+pub use deno_ast::swc::common;
+
+pub mod prompts;
+pub mod tasks;
+pub mod filter;
+pub mod emit;
+pub mod graph;
+pub mod builtins;
+pub mod eval;
+pub mod validate;
+pub mod coerce;
+pub mod signature;
+pub mod recursion;
+pub mod inline;
+pub mod format;
+pub mod inspect;
+
+// The API exported by the WebAssembly build (also tested on the host)
+#[cfg(any(target_arch = "wasm32", test))]
+pub mod wasm;
+
+// Modules that need the filesystem, the network or processes
+#[cfg(not(target_arch = "wasm32"))]
+pub mod inputs;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod diff;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod rpc;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lsp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod serve;
+
+pub trait CanPush<T> {
+    fn push(&mut self, item: T);
+}
@@ -0,0 +1,599 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::time::Duration;
+
+use deno_ast::{ModuleSpecifier, SourcePos, SourceTextInfo};
+use serde_json::{json, Value};
+use tokio::io::BufReader;
+use tokio::time::Instant;
+
+use crate::{ast, filter, inspect, rpc};
+use crate::AnyError;
+use crate::common::{BytePos, Span, Spanned};
+use crate::filter::{ClassMember, TypeParamVisitor, TypeRef, TypeRefVisitor};
+use crate::graph::{Module, ModuleGraph};
+use crate::prompts::{Prompt, PromptAstType, Prompts};
+use crate::tasks::{self, CompileOptions};
+use crate::visit::Visit;
+
+/// The severities of diagnostics.
+const ERROR: u8 = 1;
+const WARNING: u8 = 2;
+
+/// How long edits have to settle before the documents they affect are
+/// compiled again, so that typing does not compile on every keystroke.
+const DIAGNOSTICS_DELAY: Duration = Duration::from_millis(250);
+
+/// The LSP position (a line, and a column in UTF-16 code units) of `pos`.
+fn position_of(text_info: &SourceTextInfo, pos: BytePos) -> Value {
+    let range = text_info.range();
+    let pos = pos.clamp(range.start.as_byte_pos(), range.end.as_byte_pos());
+    let pos = SourcePos::unsafely_from_byte_pos(pos);
+
+    let line = text_info.line_index(pos);
+    let line_text = text_info.line_text(line);
+    let column = (pos - text_info.line_start(line)).min(line_text.len());
+    json!({
+        "line": line,
+        "character": line_text[..column].encode_utf16().count()
+    })
+}
+
+fn range_of(text_info: &SourceTextInfo, span: Span) -> Value {
+    json!({
+        "start": position_of(text_info, span.lo),
+        "end": position_of(text_info, span.hi)
+    })
+}
+
+/// The position in the text of the LSP position `position`.
+fn pos_of(text_info: &SourceTextInfo, position: &Value) -> Option<BytePos> {
+    let line = position["line"].as_u64()? as usize;
+    let character = position["character"].as_u64()? as usize;
+    if line >= text_info.lines_count() {
+        return None
+    }
+
+    let line_text = text_info.line_text(line);
+    let mut units = 0;
+    let mut column = line_text.len();
+    for (i, c) in line_text.char_indices() {
+        if units >= character {
+            column = i;
+            break
+        }
+        units += c.len_utf16();
+    }
+    Some((text_info.line_start(line) + column).as_byte_pos())
+}
+
+fn diagnostic(text_info: &SourceTextInfo, span: Span, severity: u8, message: String) -> Value {
+    json!({
+        "range": range_of(text_info, span),
+        "severity": severity,
+        "source": "kottoc",
+        "message": message
+    })
+}
+
+/// The diagnostic of an error that kept `text` from being compiled, at the
+/// syntax error it is about (if it is one).
+fn error_diagnostic(text: &str, err: &AnyError) -> Value {
+    let text_info = SourceTextInfo::from_string(text.to_string());
+    match err.downcast_ref::<deno_ast::Diagnostic>() {
+        Some(syntax_error) => {
+            let span = Span::new(syntax_error.range.start.as_byte_pos(), syntax_error.range.end.as_byte_pos(), Default::default());
+            diagnostic(&text_info, span, ERROR, syntax_error.message().to_string())
+        }
+        None => {
+            let start = text_info.range().start.as_byte_pos();
+            diagnostic(&text_info, Span::new(start, start, Default::default()), ERROR, err.to_string())
+        }
+    }
+}
+
+/// Whether the name `type_ref` refers to is one that the compiler handles
+/// without a declaration.
+fn is_native(type_ref: &TypeRef) -> bool {
+    matches!(type_ref.id(), Some((sym, _)) if &*sym == "Array" || &*sym == "ReadonlyArray")
+}
+
+/// Reports the names of members that are not supported, and the types that
+/// the declarations of `module` refer to but which do not resolve (whose
+/// declarations the model will not see).
+async fn diagnostics(module_graph: &mut ModuleGraph, module: &Rc<Module>) -> Vec<Value> {
+    let filtered_module = &module.filtered_module;
+    let mut diagnostics = Vec::new();
+    let mut type_refs = Vec::new();
+
+    for class_decl in &filtered_module.class_decls {
+        for (prop_name, class_member) in class_decl.members_in_order() {
+            if prop_name.as_ident().is_none() {
+                diagnostics.push(diagnostic(
+                    &module.text_info,
+                    prop_name.span(),
+                    WARNING,
+                    format!(
+                        "member {} is left out of the prompts: only members named by identifiers are supported",
+                        filter::member_name_text(prop_name)
+                    )
+                ));
+                continue
+            }
+            match class_member {
+                ClassMember::Method(class_method) => type_refs.extend(class_method.type_refs.iter().cloned()),
+                ClassMember::Prop(class_prop) => TypeRefVisitor(&mut type_refs).visit_class_prop(class_prop)
+            }
+        }
+    }
+    for fn_decl in &filtered_module.fn_decls {
+        type_refs.extend(fn_decl.type_refs.iter().cloned());
+    }
+    for type_alias_decl in filtered_module.type_alias_decls.values() {
+        let mut type_params = HashSet::new();
+        TypeParamVisitor(&mut type_params).visit_ts_type_alias_decl(type_alias_decl);
+        let mut decl_refs = Vec::new();
+        TypeRefVisitor(&mut decl_refs).visit_ts_type_alias_decl(type_alias_decl);
+        type_refs.extend(decl_refs.into_iter().filter(|type_ref| !type_ref.is_bound_by(&type_params)));
+    }
+    for interface_decl in filtered_module.interface_decls.values() {
+        let mut type_params = HashSet::new();
+        TypeParamVisitor(&mut type_params).visit_ts_interface_decl(interface_decl);
+        let mut decl_refs = Vec::new();
+        TypeRefVisitor(&mut decl_refs).visit_ts_interface_decl(interface_decl);
+        type_refs.extend(decl_refs.into_iter().filter(|type_ref| !type_ref.is_bound_by(&type_params)));
+    }
+
+    for type_ref in type_refs.iter().filter(|type_ref| !is_native(type_ref)) {
+        if module_graph.resolve(module, &type_ref.name()).await.is_none() {
+            diagnostics.push(diagnostic(
+                &module.text_info,
+                type_ref.type_name.span(),
+                WARNING,
+                format!("cannot resolve '{}': the model will not see its declaration", entity_name_text(&type_ref.type_name))
+            ));
+        }
+    }
+    diagnostics
+}
+
+fn entity_name_text(entity_name: &ast::TsEntityName) -> String {
+    match entity_name {
+        ast::TsEntityName::Ident(ident) => ident.sym.to_string(),
+        ast::TsEntityName::TsQualifiedName(qualified_name) => {
+            format!("{}.{}", entity_name_text(&qualified_name.left), qualified_name.right.sym)
+        }
+    }
+}
+
+/// The prompts of an open document, as compiled the last time it changed.
+struct Analysis {
+    module: Rc<Module>,
+    prompts: Prompts,
+}
+
+impl Analysis {
+    /// The prompt of one of the types `ast_tys` declared at `pos`, and
+    /// under the class `class_name` if there are several (i.e. members that
+    /// subclasses inherit).
+    fn prompt_at(&self, pos: BytePos, ast_tys: &[PromptAstType], class_name: Option<&str>) -> Option<&Prompt> {
+        let line = self.module.source_at(pos)?.line;
+        let mut candidates = self.prompts.0.iter().filter(|prompt| {
+            prompt.ast_ty.as_ref().is_some_and(|ast_ty| ast_tys.contains(ast_ty))
+                && prompt.source.as_ref().is_some_and(|source| {
+                    source.specifier == self.module.specifier.as_str() && source.line == line
+                })
+        });
+        let first = candidates.next()?;
+        let Some(class_name) = class_name else {
+            return Some(first)
+        };
+        std::iter::once(first)
+            .chain(candidates)
+            .find(|prompt| inspect::plain_id(&prompt.id).rsplit('.').nth(1) == Some(class_name))
+            .or(Some(first))
+    }
+
+    /// What the model receives for the member or function at `pos`: its prompt
+    /// and the declarations of its context.
+    fn hover(&self, pos: BytePos) -> Option<Value> {
+        let filtered_module = &self.module.filtered_module;
+        let mut found = None;
+        for class_decl in &filtered_module.class_decls {
+            for (_, class_member) in class_decl.members_in_order() {
+                let span = class_member.span();
+                if span.lo <= pos && pos < span.hi {
+                    let class_name = class_decl.class_decl.ident.sym.to_string();
+                    found = Some((span, Some(class_name)));
+                }
+            }
+        }
+        for fn_decl in &filtered_module.fn_decls {
+            let span = fn_decl.function.span;
+            if span.lo <= pos && pos < span.hi {
+                found = Some((span, None));
+            }
+        }
+        let (span, class_name) = found?;
+
+        let ast_tys = [PromptAstType::MethodDecl, PromptAstType::ClassProp, PromptAstType::FnDecl];
+        let prompt = self.prompt_at(span.lo, &ast_tys, class_name.as_deref())?;
+        let context: Vec<&Prompt> = inspect::context_closure(&self.prompts, prompt)
+            .into_iter()
+            .filter(|referenced| !std::ptr::eq(*referenced, prompt))
+            .collect();
+        let tokens = inspect::estimate_tokens(&prompt.fmt.0)
+            + context.iter().map(|referenced| inspect::estimate_tokens(&referenced.fmt.0)).sum::<usize>();
+
+        let mut value = format!("`{}` (~{} tokens with its context)\n\n```ts\n{}\n```", prompt.id.0, tokens, prompt.fmt.0);
+        if !context.is_empty() {
+            value.push_str("\n\nContext:\n\n```ts\n");
+            value.push_str(&context.iter().map(|referenced| referenced.fmt.0.as_str()).collect::<Vec<&str>>().join("\n\n"));
+            value.push_str("\n```");
+        }
+
+        Some(json!({
+            "contents": { "kind": "markdown", "value": value },
+            "range": range_of(&self.module.text_info, span)
+        }))
+    }
+
+    /// A lens on each class, with the number of tokens of its prompts: the
+    /// class, its members, and the closure of their context.
+    fn code_lenses(&self) -> Vec<Value> {
+        let mut lenses = Vec::new();
+        for class_decl in &self.module.filtered_module.class_decls {
+            let ident = &class_decl.class_decl.ident;
+            let Some(class_prompt) = self.prompt_at(class_decl.class_decl.class.span.lo, &[PromptAstType::ClassDecl], None) else {
+                continue
+            };
+
+            let scope = format!("{}.", class_prompt.id.0);
+            let mut included: Vec<&Prompt> = vec![class_prompt];
+            included.extend(self.prompts.0.iter().filter(|prompt| prompt.id.starts_with(&scope)));
+            for prompt in included.clone() {
+                included.extend(inspect::context_closure(&self.prompts, prompt));
+            }
+            let mut seen = HashSet::new();
+            included.retain(|prompt| seen.insert(*prompt as *const Prompt));
+
+            let tokens: usize = included.iter().map(|prompt| inspect::estimate_tokens(&prompt.fmt.0)).sum();
+            lenses.push(json!({
+                "range": range_of(&self.module.text_info, ident.span),
+                "command": {
+                    "title": format!("~{} tokens in {} prompts", tokens, included.len()),
+                    "command": ""
+                }
+            }));
+        }
+        lenses
+    }
+}
+
+struct Document {
+    version: Value,
+    text: String,
+    analysis: Option<Analysis>,
+}
+
+/// A language server showing, in the editor, the prompts that modules compile
+/// to.
+struct Server {
+    options: CompileOptions,
+    documents: HashMap<ModuleSpecifier, Document>,
+    /// The modules loaded so far (open documents as they are in the editor),
+    /// kept across requests until they or the modules they import change
+    module_graph: ModuleGraph,
+    /// The open documents to compile again, once edits settle
+    pending: HashSet<ModuleSpecifier>,
+    /// When to compile the pending documents
+    deadline: Option<Instant>,
+    shutdown: bool,
+}
+
+impl Server {
+    /// Takes the change of the document `specifier` (its text, or whether it
+    /// is open) into account: the module and those importing it are loaded
+    /// again, and the open ones among them compiled again once edits settle.
+    fn invalidate(&mut self, specifier: &ModuleSpecifier) {
+        let mut changed = self.module_graph.invalidate(specifier);
+        changed.insert(specifier.clone());
+        self.pending.extend(changed.into_iter().filter(|changed| self.documents.contains_key(changed)));
+        self.deadline = Some(Instant::now() + DIAGNOSTICS_DELAY);
+    }
+
+    /// Compiles the open document `specifier` (and the modules it imports,
+    /// open ones as they are in the editor), returning its diagnostics.
+    async fn analyze(&mut self, specifier: &ModuleSpecifier) -> Vec<Value> {
+        for (other, document) in &self.documents {
+            if other != specifier && self.module_graph.get(other).is_none() {
+                // Documents that do not parse are reported on their own
+                let _ = self.module_graph.add_module(other.clone(), document.text.clone()).await;
+            }
+        }
+
+        let Some(document) = self.documents.get_mut(specifier) else {
+            return Vec::new()
+        };
+        document.analysis = None;
+
+        let module = match self.module_graph.get(specifier) {
+            Some(module) => module.clone(),
+            None => match self.module_graph.add_module(specifier.clone(), document.text.clone()).await {
+                Ok(module) => module,
+                Err(err) => return vec![error_diagnostic(&document.text, &err)]
+            }
+        };
+        let mut diagnostics = diagnostics(&mut self.module_graph, &module).await;
+        match tasks::compile_module(&mut self.module_graph, specifier, &self.options).await {
+            Ok((module, prompts, _)) => document.analysis = Some(Analysis { module, prompts }),
+            Err(err) => diagnostics.push(error_diagnostic(&document.text, &err))
+        }
+        diagnostics
+    }
+
+    /// Compiles the pending documents again, and publishes their diagnostics.
+    async fn analyze_pending<W>(&mut self, writer: &mut W) -> Result<(), AnyError>
+    where
+        W: tokio::io::AsyncWrite + Unpin
+    {
+        self.deadline = None;
+        let mut specifiers: Vec<ModuleSpecifier> = self.pending.drain().collect();
+        specifiers.sort();
+        for specifier in specifiers {
+            let diagnostics = self.analyze(&specifier).await;
+            let version = self.documents.get(&specifier).map(|document| document.version.clone());
+            rpc::write_message(writer, &rpc::notification("textDocument/publishDiagnostics", json!({
+                "uri": specifier.as_str(),
+                "version": version,
+                "diagnostics": diagnostics
+            }))).await?;
+        }
+        Ok(())
+    }
+
+    fn analysis(&self, params: &Value) -> Option<&Analysis> {
+        let specifier = ModuleSpecifier::parse(params["textDocument"]["uri"].as_str()?).ok()?;
+        self.documents.get(&specifier)?.analysis.as_ref()
+    }
+
+    /// Handles the request or notification `method`, returning the result of
+    /// requests.
+    async fn handle<W>(&mut self, method: &str, params: &Value, writer: &mut W) -> Result<Option<Value>, AnyError>
+    where
+        W: tokio::io::AsyncWrite + Unpin
+    {
+        let uri = || ModuleSpecifier::parse(params["textDocument"]["uri"].as_str().unwrap_or_default());
+        match method {
+            "initialize" => Ok(Some(json!({
+                "capabilities": {
+                    // Full documents are sent on every change
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "codeLensProvider": { "resolveProvider": false }
+                },
+                "serverInfo": { "name": "kottoc", "version": env!("CARGO_PKG_VERSION") }
+            }))),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Some(Value::Null))
+            }
+            "textDocument/didOpen" => {
+                let text_document = &params["textDocument"];
+                self.documents.insert(uri()?, Document {
+                    version: text_document["version"].clone(),
+                    text: text_document["text"].as_str().unwrap_or_default().to_string(),
+                    analysis: None
+                });
+                self.invalidate(&uri()?);
+                Ok(None)
+            }
+            "textDocument/didChange" => {
+                let changes = params["contentChanges"].as_array();
+                if let (Some(document), Some(text)) = (
+                    self.documents.get_mut(&uri()?),
+                    changes.and_then(|changes| changes.last()).and_then(|change| change["text"].as_str())
+                ) {
+                    document.version = params["textDocument"]["version"].clone();
+                    document.text = text.to_string();
+                }
+                self.invalidate(&uri()?);
+                Ok(None)
+            }
+            "textDocument/didClose" => {
+                let specifier = uri()?;
+                self.documents.remove(&specifier);
+                // Modules importing it see it as it is on disk again
+                self.invalidate(&specifier);
+                rpc::write_message(writer, &rpc::notification("textDocument/publishDiagnostics", json!({
+                    "uri": specifier.as_str(),
+                    "diagnostics": []
+                }))).await?;
+                Ok(None)
+            }
+            "textDocument/hover" => {
+                // Answered from the documents as they are now
+                self.analyze_pending(writer).await?;
+                let hover = self.analysis(params).and_then(|analysis| {
+                    let pos = pos_of(&analysis.module.text_info, &params["position"])?;
+                    analysis.hover(pos)
+                });
+                Ok(Some(hover.unwrap_or(Value::Null)))
+            }
+            "textDocument/codeLens" => {
+                self.analyze_pending(writer).await?;
+                let lenses = self.analysis(params).map(Analysis::code_lenses).unwrap_or_default();
+                Ok(Some(json!(lenses)))
+            }
+            _ => Ok(None)
+        }
+    }
+}
+
+/// Runs a language server over stdin and stdout, until the client exits.
+pub async fn serve(options: CompileOptions) -> Result<(), AnyError> {
+    let mut writer = tokio::io::stdout();
+    let mut server = Server {
+        options,
+        documents: HashMap::new(),
+        module_graph: ModuleGraph::default(),
+        pending: HashSet::new(),
+        deadline: None,
+        shutdown: false
+    };
+
+    // Messages are read on a task of their own, as reading one is not to be
+    // interrupted when the pending documents are due
+    let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        let mut reader = BufReader::new(tokio::io::stdin());
+        loop {
+            let message = rpc::read_message(&mut reader).await;
+            // Reading goes on after malformed messages
+            let last = match &message {
+                Ok(message) => message.is_none(),
+                Err(err) => err.is::<std::io::Error>()
+            };
+            if sender.send(message).is_err() || last {
+                break
+            }
+        }
+    });
+
+    loop {
+        let received = match server.deadline {
+            Some(deadline) => tokio::select! {
+                received = receiver.recv() => received,
+                _ = tokio::time::sleep_until(deadline) => {
+                    server.analyze_pending(&mut writer).await?;
+                    continue
+                }
+            },
+            None => receiver.recv().await
+        };
+        let message = match received {
+            Some(Ok(Some(message))) => message,
+            Some(Err(err)) => {
+                rpc::write_message(&mut writer, &rpc::malformed_response(err)?).await?;
+                continue
+            }
+            _ => break
+        };
+
+        let method = message["method"].as_str().unwrap_or_default();
+        if method == "exit" {
+            break
+        }
+
+        let id = message.get("id").cloned();
+        let result = server.handle(method, &message["params"], &mut writer).await;
+        let Some(id) = id else {
+            // Notifications get no response, even when they fail
+            continue
+        };
+        let response = match result {
+            Ok(Some(result)) => rpc::response(id, result),
+            Ok(None) => rpc::error_response(id, rpc::METHOD_NOT_FOUND, format!("unknown method: {}", method)),
+            Err(err) => rpc::error_response(id, rpc::INTERNAL_ERROR, err)
+        };
+        rpc::write_message(&mut writer, &response).await?;
+    }
+
+    if !server.shutdown {
+        return Err(crate::anyhow!("the client exited without shutting the server down"))
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> Server {
+        Server {
+            options: CompileOptions::default(),
+            documents: HashMap::new(),
+            module_graph: ModuleGraph::default(),
+            pending: HashSet::new(),
+            deadline: None,
+            shutdown: false
+        }
+    }
+
+    /// The diagnostics published in `output`, by document.
+    async fn published(output: &mut Vec<u8>) -> Vec<(String, Vec<Value>)> {
+        let mut reader = output.as_slice();
+        let mut published = Vec::new();
+        while let Some(message) = rpc::read_message(&mut reader).await.unwrap() {
+            assert_eq!(message["method"], "textDocument/publishDiagnostics");
+            let diagnostics = message["params"]["diagnostics"].as_array().unwrap().clone();
+            published.push((message["params"]["uri"].as_str().unwrap().to_string(), diagnostics));
+        }
+        output.clear();
+        published
+    }
+
+    fn fmt_of<'a>(server: &'a Server, specifier: &ModuleSpecifier, id: &str) -> Option<&'a str> {
+        let analysis = server.documents.get(specifier)?.analysis.as_ref()?;
+        analysis.prompts.0.iter().find(|prompt| &*prompt.id == id).map(|prompt| &*prompt.fmt.0)
+    }
+
+    #[tokio::test]
+    async fn publish_diagnostics_once_edits_settle() {
+        let dir = std::env::temp_dir().join(format!("kottoc-lsp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.ts"), "export interface A { x: number }\n").unwrap();
+        let b_text = "import { A } from \"./a.ts\";\nexport function f(a: A): void {}\n";
+        std::fs::write(dir.join("b.ts"), b_text).unwrap();
+        let a = ModuleSpecifier::from_file_path(dir.join("a.ts")).unwrap();
+        let b = ModuleSpecifier::from_file_path(dir.join("b.ts")).unwrap();
+
+        let mut server = server();
+        let mut output = Vec::new();
+        let open = |specifier: &ModuleSpecifier, text: &str| json!({
+            "textDocument": { "uri": specifier.as_str(), "version": 1, "text": text }
+        });
+        let change = |specifier: &ModuleSpecifier, version: u32, text: &str| json!({
+            "textDocument": { "uri": specifier.as_str(), "version": version },
+            "contentChanges": [{ "text": text }]
+        });
+
+        server.handle("textDocument/didOpen", &open(&b, b_text), &mut output).await.unwrap();
+        assert!(output.is_empty() && server.deadline.is_some());
+        server.analyze_pending(&mut output).await.unwrap();
+        assert_eq!(published(&mut output).await, [(b.to_string(), Vec::new())]);
+        assert_eq!(fmt_of(&server, &b, "A#2"), Some("interface A {\n    x: number;\n}"));
+
+        // Changes to an open module are only compiled once they settle, along
+        // with the open modules importing it
+        server.handle("textDocument/didOpen", &open(&a, "export interface A { x: number }\n"), &mut output).await.unwrap();
+        for version in 2..5 {
+            let text = format!("export interface A {{ x: number; v{}: }}\n", version);
+            server.handle("textDocument/didChange", &change(&a, version, &text), &mut output).await.unwrap();
+        }
+        assert!(output.is_empty());
+        assert_eq!(server.pending, HashSet::from([a.clone(), b.clone()]));
+        server.analyze_pending(&mut output).await.unwrap();
+        let diagnostics = published(&mut output).await;
+        assert_eq!(diagnostics.iter().map(|(uri, _)| uri.as_str()).collect::<Vec<_>>(), [a.as_str(), b.as_str()]);
+        assert_eq!(diagnostics[0].1[0]["severity"], ERROR);
+        assert!(server.pending.is_empty() && server.deadline.is_none());
+
+        server.handle("textDocument/didChange", &change(&a, 5, "export interface A { y: number }\n"), &mut output).await.unwrap();
+        server.analyze_pending(&mut output).await.unwrap();
+        assert_eq!(published(&mut output).await, [(a.to_string(), Vec::new()), (b.to_string(), Vec::new())]);
+        assert_eq!(fmt_of(&server, &b, "A#2"), Some("interface A {\n    y: number;\n}"));
+
+        // Once closed, modules importing it see it as it is on disk, as saved
+        // since it was first loaded
+        std::fs::write(dir.join("a.ts"), "export interface A { z: string }\n").unwrap();
+        server.handle("textDocument/didClose", &json!({ "textDocument": { "uri": a.as_str() } }), &mut output).await.unwrap();
+        assert_eq!(published(&mut output).await, [(a.to_string(), Vec::new())]);
+        assert_eq!(server.pending, HashSet::from([b.clone()]));
+        server.analyze_pending(&mut output).await.unwrap();
+        assert_eq!(published(&mut output).await, [(b.to_string(), Vec::new())]);
+        assert_eq!(fmt_of(&server, &b, "A#2"), Some("interface A {\n    z: string;\n}"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,27 +1,76 @@
 use clap::Parser;
 use anyhow::Error as AnyError;
-use anyhow::anyhow;
 
-use deno_ast::swc::ast;
-use deno_ast::swc::visit;
-use deno_ast::swc::codegen;
-use deno_ast::swc::common;
+use kottoc::{diff, format, inputs, inspect, lsp, serve, tasks};
 
 mod args;
 mod util;
-mod prompts;
-mod tasks;
-mod filter;
-mod emit;
 
-use args::Flags;
+use args::{Command, Flags, OptionFlags};
 
-pub trait CanPush<T> {
-    fn push(&mut self, item: T);
+/// The exit code of `--check` when files are out of date, and of `diff` when
+/// the builds differ.
+const EXIT_DIFFERENT: i32 = 1;
+/// The exit code of any error, so that scripts can tell it from the above.
+const EXIT_ERROR: i32 = 2;
+
+fn compile_options(options: OptionFlags) -> tasks::CompileOptions {
+    tasks::CompileOptions {
+        expand_generics: options.expand_generics,
+        tree_shake: options.tree_shake,
+        inline_aliases: options.inline_aliases,
+        ..Default::default()
+    }
 }
 
 async fn run_subcommand(flags: Flags) -> Result<i32, AnyError> {
-    tasks::compile_prompts_for_specifiers(&flags.paths, flags.output).await?;
+    match flags.command {
+        Some(Command::Migrate { paths }) => {
+            for path in &paths {
+                let format = format::migrate(path)?;
+                if format < format::FORMAT_VERSION {
+                    eprintln!("migrated {} from format {} to {}", path.display(), format, format::FORMAT_VERSION);
+                }
+            }
+        }
+        Some(Command::Inspect { path, id, json, options }) => {
+            inspect::inspect(&path, &id, json, &compile_options(options)).await?;
+        }
+        Some(Command::Lsp { options }) => {
+            lsp::serve(compile_options(options)).await?;
+        }
+        Some(Command::Serve { stdio: _ }) => {
+            serve::serve().await?;
+        }
+        Some(Command::Diff { old, new, rev, json, options }) => {
+            if diff::diff(&old, new.as_ref(), &rev, json, &compile_options(options)).await? {
+                return Ok(EXIT_DIFFERENT)
+            }
+        }
+        None => {
+            let compile = flags.compile;
+            let options = tasks::CompileOptions {
+                bundle: compile.bundle,
+                root: compile.root,
+                check: compile.check,
+                jobs: compile.jobs,
+                ..compile_options(compile.options)
+            };
+            let inputs = inputs::InputOptions {
+                exclude: compile.exclude,
+                agents: compile.agents,
+            };
+            let specifiers = inputs::expand(&compile.paths, &inputs).await?;
+            let stale = tasks::compile_prompts_for_specifiers(&specifiers, compile.output, &options).await?;
+            if !stale.is_empty() {
+                for path in &stale {
+                    println!("{}", path.display());
+                }
+                eprintln!("{} file(s) out of date: run kottoc without --check to update them", stale.len());
+                return Ok(EXIT_DIFFERENT)
+            }
+        }
+    }
     Ok(0)
 }
 
@@ -29,10 +78,8 @@ fn unwrap_or_exit<T>(result: Result<T, AnyError>) -> T {
     match result {
         Ok(value) => value,
         Err(err) => {
-            // TODO
-            let error_code = 1;
             eprintln!("{}", err);
-            std::process::exit(error_code)
+            std::process::exit(EXIT_ERROR)
         }
     }
 }
@@ -46,4 +93,41 @@ async fn main() {
     let exit_code = unwrap_or_exit(run_subcommand(flags).await);
 
     std::process::exit(exit_code)
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_module_paths() {
+        let flags = Flags::try_parse_from(["kottoc", "diff", "agent.ts", "--rev", "HEAD"]).unwrap();
+        let Some(Command::Diff { old, .. }) = flags.command else {
+            panic!("not a diff")
+        };
+        assert_eq!(old.to_file_path().unwrap(), std::env::current_dir().unwrap().join("agent.ts"));
+
+        let flags = Flags::try_parse_from(["kottoc", "inspect", "https://kotto.land/agent.ts", "Agent"]).unwrap();
+        let Some(Command::Inspect { path, .. }) = flags.command else {
+            panic!("not an inspect")
+        };
+        assert_eq!(path.as_str(), "https://kotto.land/agent.ts");
+    }
+
+    #[tokio::test]
+    async fn diff_exit_codes() {
+        let dir = std::env::temp_dir().join(format!("kottoc-main-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let a = dir.join("a.ts");
+        let b = dir.join("b.ts");
+        std::fs::write(&a, "interface A {}\n").unwrap();
+        std::fs::write(&b, "interface A { x: number }\n").unwrap();
+
+        let diff = |old: &std::path::Path, new: &std::path::Path| {
+            Flags::try_parse_from(["kottoc".as_ref(), "diff".as_ref(), "--json".as_ref(), old.as_os_str(), new.as_os_str()]).unwrap()
+        };
+        assert_eq!(run_subcommand(diff(&a, &b)).await.unwrap(), EXIT_DIFFERENT);
+        assert_eq!(run_subcommand(diff(&a, &a)).await.unwrap(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,13 @@
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
 use std::ops::Deref;
 use serde::{Serialize, Deserialize};
+use ts_rs::TS;
 
 use crate::{codegen, emit, ast};
 use crate::common::comments::Comments;
 use crate::CanPush;
+use crate::format::{Meta, Source};
 
 #[derive(Debug)]
 pub enum InvalidPromptError {
@@ -29,13 +32,16 @@ pub struct Prompts(pub Vec<Prompt>);
 #[serde(transparent)]
 pub struct PromptFmt(pub String);
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, TS)]
 #[serde(rename_all = "snake_case")]
+#[ts(rename = "PromptAstTy")]
 pub enum PromptAstType {
     MethodDecl,
     ClassDecl,
     ClassProp,
     TypeAliasDecl,
+    InterfaceDecl,
+    VarDecl,
     FnDecl,
 }
 
@@ -49,24 +55,134 @@ impl Deref for PromptId {
     fn deref(&self) -> &Self::Target { &self.0 }
 }
 
-#[derive(Serialize, Deserialize)]
+/// Implements [`TS`] for types whose declarations [`write_declarations`]
+/// writes itself, as they depend on the prompts of the module: they refer to
+/// the type parameter `Id` of the declarations they are part of, which
+/// defaults to the `PromptId` of the module.
+macro_rules! declared_per_module {
+    ($ty:ty, $name:literal) => {
+        impl TS for $ty {
+            type WithoutGenerics = Self;
+
+            fn name() -> String { $name.to_string() }
+            fn inline() -> String { Self::name() }
+            fn inline_flattened() -> String { panic!("{} cannot be flattened", $name) }
+            fn decl() -> String { panic!("{} is declared per module", $name) }
+            fn decl_concrete() -> String { Self::decl() }
+        }
+    };
+}
+
+declared_per_module!(PromptId, "Id");
+
+/// The id of a prompt qualified by its node type (e.g.
+/// `interface_decl.Promise#2`), as prompts refer to their context.
+struct ContextId;
+
+declared_per_module!(ContextId, "ContextId<Id>");
+
+#[derive(Serialize, Deserialize, TS)]
 #[serde(rename_all = "snake_case")]
+#[ts(rename = "PromptTy")]
 pub enum PromptType {
     PlainText,
     #[serde(rename = "ts")]
     TypeScript
 }
 
-#[derive(Serialize, Deserialize)]
+/// How an argument given by the model may be repaired into the declared type
+/// of its parameter.
+#[derive(Serialize, Deserialize, TS)]
+#[serde(tag = "to", rename_all = "snake_case")]
+pub enum Coercion {
+    /// Parse numeric strings (e.g. `"42"`)
+    Number,
+    /// Parse `"true"`/`"false"`
+    Boolean,
+    /// Stringify numbers and booleans
+    String,
+    /// Parse dates and timestamps into ISO 8601 strings
+    Date,
+    /// Match one of `values` regardless of case
+    Enum { values: Vec<String> },
+    /// Wrap single values in an array, coercing each element
+    Array {
+        #[serde(default)]
+        #[serde(skip_serializing_if = "Option::is_none")]
+        #[ts(optional)]
+        elem: Option<Box<Coercion>>
+    },
+    /// Coerce the properties `props` of an object
+    Object { props: BTreeMap<String, Coercion> },
+}
+
+#[derive(Serialize, Deserialize, Default, TS)]
+pub struct PromptParam {
+    /// The name of the parameter, or `argN` if it is the destructured `N`th
+    /// one (counting from 0). Parameters without a name (when `argN` is the
+    /// name of another) can only be given positionally.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub name: Option<String>,
+    /// Whether the parameter may be left out, which is also the case of
+    /// parameters with a default value
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[ts(as = "Option<bool>", optional)]
+    pub optional: bool,
+    /// Whether the parameter has a default value
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[ts(as = "Option<bool>", optional)]
+    pub default: bool,
+    /// Whether this is a rest parameter, which takes the remaining arguments
+    #[serde(default)]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    #[ts(as = "Option<bool>", optional)]
+    pub rest: bool,
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub coerce: Option<Coercion>,
+}
+
+impl PromptParam {
+    /// Whether the parameter is serialized as `{}`, telling nothing about it.
+    pub fn is_empty(&self) -> bool {
+        self.name.is_none() && !self.optional && !self.default && !self.rest && self.coerce.is_none()
+    }
+}
+
+/// Where the declaration of a prompt is, for tooling. It is not part of
+/// prompts modules, which do not depend on where they are built.
+#[derive(Clone, Debug, Serialize)]
+pub struct PromptSource {
+    pub specifier: String,
+    /// Starting from 1
+    pub line: usize,
+}
+
+#[derive(Serialize, Deserialize, TS)]
+#[ts(rename = "PromptNode")]
 pub struct Prompt {
     #[serde(rename = "type")]
     pub ty: PromptType,
+    #[ts(as = "String")]
     pub fmt: PromptFmt,
     pub id: PromptId,
     pub ast_ty: Option<PromptAstType>,
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[ts(as = "Option<Vec<ContextId>>", optional)]
     pub context: Vec<PromptId>,
+    /// The parameters of method and function declarations
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[ts(as = "Option<Vec<PromptParam>>", optional)]
+    pub params: Vec<PromptParam>,
+    #[serde(skip)]
+    pub source: Option<PromptSource>,
 }
 
 impl Default for Prompt {
@@ -76,11 +192,83 @@ impl Default for Prompt {
             fmt: PromptFmt(String::new()),
             ast_ty: None,
             id: PromptId(String::new()),
-            context: Vec::new()
+            context: Vec::new(),
+            params: Vec::new(),
+            source: None
         }
     }
 }
 
+/// The union of the serialized forms of `values`, as a TypeScript type.
+fn literal_union<T: Serialize>(values: &[T]) -> String {
+    if values.is_empty() {
+        return "never".to_string()
+    }
+    values.iter()
+        .map(|value| serde_json::to_string(value).unwrap())
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+/// The path of the declarations of the types of prompts modules that the
+/// runtime (`prompts.ts`) imports, see [`write_runtime_declarations`].
+pub const RUNTIME_DECLARATIONS_PATH: &str = "kottoc.d.ts";
+
+/// Writes the declarations of the types above and those of [`crate::format`],
+/// with `prompt_id` for the type of the ids of prompts. The ones that refer to
+/// ids are generic over them (e.g. `PromptNode<Id extends string = PromptId>`).
+fn write_types(writer: &mut dyn std::io::Write, prompt_id: &str) -> std::io::Result<()> {
+    const GENERIC: &str = "<Id extends string = PromptId>";
+
+    writeln!(writer, "export {}", PromptType::decl())?;
+    writeln!(writer, "export {}", PromptAstType::decl())?;
+    writeln!(writer, "export type PromptId = {};", prompt_id)?;
+    writeln!(writer, "export type ContextId{} = `${{PromptAstTy}}.${{Id}}`;", GENERIC)?;
+    writeln!(writer)?;
+    let prompt = Prompt::decl().replacen(
+        &format!("type {} =", Prompt::name()),
+        &format!("type {}{} =", Prompt::name(), GENERIC),
+        1
+    );
+    for decl in [Coercion::decl(), PromptParam::decl(), prompt, Source::decl(), Meta::decl()] {
+        writeln!(writer, "export {}", decl)?;
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Writes the TypeScript declarations of a prompts module made of `prompts`
+/// (and validators, if `with_validators`), generated from the types above and
+/// those of [`crate::format`].
+///
+/// `PromptId` is the union of the ids of `prompts`, so that the ids a program
+/// refers to are checked against the ones that exist.
+pub fn write_declarations(
+    writer: &mut dyn std::io::Write,
+    prompts: &Prompts,
+    with_validators: bool
+) -> std::io::Result<()> {
+    let ids: Vec<&str> = prompts.0.iter().map(|prompt| &*prompt.id).collect();
+
+    writeln!(writer, "// Generated by kottoc, do not edit")?;
+    write_types(writer, &literal_union(&ids))?;
+    writeln!(writer, "export declare const meta: Meta & {{ format: {} }};", crate::format::FORMAT_VERSION)?;
+    writeln!(writer, "export declare const ast: readonly PromptNode[];")?;
+    if with_validators {
+        writeln!(writer, "export declare const validators: Partial<Record<PromptId, (args: unknown[]) => string[]>>;")?;
+    }
+    Ok(())
+}
+
+/// Writes the declarations of the types of any prompts module (whose
+/// `PromptId` is `string`), which the runtime imports rather than declaring
+/// them again. The tests check that [`RUNTIME_DECLARATIONS_PATH`] is up to
+/// date.
+pub fn write_runtime_declarations(writer: &mut dyn std::io::Write) -> std::io::Result<()> {
+    writeln!(writer, "// Generated by kottoc (`UPDATE_DECLARATIONS=1 cargo test -p kottoc`), do not edit")?;
+    write_types(writer, "string")
+}
+
 pub struct PromptsWriter<'p, C> {
     buf: &'p mut C,
     modified: bool,
@@ -106,9 +294,20 @@ impl<'p, C> PromptsWriter<'p, C> {
     }
 
     pub fn set_fmt<N: codegen::Node>(&mut self, node: &N) -> Result<(), InvalidPromptError> {
+        self.set_fmt_with_comments(node, self.comments)
+    }
+
+    /// Like [`set_fmt`](Self::set_fmt), but for nodes of another module than
+    /// the one this writer was created for.
+    pub fn set_fmt_with_comments<N: codegen::Node>(
+        &mut self,
+        node: &N,
+        comments: &dyn Comments
+    ) -> Result<(), InvalidPromptError> {
+        let comments = emit::ReadOnlyComments::new(comments);
         let mut buf = Vec::new();
         let mut emitter = emit::Emitter::new(&mut buf)
-            .with_comments(&self.comments);
+            .with_comments(&comments);
         node.emit_with(&mut emitter).unwrap();
         let source_text = String::from_utf8(buf).unwrap();
 
@@ -118,6 +317,21 @@ impl<'p, C> PromptsWriter<'p, C> {
         Ok(())
     }
 
+    pub fn set_source(&mut self, source: Option<PromptSource>) {
+        self.modified = true;
+        self.builder.source = source;
+    }
+
+    /// Sets the parameters of the signature, unless none of them tells
+    /// anything (e.g. all destructured, without coercions).
+    pub fn set_params(&mut self, mut params: Vec<PromptParam>) {
+        if params.iter().all(PromptParam::is_empty) {
+            params.clear();
+        }
+        self.modified = true;
+        self.builder.params = params;
+    }
+
     pub fn add_to_context<I, S>(&mut self, iter: I) -> Result<(), InvalidPromptError>
     where
         I: Iterator<Item = S>,
@@ -127,19 +341,41 @@ impl<'p, C> PromptsWriter<'p, C> {
         Ok(())
     }
 
-    pub fn enter_scope(&mut self, scope: &ast::Ident) -> () {
+    pub fn enter_scope(&mut self, scope: &ast::Ident) {
         self.scope.push(format!("{}", scope));
     }
 
+    /// Like [`enter_scope`](Self::enter_scope), for scopes that are not named
+    /// after an identifier of the module (e.g. the namespace of a module).
+    pub fn enter_raw_scope(&mut self, scope: String) {
+        self.scope.push(scope);
+    }
+
     pub fn exit_scope(&mut self) -> Option<String> {
         self.scope.pop()
     }
 
+    /// The id that a prompt with the id `id` gets in the current scope.
+    pub fn scoped_id(&self, id: &str) -> String {
+        if self.scope.is_empty() {
+            id.to_string()
+        } else {
+            format!("{}.{}", self.scope.join("."), id)
+        }
+    }
+
     pub fn set_id(&mut self, id: &ast::Ident) {
         self.modified = true;
         self.builder.id = PromptId(format!("{}", id));
     }
 
+    /// Like [`set_id`](Self::set_id), for prompts that are not named after
+    /// an identifier of the module (e.g. `builtins.fetch`).
+    pub fn set_raw_id(&mut self, id: String) {
+        self.modified = true;
+        self.builder.id = PromptId(id);
+    }
+
     pub fn set_ast_ty(&mut self, ast_ty: PromptAstType) {
         self.modified = true;
         self.builder.ast_ty = Some(ast_ty);
@@ -159,11 +395,9 @@ where
             return Err(InvalidPromptError::InvalidId(self.builder.id.to_string()))
         }
 
-        let mut prompt = std::mem::replace(&mut self.builder, Prompt::default());
+        let mut prompt = std::mem::take(&mut self.builder);
 
-        if !self.scope.is_empty() {
-            prompt.id = PromptId(format!("{}.{}", self.scope.join("."), &*prompt.id));
-        }
+        prompt.id = PromptId(self.scoped_id(&prompt.id));
 
         self.buf.push(prompt);
 
@@ -171,4 +405,60 @@ where
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prompt(id: &str, context: &[&str]) -> Prompt {
+        Prompt {
+            ty: PromptType::TypeScript,
+            id: PromptId(id.to_string()),
+            ast_ty: Some(PromptAstType::InterfaceDecl),
+            context: context.iter().map(|id| PromptId(id.to_string())).collect(),
+            ..Prompt::default()
+        }
+    }
+
+    fn declarations(prompts: &Prompts, with_validators: bool) -> String {
+        let mut buf = Vec::new();
+        write_declarations(&mut buf, prompts, with_validators).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn declare_prompt_ids() {
+        let prompts = Prompts(vec![prompt("Query#2", &[]), prompt("Agent#2", &["interface_decl.Query#2"])]);
+        let text = declarations(&prompts, true);
+
+        assert!(text.contains(r#"export type PromptId = "Query#2" | "Agent#2";"#));
+        assert!(text.contains("export type ContextId<Id extends string = PromptId> = `${PromptAstTy}.${Id}`;"));
+        assert!(text.contains("export type PromptNode<Id extends string = PromptId> = {"));
+        assert!(text.contains("id: Id,"));
+        assert!(text.contains("context?: Array<ContextId<Id>>"));
+        assert!(text.contains("export declare const meta: Meta & { format: 2 };"));
+        assert!(text.contains("export declare const validators: Partial<Record<PromptId, "));
+
+        // Modules without prompts have no ids to refer to
+        let text = declarations(&Prompts(Vec::new()), false);
+        assert!(text.contains("export type PromptId = never;"));
+        assert!(!text.contains("validators"));
+    }
+
+    #[test]
+    fn runtime_declarations_are_up_to_date() {
+        let mut buf = Vec::new();
+        write_runtime_declarations(&mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join(RUNTIME_DECLARATIONS_PATH);
+        if std::env::var_os("UPDATE_DECLARATIONS").is_some() {
+            std::fs::write(&path, &text).unwrap();
+        }
+        assert!(
+            std::fs::read_to_string(&path).is_ok_and(|current| current == text),
+            "{} is out of date: run `UPDATE_DECLARATIONS=1 cargo test -p kottoc`",
+            RUNTIME_DECLARATIONS_PATH
+        );
+    }
+}
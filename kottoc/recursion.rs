@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use deno_ast::ModuleSpecifier;
+
+use crate::ast;
+use crate::filter::{TypeParamVisitor, TypeRefVisitor};
+use crate::graph::{ModuleGraph, Resolved};
+use crate::visit::Visit;
+
+type Key = (ModuleSpecifier, ast::Id, bool);
+
+fn key_of(decl: &Resolved) -> Key {
+    (decl.module.specifier.clone(), decl.id.clone(), decl.value)
+}
+
+/// Detects recursion in the graph of type declarations, where declarations
+/// refer to each other through their type references (following imports).
+///
+/// A declaration is recursive if it is part of a cycle of references, either
+/// to itself (e.g. `type Tree = { children: Tree[] }`) or through others
+/// (e.g. `Expr` and `Call`, which has `Expr` arguments). Such declarations
+/// cannot be expanded in place.
+#[derive(Default)]
+pub struct Recursion {
+    /// The declarations that each declaration directly refers to
+    edges: HashMap<Key, Vec<Resolved>>,
+    /// The cycles that recursive declarations are part of
+    cycles: HashMap<Key, Rc<Vec<Resolved>>>,
+    /// The declarations whose cycles are known
+    done: HashSet<Key>,
+}
+
+impl Recursion {
+    /// The declarations in the cycle that `decl` is part of (`decl` included),
+    /// if it is recursive.
+    pub async fn cycle_of(&mut self, module_graph: &mut ModuleGraph, decl: &Resolved) -> Option<Rc<Vec<Resolved>>> {
+        if !self.done.contains(&key_of(decl)) {
+            self.load(module_graph, decl).await;
+            let mut tarjan = Tarjan::default();
+            tarjan.visit(self, decl);
+        }
+        self.cycles.get(&key_of(decl)).cloned()
+    }
+
+    /// Resolves the references of the declarations reachable from `decl`.
+    async fn load(&mut self, module_graph: &mut ModuleGraph, decl: &Resolved) {
+        let mut to_visit = vec![decl.clone()];
+        while let Some(next) = to_visit.pop() {
+            let key = key_of(&next);
+            if self.edges.contains_key(&key) || self.done.contains(&key) {
+                continue
+            }
+
+            let filtered_module = &next.module.filtered_module;
+            let mut type_refs = Vec::new();
+            let mut type_params = HashSet::new();
+            if next.value {
+                if let Some(var_decl) = filtered_module.var_decls.get(&next.id) {
+                    TypeRefVisitor(&mut type_refs).visit_var_decl(var_decl);
+                }
+            } else if let Some(type_alias_decl) = filtered_module.type_alias_decls.get(&next.id) {
+                TypeParamVisitor(&mut type_params).visit_ts_type_alias_decl(type_alias_decl);
+                TypeRefVisitor(&mut type_refs).visit_ts_type_alias_decl(type_alias_decl);
+            } else if let Some(interface_decl) = filtered_module.interface_decls.get(&next.id) {
+                TypeParamVisitor(&mut type_params).visit_ts_interface_decl(interface_decl);
+                TypeRefVisitor(&mut type_refs).visit_ts_interface_decl(interface_decl);
+            }
+
+            let mut edges = Vec::new();
+            for type_ref in type_refs.iter().filter(|type_ref| !type_ref.is_bound_by(&type_params)) {
+                if let Some(referenced) = module_graph.resolve(&next.module, &type_ref.name()).await {
+                    to_visit.push(referenced.clone());
+                    edges.push(referenced);
+                }
+            }
+            self.edges.insert(key, edges);
+        }
+    }
+}
+
+/// Tarjan's algorithm for the strongly connected components of the graph.
+#[derive(Default)]
+struct Tarjan {
+    index: HashMap<Key, usize>,
+    low_link: HashMap<Key, usize>,
+    stack: Vec<Resolved>,
+    on_stack: HashSet<Key>,
+}
+
+impl Tarjan {
+    fn visit(&mut self, recursion: &mut Recursion, decl: &Resolved) {
+        let key = key_of(decl);
+        let index = self.index.len();
+        self.index.insert(key.clone(), index);
+        self.low_link.insert(key.clone(), index);
+        self.stack.push(decl.clone());
+        self.on_stack.insert(key.clone());
+
+        let edges = recursion.edges.get(&key).cloned().unwrap_or_default();
+        for referenced in &edges {
+            let referenced_key = key_of(referenced);
+            if recursion.done.contains(&referenced_key) {
+                continue
+            }
+            if !self.index.contains_key(&referenced_key) {
+                self.visit(recursion, referenced);
+                let low_link = self.low_link[&key].min(self.low_link[&referenced_key]);
+                self.low_link.insert(key.clone(), low_link);
+            } else if self.on_stack.contains(&referenced_key) {
+                let low_link = self.low_link[&key].min(self.index[&referenced_key]);
+                self.low_link.insert(key.clone(), low_link);
+            }
+        }
+
+        if self.low_link[&key] != index {
+            return
+        }
+
+        let mut component = Vec::new();
+        while let Some(member) = self.stack.pop() {
+            let member_key = key_of(&member);
+            self.on_stack.remove(&member_key);
+            component.push(member);
+            if member_key == key {
+                break
+            }
+        }
+
+        let is_cycle = component.len() > 1 || edges.iter().any(|referenced| key_of(referenced) == key);
+        let component = Rc::new(component);
+        for member in component.iter() {
+            let member_key = key_of(member);
+            if is_cycle {
+                recursion.cycles.insert(member_key.clone(), component.clone());
+            }
+            recursion.done.insert(member_key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: &str = "type Tree = { value: number; children: Tree[] };\n\
+        interface Expr { call?: Call; value?: number }\n\
+        interface Call { name: string; args: Expr[] }\n\
+        type Program = { body: Expr[]; root: Tree };\n\
+        type List<T> = { head: T; tail?: List<T> };\n\
+        type Leaf<Tree> = { value: Tree };\n\
+        type Wrapped = Leaf<Wrapped>;\n";
+
+    /// The names of the declarations in the cycle of `name`, if it is recursive.
+    async fn cycle_names(name: &str) -> Option<Vec<String>> {
+        let specifier = ModuleSpecifier::parse("file:///types.ts").unwrap();
+        let mut module_graph = ModuleGraph::default();
+        let module = module_graph.load_source(&specifier, TYPES.to_string()).await.unwrap();
+
+        let filtered_module = &module.filtered_module;
+        let id = filtered_module.type_alias_decls.keys()
+            .chain(filtered_module.interface_decls.keys())
+            .find(|id| &*id.0 == name)
+            .unwrap()
+            .clone();
+        let decl = Resolved { module: module.clone(), id, value: false };
+
+        let cycle = Recursion::default().cycle_of(&mut module_graph, &decl).await?;
+        let mut names: Vec<String> = cycle.iter().map(|decl| decl.id.0.to_string()).collect();
+        names.sort();
+        Some(names)
+    }
+
+    #[tokio::test]
+    async fn self_recursive() {
+        assert_eq!(cycle_names("Tree").await, Some(vec!["Tree".to_string()]));
+        assert_eq!(cycle_names("List").await, Some(vec!["List".to_string()]));
+        assert_eq!(cycle_names("Wrapped").await, Some(vec!["Wrapped".to_string()]));
+    }
+
+    #[tokio::test]
+    async fn mutually_recursive() {
+        let expected = Some(vec!["Call".to_string(), "Expr".to_string()]);
+        assert_eq!(cycle_names("Expr").await, expected);
+        assert_eq!(cycle_names("Call").await, expected);
+    }
+
+    #[tokio::test]
+    async fn not_recursive() {
+        // Referring to recursive types does not make a type recursive, nor
+        // does a type parameter named after a declaration
+        assert_eq!(cycle_names("Program").await, None);
+        assert_eq!(cycle_names("Leaf").await, None);
+    }
+}
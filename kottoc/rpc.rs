@@ -0,0 +1,81 @@
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::{AnyError, anyhow};
+
+/// The error codes of JSON-RPC 2.0.
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Reads a JSON-RPC message framed by a `Content-Length` header, as in the
+/// base protocol of the Language Server Protocol. Returns `None` at the end of
+/// the input.
+///
+/// Malformed messages are errors of their own, which reading can go on after
+/// (see [`malformed_response`]), unlike failing to read.
+pub async fn read_message<R>(reader: &mut R) -> Result<Option<Value>, AnyError>
+where
+    R: AsyncBufRead + Unpin
+{
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None)
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                content_length = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    // The whole header is read before, for the next message to start after it
+    let content_length = content_length.ok_or_else(|| anyhow!("missing Content-Length header"))?;
+    let content_length: usize = content_length.parse()
+        .map_err(|_| anyhow!("invalid Content-Length: {}", content_length))?;
+    let mut content = vec![0; content_length];
+    reader.read_exact(&mut content).await?;
+    let message = serde_json::from_slice(&content)
+        .map_err(|err| anyhow!("invalid message: {}", err))?;
+    Ok(Some(message))
+}
+
+/// The response to a message that [`read_message`] failed to read, or the
+/// error itself if the input failed rather than the message being malformed.
+pub fn malformed_response(err: AnyError) -> Result<Value, AnyError> {
+    if err.is::<std::io::Error>() {
+        return Err(err)
+    }
+    Ok(error_response(Value::Null, PARSE_ERROR, err))
+}
+
+/// Writes `message`, framed as [`read_message`] expects it.
+pub async fn write_message<W>(writer: &mut W, message: &Value) -> Result<(), AnyError>
+where
+    W: AsyncWrite + Unpin
+{
+    let content = serde_json::to_vec(message)?;
+    writer.write_all(format!("Content-Length: {}\r\n\r\n", content.len()).as_bytes()).await?;
+    writer.write_all(&content).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+pub fn response(id: Value, result: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+}
+
+pub fn error_response(id: Value, code: i64, message: impl ToString) -> Value {
+    json!({ "jsonrpc": "2.0", "id": id, "error": { "code": code, "message": message.to_string() } })
+}
+
+pub fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+
+use deno_ast::ModuleSpecifier;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncWrite, BufReader};
+
+use crate::{builtins, format, graph, rpc, tasks};
+use crate::{AnyError, anyhow};
+use crate::graph::ModuleGraph;
+use crate::tasks::CompileOptions;
+
+#[derive(Deserialize)]
+struct CompileParams {
+    specifier: ModuleSpecifier,
+    /// The source of the module, if it is not to be fetched (e.g. unsaved)
+    #[serde(default)]
+    source: Option<String>,
+    #[serde(default)]
+    options: CompileOptions,
+}
+
+#[derive(Deserialize)]
+struct InvalidateParams {
+    #[serde(default)]
+    specifier: Option<ModuleSpecifier>,
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, AnyError> {
+    serde_json::from_value(params).map_err(|err| anyhow!("invalid params: {}", err))
+}
+
+/// A prompts module compiled earlier, along with the modules it was compiled
+/// from.
+struct Compiled {
+    /// The hashes of the sources of the modules, by specifier
+    sources: Vec<(ModuleSpecifier, String)>,
+    text: String,
+}
+
+/// Compiles modules on request, keeping their prompts until the modules they
+/// were compiled from change.
+#[derive(Default)]
+struct Server {
+    /// By specifier and fingerprint of the options
+    cache: HashMap<(ModuleSpecifier, String), Compiled>,
+}
+
+impl Server {
+    /// Whether the modules that `compiled` was compiled from are unchanged.
+    /// Remote modules are assumed not to change, as fetching them again would
+    /// defeat the purpose of the cache.
+    async fn is_fresh(compiled: &Compiled, params: &CompileParams) -> bool {
+        for (specifier, hash) in &compiled.sources {
+            let current = match &params.source {
+                Some(source) if specifier == &params.specifier => format::hash(source.as_bytes()),
+                _ if specifier.scheme() != "file" => continue,
+                _ => match graph::fetch_module_source(specifier).await {
+                    Ok(source) => format::hash(source.as_bytes()),
+                    Err(_) => return false
+                }
+            };
+            if &current != hash {
+                return false
+            }
+        }
+        true
+    }
+
+    /// Compiles `params.specifier`, returning the text of its prompts module
+    /// and whether it was cached.
+    async fn compile(&mut self, params: CompileParams) -> Result<Value, AnyError> {
+        let key = (params.specifier.clone(), params.options.fingerprint());
+        if let Some(compiled) = self.cache.get(&key) {
+            if Self::is_fresh(compiled, &params).await {
+                return Ok(json!({ "module": compiled.text, "cached": true }))
+            }
+        }
+
+        let mut module_graph = ModuleGraph::default();
+        if let Some(source) = &params.source {
+            module_graph.add_module(params.specifier.clone(), source.clone()).await?;
+        }
+        let text = tasks::compile_to_string(&mut module_graph, &params.specifier, &params.options).await?;
+
+        let sources = module_graph.modules()
+            .filter(|module| !builtins::is_builtin(&module.specifier))
+            .map(|module| (module.specifier.clone(), module.hash.clone()))
+            .collect();
+        self.cache.insert(key, Compiled { sources, text: text.clone() });
+
+        Ok(json!({ "module": text, "cached": false }))
+    }
+
+    /// Handles the request `method`, returning `None` if it is unknown.
+    async fn handle(&mut self, method: &str, params: Value) -> Result<Option<Value>, AnyError> {
+        match method {
+            "compile" => Ok(Some(self.compile(parse_params(params)?).await?)),
+            "invalidate" => {
+                let params: InvalidateParams = parse_params(params)?;
+                match params.specifier {
+                    Some(specifier) => self.cache.retain(|(cached, _), _| cached != &specifier),
+                    None => self.cache.clear()
+                }
+                Ok(Some(Value::Null))
+            }
+            _ => Ok(None)
+        }
+    }
+}
+
+/// Serves JSON-RPC requests over stdin and stdout (framed as in the Language
+/// Server Protocol) until stdin is closed:
+///
+/// - `compile` (`{ specifier, source?, options? }`) compiles a module, or the
+///   source given for it, into `{ module, cached }`, where `module` is the text
+///   of its prompts module
+/// - `invalidate` (`{ specifier? }`) drops the prompts cached for a module, or
+///   for all of them
+pub async fn serve() -> Result<(), AnyError> {
+    serve_on(BufReader::new(tokio::io::stdin()), tokio::io::stdout()).await
+}
+
+/// Like [`serve`], reading requests from `reader` and writing responses to
+/// `writer`. Malformed messages are answered with errors, and only the end of
+/// the input (or failing to read or write) stops serving.
+async fn serve_on<R, W>(mut reader: R, mut writer: W) -> Result<(), AnyError>
+where
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin
+{
+    let mut server = Server::default();
+
+    loop {
+        let mut message = match rpc::read_message(&mut reader).await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(err) => {
+                rpc::write_message(&mut writer, &rpc::malformed_response(err)?).await?;
+                continue
+            }
+        };
+        if !message.is_object() {
+            let response = rpc::error_response(Value::Null, rpc::INVALID_REQUEST, "not a request object");
+            rpc::write_message(&mut writer, &response).await?;
+            continue
+        }
+
+        let method = message["method"].as_str().unwrap_or_default().to_string();
+        let params = match message["params"].take() {
+            Value::Null => json!({}),
+            params => params
+        };
+        let result = server.handle(&method, params).await;
+        let Some(id) = message.get("id").cloned() else {
+            continue
+        };
+        let response = match result {
+            Ok(Some(result)) => rpc::response(id, result),
+            Ok(None) => rpc::error_response(id, rpc::METHOD_NOT_FOUND, format!("unknown method: {}", method)),
+            Err(err) => rpc::error_response(id, rpc::INTERNAL_ERROR, err)
+        };
+        rpc::write_message(&mut writer, &response).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(content: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", content.len(), content)
+    }
+
+    async fn responses(input: &str) -> Vec<Value> {
+        let mut output = Vec::new();
+        serve_on(input.as_bytes(), &mut output).await.unwrap();
+
+        let mut reader = output.as_slice();
+        let mut responses = Vec::new();
+        while let Some(response) = rpc::read_message(&mut reader).await.unwrap() {
+            responses.push(response);
+        }
+        responses
+    }
+
+    #[tokio::test]
+    async fn keep_serving_after_malformed_messages() {
+        let input = [
+            frame("{ not json"),
+            "Content-Length: many\r\n\r\n".to_string(),
+            frame("[1, 2]"),
+            frame(r#"{"jsonrpc": "2.0", "id": 1, "method": "invalidate"}"#),
+            frame(r#"{"jsonrpc": "2.0", "id": 2, "method": "unknown"}"#),
+        ].concat();
+        let responses = responses(&input).await;
+
+        let codes: Vec<&Value> = responses.iter().map(|response| &response["error"]["code"]).collect();
+        assert_eq!(codes, [
+            &json!(rpc::PARSE_ERROR),
+            &json!(rpc::PARSE_ERROR),
+            &json!(rpc::INVALID_REQUEST),
+            &Value::Null,
+            &json!(rpc::METHOD_NOT_FOUND)
+        ]);
+        assert_eq!(responses[0]["id"], Value::Null);
+        assert_eq!(responses[3], rpc::response(json!(1), Value::Null));
+    }
+
+    #[tokio::test]
+    async fn stop_on_truncated_input() {
+        let mut output = Vec::new();
+        let input = "Content-Length: 10\r\n\r\n{}";
+        assert!(serve_on(input.as_bytes(), &mut output).await.is_err());
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,139 @@
+use std::borrow::Cow;
+use std::rc::Rc;
+
+use crate::{ast, coerce, filter};
+use crate::eval::TypeEvaluator;
+use crate::graph::Module;
+use crate::prompts::PromptParam;
+
+/// A parameter of a function or method, as the runtime sees it.
+pub struct Param<'a> {
+    /// The name of the parameter, unless it is destructured
+    pub name: Option<String>,
+    /// The type of the parameter, inferred from its default value if it has
+    /// none (as [`filter::normalize_params`] does)
+    pub type_ann: Option<Cow<'a, ast::TsType>>,
+    pub optional: bool,
+    pub default: bool,
+    pub rest: bool,
+}
+
+impl<'a> Param<'a> {
+    pub fn from_param(param: &'a ast::Param) -> Self {
+        let (pat, default) = match &param.pat {
+            ast::Pat::Assign(assign) => (&*assign.left, Some(&*assign.right)),
+            pat => (pat, None)
+        };
+
+        let type_ann = |type_ann: &'a Option<Box<ast::TsTypeAnn>>| match (type_ann, default) {
+            (Some(type_ann), _) => Some(Cow::Borrowed(&*type_ann.type_ann)),
+            (None, Some(default)) => Some(Cow::Owned(filter::default_type(default))),
+            (None, None) => None
+        };
+
+        let (name, type_ann, optional, rest) = match pat {
+            ast::Pat::Ident(binding) => (Some(binding.id.sym.to_string()), type_ann(&binding.type_ann), binding.optional, false),
+            ast::Pat::Rest(rest_pat) => {
+                let name = rest_pat.arg.as_ident().map(|binding| binding.id.sym.to_string());
+                (name, type_ann(&rest_pat.type_ann), true, true)
+            }
+            ast::Pat::Array(array) => (None, type_ann(&array.type_ann), array.optional, false),
+            ast::Pat::Object(object) => (None, type_ann(&object.type_ann), object.optional, false),
+            _ => (None, None, false, false)
+        };
+
+        Self {
+            name,
+            type_ann,
+            optional: optional || default.is_some(),
+            default: default.is_some(),
+            rest
+        }
+    }
+}
+
+/// Describes `params` for the runtime: how to map named arguments to them, and
+/// how to repair the arguments given by the model.
+pub fn prompt_params(evaluator: &TypeEvaluator, module: &Rc<Module>, params: &[ast::Param]) -> Vec<PromptParam> {
+    let params: Vec<Param> = params.iter().map(Param::from_param).collect();
+    let names: Vec<&str> = params.iter().filter_map(|param| param.name.as_deref()).collect();
+    // Destructured parameters are named after their position for the model
+    // to be able to give them by name, unless that is some other's name
+    let positional_names: Vec<Option<String>> = (0..params.len())
+        .map(|i| Some(format!("arg{}", i)).filter(|name| !names.contains(&name.as_str())))
+        .collect();
+
+    params.iter()
+        .zip(positional_names)
+        .map(|(param, positional_name)| {
+            let coerce = param.type_ann.as_ref().and_then(|type_ann| {
+                coerce::coercion_of(evaluator, &evaluator.evaluate(module, type_ann))
+            });
+
+            PromptParam {
+                name: param.name.clone().or(positional_name),
+                optional: param.optional,
+                default: param.default,
+                rest: param.rest,
+                coerce
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use deno_ast::ModuleSpecifier;
+    use serde_json::json;
+
+    use super::*;
+    use crate::graph::ModuleGraph;
+
+    /// Describes the parameters of the function `f` declared in `source`.
+    async fn params_of(source: &str) -> serde_json::Value {
+        let specifier = ModuleSpecifier::parse("file:///test.ts").unwrap();
+        let mut module_graph = ModuleGraph::default();
+        let module = module_graph.load_source(&specifier, source.to_string()).await.unwrap();
+        module_graph.builtins().await.unwrap();
+
+        let fn_decl = module.filtered_module.fn_decls.iter()
+            .find(|fn_decl| &*fn_decl.fn_decl.ident.sym == "f")
+            .unwrap();
+        serde_json::to_value(prompt_params(&TypeEvaluator::new(&module_graph), &module, &fn_decl.params)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn typed_params() {
+        assert_eq!(
+            params_of("export function f(query: string, tags?: string[]) {}").await,
+            json!([
+                { "name": "query", "coerce": { "to": "string" } },
+                { "name": "tags", "optional": true, "coerce": { "to": "array", "elem": { "to": "string" } } }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn untyped_params_with_defaults() {
+        assert_eq!(
+            params_of("export function f(limit = 10, exact = false, other) {}").await,
+            json!([
+                { "name": "limit", "optional": true, "default": true, "coerce": { "to": "number" } },
+                { "name": "exact", "optional": true, "default": true, "coerce": { "to": "boolean" } },
+                { "name": "other" }
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn destructured_params() {
+        assert_eq!(
+            params_of("export function f({ limit }: { limit: number }, [first] = [1], arg0?: string) {}").await,
+            json!([
+                { "coerce": { "to": "object", "props": { "limit": { "to": "number" } } } },
+                { "name": "arg1", "optional": true, "default": true, "coerce": { "to": "array", "elem": { "to": "number" } } },
+                { "name": "arg0", "optional": true, "coerce": { "to": "string" } }
+            ])
+        );
+    }
+}
@@ -1,15 +1,93 @@
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::io::Write;
+use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
 
-use crate::{AnyError, anyhow, CanPush};
-use crate::{ast, filter};
+use crate::{AnyError, CanPush, anyhow};
+use crate::{ast, builtins, filter, format, signature};
+use crate::format::{Meta, Source};
+use crate::inline::InlinedAliases;
+use crate::recursion::Recursion;
+use crate::filter::{TypeName, TypeRef, TypeRefVisitor};
+use crate::eval::TypeEvaluator;
+use crate::validate::Validators;
+use crate::graph::{Module, ModuleGraph, Resolved};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::graph::SourceCache;
 use crate::prompts::{Prompt, PromptAstType, Prompts, PromptsWriter, PromptType};
 
 use deno_ast::ModuleSpecifier;
 
+use serde::{Deserialize, Serialize};
+
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::sync::Semaphore;
+#[cfg(not(target_arch = "wasm32"))]
+use tokio::task::JoinSet;
+
 use tracing::{event, Level};
 
-const PROMPTS_EXT: &'static str = "prompts.js";
+use crate::visit::{Visit, VisitMutWith};
+
+pub const PROMPTS_EXT: &str = "prompts.js";
+
+/// The extension of the declarations written next to prompts modules.
+#[cfg(not(target_arch = "wasm32"))]
+const DECLARATIONS_EXT: &str = "d.ts";
+
+/// The name of the prompts module that `--bundle` writes to the output
+/// directory.
+#[cfg(not(target_arch = "wasm32"))]
+const BUNDLE_FILE_NAME: &str = "bundle.prompts.js";
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CompileOptions {
+    /// Emit instantiated forms of the generic aliases used in signatures
+    /// (e.g. `Page<Item>`), in addition to the generic declarations. Those
+    /// of the builtin utility types (e.g. `Pick<Order, "id">`) always are.
+    pub expand_generics: bool,
+    /// Only emit the declarations reachable from the methods exposed with
+    /// `@use`, the exported functions (and the requested builtins), rather
+    /// than all of the module's.
+    pub tree_shake: bool,
+    /// Substitute the aliases whose definitions are at most this many
+    /// characters long for their references in signatures.
+    pub inline_aliases: Option<usize>,
+    /// Compile all the modules into a single prompts module.
+    pub bundle: bool,
+    /// Mirror the input tree relative to this directory in the output one,
+    /// rather than writing all the prompts modules next to each other.
+    #[serde(skip)]
+    pub root: Option<PathBuf>,
+    /// Compare the prompts modules with the ones in the output directory
+    /// rather than writing them.
+    #[serde(skip)]
+    pub check: bool,
+    /// The number of modules compiled at a time, defaulting to the number of
+    /// threads available.
+    #[serde(skip)]
+    pub jobs: Option<usize>,
+}
+
+impl CompileOptions {
+    /// Identifies the options that change what prompts modules contain.
+    pub fn fingerprint(&self) -> String {
+        let mut fingerprint = format::hash(serde_json::to_string(self).unwrap().as_bytes());
+        fingerprint.truncate(16);
+        fingerprint
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn jobs(&self) -> usize {
+        self.jobs
+            .or_else(|| std::thread::available_parallelism().ok().map(Into::into))
+            .unwrap_or(1)
+            .max(1)
+    }
+}
 
 pub async fn parse_module(module_specifier: String, module_source: String) -> Result<deno_ast::ParsedSource, AnyError> {
     let media_type = deno_ast::MediaType::from_path(Path::new(&module_specifier));
@@ -31,126 +109,1605 @@ pub async fn parse_module(module_specifier: String, module_source: String) -> Re
     }
 }
 
+/// Resolves the closure of `type_refs` in `module`, following imports.
+async fn resolve_closure(
+    module_graph: &mut ModuleGraph,
+    module: &Rc<Module>,
+    type_refs: &[TypeRef]
+) -> Vec<Resolved> {
+    let mut resolved = Vec::new();
+    for type_name in module.filtered_module.find_closure_of_type_refs(type_refs) {
+        if let Some(decl) = module_graph.resolve(module, &type_name).await {
+            resolved.push(decl);
+        }
+    }
+    // In a stable order, as the closure is a set, and once each, as different
+    // names may refer to the same declaration (e.g. through a namespace import)
+    resolved.sort_by(|a, b| {
+        (a.module.specifier.as_str(), &*a.id.0, a.id.1.as_u32()).cmp(&(b.module.specifier.as_str(), &*b.id.0, b.id.1.as_u32()))
+    });
+    resolved.dedup_by(|a, b| a.module.specifier == b.module.specifier && a.id == b.id && a.value == b.value);
+    resolved
+}
+
+fn context_ids(resolved: &[Resolved]) -> impl Iterator<Item = String> + '_ {
+    resolved.iter().filter_map(Resolved::context_id)
+}
+
+/// Declarations that prompts of the module being compiled refer to, but which
+/// are not emitted with the rest of it: the ones from other modules, and the
+/// constants whose types are referred to with `typeof`. When tree shaking, the
+/// types of the module itself are only emitted this way as well.
+struct ExternalDecls<'m> {
+    module: &'m Rc<Module>,
+    include_local: bool,
+    /// The declarations emitted so far, by module and context id
+    seen: HashSet<(ModuleSpecifier, String)>,
+    to_emit: Vec<Resolved>,
+    seen_instances: HashSet<String>,
+    instances: Vec<(Resolved, ast::TsTypeAliasDecl, Vec<String>)>,
+    recursion: Recursion,
+}
+
+impl<'m> ExternalDecls<'m> {
+    fn new(module: &'m Rc<Module>, include_local: bool) -> Self {
+        Self {
+            module,
+            include_local,
+            seen: HashSet::new(),
+            to_emit: Vec::new(),
+            seen_instances: HashSet::new(),
+            instances: Vec::new(),
+            recursion: Recursion::default()
+        }
+    }
+
+    /// Instantiates the generic aliases that `type_refs` refer to with type
+    /// arguments (only the builtin ones, unless `all`), returning the context
+    /// ids of the instances.
+    async fn instantiate_generics(
+        &mut self,
+        module_graph: &mut ModuleGraph,
+        module: &Rc<Module>,
+        type_refs: &[TypeRef],
+        all: bool
+    ) -> Vec<String> {
+        let mut context = Vec::new();
+        for type_ref in type_refs {
+            let Some(type_args) = &type_ref.type_params else {
+                continue
+            };
+            let Some(decl) = module_graph.resolve(module, &type_ref.name()).await else {
+                continue
+            };
+            if !all && decl.module.specifier != builtins::types_specifier() {
+                continue
+            }
+
+            // Arguments that do not resolve (e.g. the signature's own type
+            // parameters) would not make the instance any more concrete
+            let mut concrete = true;
+            for type_arg in &type_args.params {
+                if let ast::TsType::TsTypeRef(ast::TsTypeRef { type_name: ast::TsEntityName::Ident(ident), .. }) = &**type_arg {
+                    concrete &= module_graph.resolve(module, &ident.to_id().into()).await.is_some();
+                }
+            }
+            if !concrete {
+                continue
+            }
+
+            let Some(mut instance) = decl.module.filtered_module.type_alias_decls
+                .get(&decl.id)
+                .and_then(|type_alias_decl| type_alias_decl.instantiate(type_args)) else {
+                continue
+            };
+
+            let context_id = format!(
+                "type_alias_decl.{}",
+                decl.module.qualified_name(&decl.module.filtered_module.scoped_name(&decl.id, &instance.id))
+            );
+            if self.seen_instances.insert(context_id.clone()) {
+                // Show what the instance amounts to (e.g. the properties that
+                // `Pick` keeps) rather than the substituted definition
+                let evaluator = TypeEvaluator::new(module_graph);
+                instance.type_ann = Box::new(evaluator.evaluate(module, &ast::TsType::TsTypeRef(type_ref.type_ref.clone())));
+
+                let mut evaluated_refs = Vec::new();
+                TypeRefVisitor(&mut evaluated_refs).visit_ts_type(&instance.type_ann);
+                let referenced: Vec<Resolved> = evaluated_refs.iter()
+                    .filter_map(|type_ref| evaluator.resolve(type_ref))
+                    .collect();
+
+                self.extend(&referenced);
+                self.instances.push((decl, instance, context_ids(&referenced).collect()));
+            }
+            context.push(context_id);
+        }
+        context
+    }
+
+    fn extend<'r, I: IntoIterator<Item = &'r Resolved>>(&mut self, iter: I) {
+        for decl in iter {
+            let constant = decl.value && self.module.filtered_module.var_decls.contains_key(&decl.id);
+            if Rc::ptr_eq(&decl.module, self.module) && !self.include_local && !constant {
+                continue
+            }
+            let Some(context_id) = decl.context_id() else {
+                continue
+            };
+            if self.seen.insert((decl.module.specifier.clone(), context_id)) {
+                self.to_emit.push(decl.clone());
+            }
+        }
+    }
+
+    /// Records that `decl` is emitted with the rest of the module.
+    fn mark_emitted(&mut self, decl: &Resolved) {
+        if let Some(context_id) = decl.context_id() {
+            self.seen.insert((decl.module.specifier.clone(), context_id));
+        }
+    }
+
+    async fn emit<'p, C>(
+        mut self,
+        module_graph: &mut ModuleGraph,
+        prompt_writer: &mut PromptsWriter<'p, C>
+    ) -> Result<(), AnyError>
+    where
+        C: CanPush<Prompt>
+    {
+        while let Some(decl) = self.to_emit.pop() {
+            let filtered_module = &decl.module.filtered_module;
+            let comments = &decl.module.comments;
+
+            let mut type_refs = Vec::new();
+            let depth = enter_namespaces(prompt_writer, &decl.module, &decl.id);
+            prompt_writer.set_type(PromptType::TypeScript);
+
+            if let Some(type_alias_decl) = filtered_module.type_alias_decls.get(&decl.id).filter(|_| !decl.value) {
+                TypeRefVisitor(&mut type_refs).visit_ts_type_alias_decl(type_alias_decl);
+                prompt_writer.set_ast_ty(PromptAstType::TypeAliasDecl);
+                prompt_writer.set_id(&type_alias_decl.id);
+                prompt_writer.set_fmt_with_comments(&type_alias_decl.0, comments)?;
+                prompt_writer.set_source(decl.module.source_at(type_alias_decl.span.lo));
+            } else if let Some(interface_decl) = filtered_module.interface_decls.get(&decl.id).filter(|_| !decl.value) {
+                TypeRefVisitor(&mut type_refs).visit_ts_interface_decl(interface_decl);
+                prompt_writer.set_ast_ty(PromptAstType::InterfaceDecl);
+                prompt_writer.set_id(&interface_decl.id);
+                prompt_writer.set_fmt_with_comments(&interface_decl.0, comments)?;
+                prompt_writer.set_source(decl.module.source_at(interface_decl.span.lo));
+            } else if let Some(class_decl) = filtered_module.find_class_decl(&decl.id) {
+                let inner = &class_decl.class_decl;
+                TypeRefVisitor(&mut type_refs).visit_class_decl(inner);
+                prompt_writer.set_ast_ty(PromptAstType::ClassDecl);
+                prompt_writer.set_id(&inner.ident);
+                prompt_writer.set_fmt_with_comments(inner, comments)?;
+                prompt_writer.set_source(decl.module.source_at(inner.class.span.lo));
+            } else if let Some(var_decl) = filtered_module.var_decls.get(&decl.id) {
+                TypeRefVisitor(&mut type_refs).visit_var_decl(var_decl);
+                prompt_writer.set_ast_ty(PromptAstType::VarDecl);
+                prompt_writer.set_id(&var_decl.ident);
+                let var = ast::Decl::Var(Box::new(var_decl.var_decl.clone()));
+                prompt_writer.set_fmt_with_comments(&var, comments)?;
+                prompt_writer.set_source(decl.module.source_at(var_decl.var_decl.span.lo));
+            }
+
+            let closure = resolve_closure(module_graph, &decl.module, &type_refs).await;
+            prompt_writer.add_to_context(context_ids(&closure))?;
+            prompt_writer.push()?;
+            exit_namespaces(prompt_writer, depth);
+
+            self.extend(&closure);
+        }
+
+        for (decl, instance, context) in &self.instances {
+            let depth = enter_namespaces(prompt_writer, &decl.module, &decl.id);
+            prompt_writer.set_type(PromptType::TypeScript);
+            prompt_writer.set_ast_ty(PromptAstType::TypeAliasDecl);
+            prompt_writer.set_id(&instance.id);
+            prompt_writer.set_fmt_with_comments(instance, &decl.module.comments)?;
+            prompt_writer.set_source(decl.module.source_at(instance.span.lo));
+            prompt_writer.add_to_context(context.iter())?;
+            prompt_writer.push()?;
+            exit_namespaces(prompt_writer, depth);
+        }
+
+        Ok(())
+    }
+}
+
+/// Enters the scopes of the namespaces enclosing the declaration `id` of
+/// `module`, so that it is emitted as e.g. `Api.Request` (or `api.Api.Request`
+/// in a bundle). Returns how many scopes were entered.
+fn enter_namespaces<C>(
+    prompt_writer: &mut PromptsWriter<'_, C>,
+    module: &Module,
+    id: &ast::Id
+) -> usize {
+    let mut depth = 0;
+    if let Some(namespace) = &module.namespace {
+        prompt_writer.enter_raw_scope(namespace.clone());
+        depth += 1;
+    }
+
+    let path = module.filtered_module.namespaces.paths.get(id).map(Vec::as_slice).unwrap_or_default();
+    for namespace in path {
+        prompt_writer.enter_scope(namespace);
+    }
+    depth + path.len()
+}
+
+fn exit_namespaces<C>(prompt_writer: &mut PromptsWriter<'_, C>, depth: usize) {
+    for _ in 0..depth {
+        prompt_writer.exit_scope();
+    }
+}
+
+fn member_name(prop_name: &ast::PropName) -> Option<String> {
+    prop_name.as_ident().map(|ident| ident.sym.to_string())
+}
+
+/// Resolves the context of a function or method signature, adding it to the
+/// prompt being written.
+async fn add_signature_context<'p, C>(
+    module_graph: &mut ModuleGraph,
+    module: &Rc<Module>,
+    options: &CompileOptions,
+    type_refs: &[TypeRef],
+    external_decls: &mut ExternalDecls<'_>,
+    prompt_writer: &mut PromptsWriter<'p, C>
+) -> Result<(), AnyError> {
+    let closure = resolve_closure(module_graph, module, type_refs).await;
+    external_decls.extend(&closure);
+
+    let mut context: Vec<String> = context_ids(&closure).collect();
+    context.extend(external_decls.instantiate_generics(module_graph, module, type_refs, options.expand_generics).await);
+    prompt_writer.add_to_context(context.iter())?;
+    Ok(())
+}
+
+/// Inlines the small aliases that `type_refs` refer to into the signature
+/// `node`, if asked to, returning the references that are left.
+async fn inline_aliases<N>(
+    module_graph: &mut ModuleGraph,
+    module: &Rc<Module>,
+    options: &CompileOptions,
+    node: &mut N,
+    type_refs: &[TypeRef]
+) -> Vec<TypeRef>
+where
+    N: VisitMutWith<InlinedAliases>
+{
+    let Some(max_size) = options.inline_aliases else {
+        return type_refs.to_vec()
+    };
+    let mut inlined = InlinedAliases::find(module_graph, module, max_size, type_refs).await;
+    inlined.apply(node);
+    inlined.remaining(type_refs)
+}
+
+/// Warns about the parameters of the method `name`, exposed to the model,
+/// whose types are recursive: arguments of such types are only repaired down
+/// to the first recursive reference.
+async fn warn_recursive_params(
+    module_graph: &mut ModuleGraph,
+    module: &Rc<Module>,
+    name: &str,
+    params: &[ast::Param],
+    external_decls: &mut ExternalDecls<'_>
+) {
+    for param in params.iter().map(signature::Param::from_param) {
+        let Some(type_ann) = param.type_ann else {
+            continue
+        };
+        let mut type_refs = Vec::new();
+        TypeRefVisitor(&mut type_refs).visit_ts_type(&type_ann);
+
+        for decl in resolve_closure(module_graph, module, &type_refs).await {
+            if external_decls.recursion.cycle_of(module_graph, &decl).await.is_some() {
+                let param_name = param.name.as_deref().unwrap_or("<destructured>");
+                event!(Level::WARN, "parameter '{}' of '{}' has the recursive type '{}'", param_name, name, decl.id.0);
+                break
+            }
+        }
+    }
+}
+
+/// What the members of a class are compiled with: the module graph to resolve
+/// their types in, and what their prompts, validators and the declarations
+/// they refer to are written to.
+struct MemberContext<'a, 'm, 'p, C> {
+    module_graph: &'a mut ModuleGraph,
+    options: &'a CompileOptions,
+    external_decls: &'a mut ExternalDecls<'m>,
+    validators: &'a mut Validators,
+    prompt_writer: &'a mut PromptsWriter<'p, C>,
+}
+
+async fn push_class_member<C>(
+    cx: &mut MemberContext<'_, '_, '_, C>,
+    module: &Rc<Module>,
+    prop_name: &ast::PropName,
+    class_member: &filter::ClassMember
+) -> Result<(), AnyError>
+where
+    C: CanPush<Prompt>
+{
+    let MemberContext { module_graph, options, external_decls, validators, prompt_writer } = cx;
+
+    let Some(ident) = prop_name.as_ident() else {
+        event!(Level::WARN, "member {} is left out: only members named by identifiers are supported", filter::member_name_text(prop_name));
+        return Ok(())
+    };
+
+    match class_member {
+        filter::ClassMember::Method(class_method) => {
+            prompt_writer.set_type(PromptType::TypeScript);
+            prompt_writer.set_ast_ty(PromptAstType::MethodDecl);
+            prompt_writer.set_id(ident);
+
+            let mut method = class_method.class_method.clone();
+            let type_refs = inline_aliases(module_graph, module, options, &mut method, &class_method.type_refs).await;
+            filter::note_defaults(&mut method.function, &class_method.defaults);
+            prompt_writer.set_fmt_with_comments(&method, &module.comments)?;
+            prompt_writer.set_source(module.source_at(class_method.span.lo));
+
+            add_signature_context(module_graph, module, options, &type_refs, external_decls, prompt_writer).await?;
+            if class_method.used {
+                warn_recursive_params(module_graph, module, &ident.sym, &class_method.params, external_decls).await;
+            }
+
+            let evaluator = TypeEvaluator::new(module_graph);
+            prompt_writer.set_params(signature::prompt_params(&evaluator, module, &class_method.params));
+            let id = prompt_writer.scoped_id(&format!("{}", ident));
+            validators.add_signature(&evaluator, module, id, &class_method.params);
+
+            prompt_writer.push()?;
+
+            Ok(())
+        }
+        filter::ClassMember::Prop(class_prop) => {
+            prompt_writer.set_type(PromptType::TypeScript);
+            prompt_writer.set_ast_ty(PromptAstType::ClassProp);
+            prompt_writer.set_id(ident);
+            prompt_writer.set_fmt_with_comments(&class_prop.0, &module.comments)?;
+            prompt_writer.set_source(module.source_at(class_prop.span.lo));
+
+            // Like signatures, so that what props refer to is emitted when
+            // tree shaking too
+            let mut type_refs = Vec::new();
+            TypeRefVisitor(&mut type_refs).visit_class_prop(&class_prop.0);
+            add_signature_context(module_graph, module, options, &type_refs, external_decls, prompt_writer).await?;
+
+            prompt_writer.push()?;
+
+            Ok(())
+        }
+    }
+}
+
+/// The names of the methods that the class exposes with `@use`, its own and
+/// the ones it inherits.
+async fn used_methods(
+    module_graph: &mut ModuleGraph,
+    module: &Rc<Module>,
+    class_decl: &filter::ClassDecl
+) -> HashSet<String> {
+    let mut used = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut next = Some((module.clone(), class_decl.class_decl.ident.to_id()));
+    while let Some((module, id)) = next.take() {
+        if !visited.insert((module.specifier.clone(), id.clone())) {
+            break
+        }
+        let Some(class_decl) = module.filtered_module.find_class_decl(&id) else {
+            break
+        };
+
+        for (prop_name, class_member) in &class_decl.class_members {
+            if let filter::ClassMember::Method(class_method) = class_member {
+                if class_method.used {
+                    used.extend(member_name(prop_name));
+                }
+            }
+        }
+
+        if let Some(entity_name) = class_decl.class_decl.class.super_class.as_deref().and_then(filter::entity_name_of) {
+            next = module_graph.resolve(&module, &TypeName::from_entity_name(&entity_name)).await
+                .map(|base| (base.module, base.id));
+        }
+    }
+    used
+}
+
+/// Whether tree shaking leaves out `class_member`, a method that the class
+/// does not expose.
+fn is_shaken(used: Option<&HashSet<String>>, prop_name: &ast::PropName, class_member: &filter::ClassMember) -> bool {
+    match (used, class_member) {
+        (Some(used), filter::ClassMember::Method(_)) => !member_name(prop_name).is_some_and(|name| used.contains(&name)),
+        _ => false
+    }
+}
+
+/// The name that the builtin function `fn_decl` is called by (after
+/// `builtins.`), e.g. `Deno.readTextFile`.
+fn builtin_name(functions: &Module, fn_decl: &filter::FnDecl) -> String {
+    let mut name = String::new();
+    for namespace in functions.filtered_module.namespaces.paths.get(&fn_decl.ident.to_id()).into_iter().flatten() {
+        name.push_str(&format!("{}.", namespace.sym));
+    }
+    name.push_str(&fn_decl.ident.sym);
+    name
+}
+
+/// The names of the builtin functions that `module` opts in to with its
+/// pragmas, in the order they are declared in.
+async fn requested_builtins(module_graph: &mut ModuleGraph, module: &Module) -> Result<Vec<String>, AnyError> {
+    let requested = builtins::requested_functions(&module.comments);
+    if requested.is_empty() {
+        return Ok(Vec::new())
+    }
+
+    let functions = module_graph.builtin_functions().await?;
+    let names: Vec<String> = functions.filtered_module.fn_decls.iter()
+        .map(|fn_decl| builtin_name(&functions, fn_decl))
+        .collect();
+
+    // A misspelled name would otherwise leave the agent without the
+    // function, and without telling why
+    let unknown: Vec<&str> = requested.iter()
+        .filter(|requested| !names.iter().any(|name| builtins::is_requested(std::slice::from_ref(requested), name)))
+        .map(String::as_str)
+        .collect();
+    if !unknown.is_empty() {
+        return Err(anyhow!(
+            "unknown builtin functions in {} pragma of {}: {}",
+            builtins::PRAGMA,
+            module.specifier,
+            unknown.join(", ")
+        ))
+    }
+
+    Ok(names.into_iter().filter(|name| builtins::is_requested(&requested, name)).collect())
+}
+
 pub async fn compile_prompts_for_module<'p, C>(
-    parsed_module: &deno_ast::ParsedSource,
+    module_graph: &mut ModuleGraph,
+    module: &Rc<Module>,
+    options: &CompileOptions,
+    validators: &mut Validators,
     prompt_writer: &mut PromptsWriter<'p, C>
 ) -> Result<(), AnyError>
 where
     C: CanPush<Prompt>
 {
-    let module = parsed_module.module();
+    let filtered_module = &module.filtered_module;
+    let mut external_decls = ExternalDecls::new(module, options.tree_shake);
+
+    // When tree shaking, the roots are the agents (the classes exposing
+    // methods with `@use`) and the exported functions, and the rest is
+    // emitted as they refer to it
+    let mut agents = Vec::new();
+    if options.tree_shake {
+        for class_decl in &filtered_module.class_decls {
+            let used = used_methods(module_graph, module, class_decl).await;
+            if !used.is_empty() {
+                external_decls.mark_emitted(&Resolved {
+                    module: module.clone(),
+                    id: class_decl.class_decl.ident.to_id(),
+                    value: false
+                });
+                agents.push((class_decl, Some(used)));
+            }
+        }
+    } else {
+        agents.extend(filtered_module.class_decls.iter().map(|class_decl| (class_decl, None)));
+    }
 
-    let filter_params = filter::FilterParams::default();
-    let filtered_module = filter::run_filters(filter_params, module).await?;
+    let local_decls = !options.tree_shake;
 
-    for type_alias_decl in filtered_module.type_alias_decls.values() {
-        prompt_writer.set_type(PromptType::TypeScript);
-        prompt_writer.set_ast_ty(PromptAstType::TypeAliasDecl);
-        prompt_writer.set_id(&type_alias_decl.id);
-        prompt_writer.set_fmt(&type_alias_decl.0)?;
-        prompt_writer.push()?;
+    // When tree shaking, types are emitted as external declarations instead,
+    // as far as the roots refer to them
+    if local_decls {
+        // Declarations are emitted in the order they are declared in, for the
+        // output to be the same from one build to the next
+        let mut type_alias_decls: Vec<_> = filtered_module.type_alias_decls.values().collect();
+        type_alias_decls.sort_by_key(|type_alias_decl| type_alias_decl.span.lo);
+        for type_alias_decl in type_alias_decls {
+            let depth = enter_namespaces(prompt_writer, module, &type_alias_decl.id.to_id());
+            prompt_writer.set_type(PromptType::TypeScript);
+            prompt_writer.set_ast_ty(PromptAstType::TypeAliasDecl);
+            prompt_writer.set_id(&type_alias_decl.id);
+            prompt_writer.set_fmt(&type_alias_decl.0)?;
+            prompt_writer.set_source(module.source_at(type_alias_decl.span.lo));
+            prompt_writer.push()?;
+            exit_namespaces(prompt_writer, depth);
+        }
+
+        let mut interface_decls: Vec<_> = filtered_module.interface_decls.values().collect();
+        interface_decls.sort_by_key(|interface_decl| interface_decl.span.lo);
+        for interface_decl in interface_decls {
+            let depth = enter_namespaces(prompt_writer, module, &interface_decl.id.to_id());
+            prompt_writer.set_type(PromptType::TypeScript);
+            prompt_writer.set_ast_ty(PromptAstType::InterfaceDecl);
+            prompt_writer.set_id(&interface_decl.id);
+            prompt_writer.set_fmt(&interface_decl.0)?;
+            prompt_writer.set_source(module.source_at(interface_decl.span.lo));
+            prompt_writer.push()?;
+            exit_namespaces(prompt_writer, depth);
+        }
     }
 
-    for fn_decl in &filtered_module.fn_decls {
+    for fn_decl in filtered_module.fn_decls.iter().filter(|fn_decl| local_decls || fn_decl.exported) {
+        let depth = enter_namespaces(prompt_writer, module, &fn_decl.ident.to_id());
         prompt_writer.set_type(PromptType::TypeScript);
         prompt_writer.set_ast_ty(PromptAstType::FnDecl);
         prompt_writer.set_id(&fn_decl.ident);
-        prompt_writer.set_fmt(&fn_decl.fn_decl)?;
 
-        let closure = filtered_module
-            .find_closure_of_type_refs(&fn_decl.type_refs)
-            .into_iter()
-            .map(|id| format!("type_alias_decl.{}", ast::Ident::from(id)));
-        prompt_writer.add_to_context(closure)?;
+        let mut function = fn_decl.fn_decl.clone();
+        let type_refs = inline_aliases(module_graph, module, options, &mut function, &fn_decl.type_refs).await;
+        filter::note_defaults(&mut function.function, &fn_decl.defaults);
+        prompt_writer.set_fmt(&function)?;
+        prompt_writer.set_source(module.source_at(fn_decl.function.span.lo));
+
+        add_signature_context(module_graph, module, options, &type_refs, &mut external_decls, prompt_writer).await?;
+
+        let evaluator = TypeEvaluator::new(module_graph);
+        prompt_writer.set_params(signature::prompt_params(&evaluator, module, &fn_decl.params));
+        let id = prompt_writer.scoped_id(&format!("{}", fn_decl.ident));
+        validators.add_signature(&evaluator, module, id, &fn_decl.params);
 
         prompt_writer.push()?;
+        exit_namespaces(prompt_writer, depth);
     }
 
-    for class_decl in &filtered_module.class_decls {
+    for (class_decl, used) in agents {
         let inner = &class_decl.class_decl;
 
+        let super_class = match inner.class.super_class.as_deref().and_then(filter::entity_name_of) {
+            Some(entity_name) => module_graph.resolve(module, &TypeName::from_entity_name(&entity_name)).await,
+            None => None
+        };
+
+        let mut supertypes: Vec<Resolved> = super_class.iter().cloned().collect();
+        for implements in &inner.class.implements {
+            if let Some(entity_name) = filter::entity_name_of(&implements.expr) {
+                supertypes.extend(module_graph.resolve(module, &TypeName::from_entity_name(&entity_name)).await);
+            }
+        }
+
+        let depth = enter_namespaces(prompt_writer, module, &inner.ident.to_id());
+
         prompt_writer.set_type(PromptType::TypeScript);
         prompt_writer.set_ast_ty(PromptAstType::ClassDecl);
         prompt_writer.set_id(&inner.ident);
         prompt_writer.set_fmt(&inner)?;
+        prompt_writer.set_source(module.source_at(inner.class.span.lo));
+        prompt_writer.add_to_context(context_ids(&supertypes))?;
         prompt_writer.push()?;
 
+        external_decls.extend(&supertypes);
+
         prompt_writer.enter_scope(&inner.ident);
 
-        for (prop_name, class_member) in &class_decl.class_members {
-            match class_member {
-                filter::ClassMember::Method(class_method) => {
-                    prompt_writer.set_type(PromptType::TypeScript);
-                    prompt_writer.set_ast_ty(PromptAstType::MethodDecl);
-                    prompt_writer.set_id(prop_name.as_ident().unwrap());
-                    prompt_writer.set_fmt(&class_method.class_method)?;
-
-                    let closure = filtered_module
-                        .find_closure_of_type_refs(&class_method.type_refs)
-                        .into_iter()
-                        .map(|id| format!("type_alias_decl.{}", ast::Ident::from(id)));
-                    prompt_writer.add_to_context(closure)?;
-
-                    prompt_writer.push()?;
+        let mut cx = MemberContext {
+            module_graph: &mut *module_graph,
+            options,
+            external_decls: &mut external_decls,
+            validators: &mut *validators,
+            prompt_writer: &mut *prompt_writer
+        };
+        let mut defined = HashSet::new();
+
+        for (prop_name, class_member) in class_decl.members_in_order() {
+            defined.extend(member_name(prop_name));
+            if is_shaken(used.as_ref(), prop_name, class_member) {
+                continue
+            }
+            push_class_member(&mut cx, module, prop_name, class_member).await?;
+        }
+
+        // Walk up the inheritance chain, emitting the members that are not
+        // overridden under the scope of this class.
+        let mut visited = HashSet::new();
+        let mut next_base = super_class;
+        while let Some(base) = next_base.take() {
+            if !visited.insert((base.module.specifier.clone(), base.id.clone())) {
+                break
+            }
+
+            let Some(base_decl) = base.module.filtered_module.find_class_decl(&base.id) else {
+                break
+            };
+
+            for (prop_name, class_member) in base_decl.members_in_order() {
+                if member_name(prop_name).map(|name| defined.insert(name)) == Some(false) {
+                    continue
                 }
-                filter::ClassMember::Prop(class_prop) => {
-                    prompt_writer.set_type(PromptType::TypeScript);
-                    prompt_writer.set_ast_ty(PromptAstType::ClassProp);
-                    prompt_writer.set_id(prop_name.as_ident().unwrap());
-                    prompt_writer.set_fmt(&class_prop.0)?;
-                    prompt_writer.push()?;
+                if is_shaken(used.as_ref(), prop_name, class_member) {
+                    continue
                 }
+                push_class_member(&mut cx, &base.module, prop_name, class_member).await?;
+            }
+
+            if let Some(entity_name) = base_decl.class_decl.class.super_class.as_deref().and_then(filter::entity_name_of) {
+                next_base = cx.module_graph.resolve(&base.module, &TypeName::from_entity_name(&entity_name)).await;
             }
         }
 
         prompt_writer.exit_scope();
+        exit_namespaces(prompt_writer, depth);
+    }
+
+    let requested = requested_builtins(module_graph, module).await?;
+    if !requested.is_empty() {
+        let functions = module_graph.builtin_functions().await?;
+        for fn_decl in &functions.filtered_module.fn_decls {
+            let name = builtin_name(&functions, fn_decl);
+            if !requested.contains(&name) {
+                continue
+            }
+
+            // Shown the way the agent calls it
+            let mut builtin = fn_decl.fn_decl.clone();
+            builtin.declare = false;
+            builtin.ident.sym = format!("builtins.{}", name).into();
+            let type_refs = inline_aliases(module_graph, &functions, options, &mut builtin, &fn_decl.type_refs).await;
+            filter::note_defaults(&mut builtin.function, &fn_decl.defaults);
+
+            prompt_writer.set_type(PromptType::TypeScript);
+            prompt_writer.set_ast_ty(PromptAstType::FnDecl);
+            prompt_writer.set_raw_id(builtin.ident.sym.to_string());
+            prompt_writer.set_fmt_with_comments(&builtin, &functions.comments)?;
+            prompt_writer.set_source(functions.source_at(fn_decl.function.span.lo));
+
+            add_signature_context(module_graph, &functions, options, &type_refs, &mut external_decls, prompt_writer).await?;
+
+            let evaluator = TypeEvaluator::new(module_graph);
+            prompt_writer.set_params(signature::prompt_params(&evaluator, &functions, &fn_decl.params));
+            let id = builtin.ident.sym.to_string();
+            validators.add_signature(&evaluator, &functions, id, &fn_decl.params);
+
+            prompt_writer.push()?;
+        }
     }
 
+    external_decls.emit(module_graph, prompt_writer).await?;
+
     Ok(())
 }
 
+/// Where the prompts of `specifier` are written, relative to the output
+/// directory: named after the module, or at the path of the module relative to
+/// `root`. Remote modules are put under their host (e.g. `deno.land/x/...`).
+#[cfg(not(target_arch = "wasm32"))]
+fn output_path(specifier: &ModuleSpecifier, root: Option<&Path>) -> Result<PathBuf, AnyError> {
+    let Some(root) = root else {
+        let prompts_path = Path::new(specifier.path()).with_extension(PROMPTS_EXT);
+        let file_name = prompts_path.file_name()
+            .ok_or_else(|| anyhow!("not a module path: {specifier}"))?;
+        return Ok(PathBuf::from(file_name))
+    };
+
+    let relative = if specifier.scheme() == "file" {
+        let path = specifier.to_file_path()
+            .map_err(|_| anyhow!("not a local path: {specifier}"))?;
+        // Both are canonicalized, so that e.g. `--root ..` or a symlinked
+        // input compare as the same tree
+        let root = root.canonicalize()
+            .map_err(|err| anyhow!("cannot resolve the root {}: {err}", root.display()))?;
+        let path = path.canonicalize()
+            .map_err(|err| anyhow!("cannot resolve {specifier}: {err}"))?;
+        path.strip_prefix(&root)
+            .map_err(|_| anyhow!("{specifier} is not under the root {}", root.display()))?
+            .to_path_buf()
+    } else {
+        let host = specifier.host_str().unwrap_or(specifier.scheme());
+        let mut relative = match specifier.port() {
+            Some(port) => PathBuf::from(format!("{}_{}", host, port)),
+            None => PathBuf::from(host)
+        };
+        relative.extend(specifier.path_segments().into_iter().flatten().filter(|segment| !segment.is_empty()));
+        relative
+    };
+
+    Ok(relative.with_extension(PROMPTS_EXT))
+}
+
+/// The output paths of `specifiers` (see [`output_path`]), which must all be
+/// different.
+#[cfg(not(target_arch = "wasm32"))]
+fn output_paths(specifiers: &[ModuleSpecifier], root: Option<&Path>) -> Result<Vec<PathBuf>, AnyError> {
+    let mut written_by: HashMap<PathBuf, &ModuleSpecifier> = HashMap::new();
+    let mut paths = Vec::new();
+    for specifier in specifiers {
+        let path = output_path(specifier, root)?;
+        if let Some(other) = written_by.insert(path.clone(), specifier) {
+            return Err(anyhow!(
+                "{} and {} would both be written to {} (use --root to keep the input tree)",
+                other,
+                specifier,
+                path.display()
+            ))
+        }
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// The output directory, to which files are written or, when checking, in
+/// which they are compared with the ones already there.
+#[cfg(not(target_arch = "wasm32"))]
+struct OutputDir<'o> {
+    base: &'o Path,
+    check: bool,
+    /// The files that differ from the ones that would be written, when
+    /// checking
+    stale: Vec<PathBuf>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'o> OutputDir<'o> {
+    fn new(base: &'o Path, check: bool) -> Self {
+        Self {
+            base,
+            check,
+            stale: Vec::new()
+        }
+    }
+
+    /// Writes `contents` to the file `path` (relative to the directory),
+    /// creating the directories leading to it.
+    fn write(&mut self, path: &Path, contents: &[u8]) -> Result<(), AnyError> {
+        let path = self.base.join(path);
+        if self.check {
+            // Missing files are as stale as outdated ones
+            if std::fs::read(&path).ok().as_deref() != Some(contents) {
+                self.stale.push(path);
+            }
+            return Ok(())
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Ok(std::fs::write(path, contents)?)
+    }
+}
+
+async fn source_of(module_graph: &mut ModuleGraph, module: &Module) -> Result<Source, AnyError> {
+    Ok(Source {
+        specifier: module.specifier.to_string(),
+        hash: module.hash.clone(),
+        namespace: module.namespace.clone(),
+        builtins: Some(requested_builtins(module_graph, module).await?)
+    })
+}
+
+/// Makes the specifiers of the local modules among `meta.sources` relative to
+/// `base` (the root, or the output directory), so that prompts do not depend
+/// on where they were built. Remote modules keep their URLs.
+#[cfg(not(target_arch = "wasm32"))]
+fn relativize_sources(meta: &mut Meta, base: &Path) -> Result<(), AnyError> {
+    let base = base.canonicalize().or_else(|_| std::path::absolute(base))?;
+    for source in &mut meta.sources {
+        let specifier = ModuleSpecifier::parse(&source.specifier)?;
+        if specifier.scheme() != "file" {
+            continue
+        }
+        let path = specifier.to_file_path()
+            .map_err(|_| anyhow!("not a local path: {specifier}"))?;
+        let path = path.canonicalize().unwrap_or(path);
+        source.specifier = relative_path(&base, &path);
+    }
+    Ok(())
+}
+
+/// The path `path` relative to the directory `base` (both absolute), with `/`
+/// separators (e.g. `../src/agent.ts`).
+#[cfg(not(target_arch = "wasm32"))]
+fn relative_path(base: &Path, path: &Path) -> String {
+    let base: Vec<_> = base.components().collect();
+    let path: Vec<_> = path.components().collect();
+    let common = base.iter().zip(&path).take_while(|(a, b)| a == b).count();
+
+    let mut segments = vec![String::from(".."); base.len() - common];
+    segments.extend(path[common..].iter().map(|component| component.as_os_str().to_string_lossy().into_owned()));
+    segments.join("/")
+}
+
+/// Writes `meta`, `prompts` and `validators` as the exports of a prompts
+/// module, typed by the declarations `types` (a path relative to the module),
+/// if any.
+fn write_prompts(
+    writer: &mut dyn Write,
+    meta: &Meta,
+    prompts: &Prompts,
+    validators: &Validators,
+    types: Option<&Path>
+) -> Result<(), AnyError> {
+    if let Some(types) = types {
+        writeln!(writer, "/// <reference types=\"./{}\" />", types.display())?;
+    }
+    writeln!(writer, "export const meta = {};", serde_json::to_string(meta)?)?;
+    write!(writer, "export const ast = ")?;
+    serde_json::to_writer_pretty(&mut *writer, prompts)?;
+    writeln!(writer, ";")?;
+
+    if !validators.is_empty() {
+        writeln!(writer)?;
+        validators.write(writer)?;
+    }
+    Ok(())
+}
+
+/// Writes the prompts module `path` (relative to the directory `output`), and
+/// its declarations next to it.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_prompts_files(
+    output: &mut OutputDir<'_>,
+    path: &Path,
+    meta: &Meta,
+    prompts: &Prompts,
+    validators: &Validators
+) -> Result<(), AnyError> {
+    let types_path = path.with_extension(DECLARATIONS_EXT);
+    let mut buf = Vec::new();
+    crate::prompts::write_declarations(&mut buf, prompts, !validators.is_empty())?;
+    output.write(&types_path, &buf)?;
+
+    let mut buf = Vec::new();
+    write_prompts(&mut buf, meta, prompts, validators, types_path.file_name().map(Path::new))?;
+    output.write(path, &buf)
+}
+
+/// Writes `prompts` and `validators` as an object with the exports of a
+/// prompts module as properties.
+#[cfg(not(target_arch = "wasm32"))]
+fn write_prompts_object(writer: &mut dyn Write, meta: &Meta, prompts: &Prompts, validators: &Validators) -> Result<(), AnyError> {
+    write!(writer, "{{\n\"meta\": {},\n\"ast\": ", serde_json::to_string(meta)?)?;
+    serde_json::to_writer_pretty(&mut *writer, prompts)?;
+    if !validators.is_empty() {
+        write!(writer, ",\n\"validators\": ")?;
+        validators.write_expr(writer)?;
+    }
+    write!(writer, "\n}}")?;
+    Ok(())
+}
+
+/// Compiles the module `specifier`, without writing anything.
+pub async fn compile_module(
+    module_graph: &mut ModuleGraph,
+    specifier: &ModuleSpecifier,
+    options: &CompileOptions
+) -> Result<(Rc<Module>, Prompts, Validators), AnyError> {
+    let module = module_graph.load(specifier).await?;
+
+    let mut prompts: Vec<Prompt> = Vec::new();
+    let mut prompt_writer = PromptsWriter::new(&mut prompts, &module.comments);
+
+    event!(Level::INFO, "building for {}", specifier);
+
+    let mut validators = Validators::default();
+    compile_prompts_for_module(module_graph, &module, options, &mut validators, &mut prompt_writer).await?;
+    check_collisions(&prompts)?;
+
+    Ok((module, Prompts(prompts), validators))
+}
+
+/// Errors if declarations of different modules are emitted under the same id
+/// (e.g. two imported `Options`), as neither the model nor the runtime could
+/// tell them apart. Bundles qualify ids by module, so they do not collide.
+fn check_collisions(prompts: &[Prompt]) -> Result<(), AnyError> {
+    let mut declared_by: HashMap<(Option<PromptAstType>, &str), &str> = HashMap::new();
+    for prompt in prompts {
+        let Some(source) = &prompt.source else {
+            continue
+        };
+        let key = (prompt.ast_ty, &*prompt.id.0);
+        match declared_by.get(&key) {
+            Some(other) if *other != source.specifier => return Err(anyhow!(
+                "{} is declared by both {} and {}: rename one of them, or use --bundle to qualify declarations by module",
+                prompt.id.0,
+                other,
+                source.specifier
+            )),
+            _ => {
+                declared_by.insert(key, &source.specifier);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Compiles the module `specifier` into the text of its prompts module, as it
+/// is written to stdout.
+pub async fn compile_to_string(
+    module_graph: &mut ModuleGraph,
+    specifier: &ModuleSpecifier,
+    options: &CompileOptions
+) -> Result<String, AnyError> {
+    let (module, prompts, validators) = compile_module(module_graph, specifier, options).await?;
+    let meta = Meta::new(vec![source_of(module_graph, &module).await?], Some(options.fingerprint()));
+
+    let mut buf = Vec::new();
+    write_prompts(&mut buf, &meta, &prompts, &validators, None)?;
+    Ok(String::from_utf8(buf)?)
+}
+
+/// Compiles the modules `specifiers`, writing their prompts modules to the
+/// directory `output` (or stdout).
+///
+/// When checking, nothing is written and the files of `output` that differ
+/// from the ones that would be are returned instead.
+#[cfg(not(target_arch = "wasm32"))]
 pub async fn compile_prompts_for_specifiers<P>(
     specifiers: &[ModuleSpecifier],
-    output: Option<P>
-) -> Result<(), AnyError>
+    output: Option<P>,
+    options: &CompileOptions
+) -> Result<Vec<PathBuf>, AnyError>
 where
     P: AsRef<Path>
 {
-    for specifier in specifiers {
-        let module_source = if specifier.scheme().contains("http") {
-            let resp = reqwest::get(specifier.to_string()).await?;
-            if resp.status() == 200 {
-                resp.text().await?
-            } else {
-                return Err(anyhow!("could not retrieve {specifier}: {}", resp.status()))
-            }
+    if options.check && output.is_none() {
+        return Err(anyhow!("checking needs the output directory (-o) to compare with"))
+    }
+    if options.bundle && options.root.is_some() {
+        return Err(anyhow!("a bundle is a single file, it cannot mirror the tree under the root"))
+    }
+    let mut output = output.as_ref().map(|base| OutputDir::new(base.as_ref(), options.check));
+    let sources_base = match (&options.root, &output) {
+        (Some(root), _) => root.clone(),
+        (None, Some(output)) => output.base.to_path_buf(),
+        (None, None) => PathBuf::from(".")
+    };
+
+    if options.bundle {
+        compile_bundle(specifiers, output.as_mut(), &sources_base, options).await?;
+        return Ok(output.map(|output| output.stale).unwrap_or_default())
+    }
+
+    // Checked before anything is written
+    let output_paths = match output {
+        Some(_) => output_paths(specifiers, options.root.as_deref())?,
+        None => Vec::new()
+    };
+
+    let compiled = compile_modules(specifiers, options).await?;
+
+    // A module cannot have several `ast` exports, so the prompts of several
+    // modules written to stdout are keyed by their specifiers instead
+    let keyed = output.is_none() && specifiers.len() > 1;
+    if keyed {
+        writeln!(std::io::stdout(), "export const modules = {{")?;
+    }
+
+    for (i, (specifier, (mut meta, prompts, validators))) in specifiers.iter().zip(compiled).enumerate() {
+        relativize_sources(&mut meta, &sources_base)?;
+        if keyed {
+            let mut writer = std::io::stdout();
+            write!(writer, "{}: ", serde_json::to_string(specifier.as_str())?)?;
+            write_prompts_object(&mut writer, &meta, &prompts, &validators)?;
+            writeln!(writer, ",")?;
         } else {
-            tokio::fs::read_to_string(specifier.to_file_path().unwrap()).await?
+            match (output.as_mut(), output_paths.get(i)) {
+                (Some(output), Some(path)) => write_prompts_files(output, path, &meta, &prompts, &validators)?,
+                _ => write_prompts(&mut std::io::stdout(), &meta, &prompts, &validators, None)?
+            }
+        }
+    }
+
+    if keyed {
+        writeln!(std::io::stdout(), "}};")?;
+    }
+
+    Ok(output.map(|output| output.stale).unwrap_or_default())
+}
+
+/// Compiles the modules `specifiers` separately, up to `options.jobs` of them
+/// at a time. They are fetched and parsed into a shared cache first; modules
+/// are not `Send`, so each is then compiled on a blocking thread, with a graph
+/// of its own over the cache. The results are in the order of `specifiers`,
+/// and all the modules are compiled even if some of them fail.
+#[cfg(not(target_arch = "wasm32"))]
+async fn compile_modules(
+    specifiers: &[ModuleSpecifier],
+    options: &CompileOptions
+) -> Result<Vec<(Meta, Prompts, Validators)>, AnyError> {
+    let cache = SourceCache::default();
+    prefetch(&cache, specifiers, options).await;
+
+    let handle = tokio::runtime::Handle::current();
+    let mut tasks = JoinSet::new();
+    let mut results = Vec::new();
+    results.resize_with(specifiers.len(), || None);
+
+    for (i, specifier) in specifiers.iter().enumerate() {
+        if tasks.len() >= options.jobs() {
+            let (i, result) = tasks.join_next().await.unwrap()?;
+            results[i] = Some(result);
+        }
+
+        let (handle, cache, specifier, options) = (handle.clone(), cache.clone(), specifier.clone(), options.clone());
+        tasks.spawn_blocking(move || {
+            let result = handle.block_on(async {
+                let mut module_graph = ModuleGraph::default().with_cache(cache);
+                let (module, prompts, validators) = compile_module(&mut module_graph, &specifier, &options).await?;
+                let meta = Meta::new(vec![source_of(&mut module_graph, &module).await?], Some(options.fingerprint()));
+                Ok((meta, prompts, validators))
+            });
+            (i, result)
+        });
+    }
+    while let Some(joined) = tasks.join_next().await {
+        let (i, result) = joined?;
+        results[i] = Some(result);
+    }
+
+    collect_results(specifiers, results.into_iter().map(Option::unwrap))
+}
+
+/// Fetches and parses the modules `specifiers` and the local modules they
+/// import, up to `options.jobs` of them at a time, into `cache`. Remote imports
+/// are left for the graphs to load if they need them, and modules that fail to
+/// load for compiling them to report why.
+#[cfg(not(target_arch = "wasm32"))]
+async fn prefetch(cache: &SourceCache, specifiers: &[ModuleSpecifier], options: &CompileOptions) {
+    let semaphore = Arc::new(Semaphore::new(options.jobs()));
+    let mut tasks = JoinSet::new();
+    let mut seen = HashSet::new();
+    let mut spawn = |tasks: &mut JoinSet<_>, specifier: ModuleSpecifier| {
+        if !seen.insert(specifier.clone()) {
+            return
+        }
+        let semaphore = semaphore.clone();
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await?;
+            let module_source = crate::graph::fetch_module_source(&specifier).await?;
+            let parsed_source = parse_module(specifier.to_string(), module_source).await?;
+            Ok::<_, AnyError>((specifier, parsed_source))
+        });
+    };
+
+    for specifier in specifiers {
+        spawn(&mut tasks, specifier.clone());
+    }
+    while let Some(joined) = tasks.join_next().await {
+        let Ok(Ok((specifier, parsed_source))) = joined else {
+            continue
         };
+        for dependency in imported_by(&specifier, &parsed_source) {
+            if dependency.scheme() == "file" {
+                spawn(&mut tasks, dependency);
+            }
+        }
+        cache.insert(specifier, parsed_source);
+    }
+}
 
-        let parsed_module = parse_module(specifier.to_string(), module_source).await?;
-        let comments = parsed_module.comments().as_single_threaded();
+/// The modules that the module `specifier` imports (or re-exports) from.
+#[cfg(not(target_arch = "wasm32"))]
+fn imported_by(specifier: &ModuleSpecifier, parsed_source: &deno_ast::ParsedSource) -> Vec<ModuleSpecifier> {
+    parsed_source.module().body.iter()
+        .filter_map(|item| match item {
+            ast::ModuleItem::ModuleDecl(ast::ModuleDecl::Import(import)) => Some(&import.src),
+            ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportAll(export)) => Some(&export.src),
+            ast::ModuleItem::ModuleDecl(ast::ModuleDecl::ExportNamed(export)) => export.src.as_ref(),
+            _ => None
+        })
+        .filter_map(|src| specifier.join(&src.value).ok())
+        .collect()
+}
 
-        let mut prompts: Vec<Prompt> = Vec::new();
-        let mut prompt_writer = PromptsWriter::new(&mut prompts, &comments);
+/// The values of `results`, or an error listing all the modules (in
+/// `specifiers`) that failed.
+#[cfg(not(target_arch = "wasm32"))]
+fn collect_results<T>(
+    specifiers: &[ModuleSpecifier],
+    results: impl IntoIterator<Item = Result<T, AnyError>>
+) -> Result<Vec<T>, AnyError> {
+    let mut values = Vec::new();
+    let mut errors = Vec::new();
+    for (specifier, result) in specifiers.iter().zip(results) {
+        match result {
+            Ok(value) => values.push(value),
+            Err(err) => errors.push((specifier, err))
+        }
+    }
 
-        event!(Level::INFO, "building for {}", specifier);
+    match errors.len() {
+        0 => Ok(values),
+        1 if specifiers.len() == 1 => Err(errors.pop().unwrap().1),
+        _ => {
+            let errors: Vec<_> = errors.iter()
+                .map(|(specifier, err)| format!("  {}: {}", specifier, err))
+                .collect();
+            Err(anyhow!("{} of {} modules failed:\n{}", errors.len(), specifiers.len(), errors.join("\n")))
+        }
+    }
+}
 
-        compile_prompts_for_module(&parsed_module, &mut prompt_writer).await?;
+/// Compiles the modules `specifiers` into a single prompts module, in which
+/// the ids of the prompts are qualified by the namespaces of their modules
+/// (e.g. `agent.Agent#2.search#0`).
+#[cfg(not(target_arch = "wasm32"))]
+async fn compile_bundle(
+    specifiers: &[ModuleSpecifier],
+    output: Option<&mut OutputDir<'_>>,
+    sources_base: &Path,
+    options: &CompileOptions
+) -> Result<(), AnyError> {
+    let (meta, prompts, validators) = bundle_modules(specifiers, sources_base, options).await?;
+    match output {
+        Some(output) => write_prompts_files(output, Path::new(BUNDLE_FILE_NAME), &meta, &prompts, &validators),
+        None => write_prompts(&mut std::io::stdout(), &meta, &prompts, &validators, None)
+    }
+}
 
-        let prompts = Prompts(prompts);
+/// The meta header, prompts and validators of the bundle of the modules
+/// `specifiers`, see [`compile_bundle`].
+#[cfg(not(target_arch = "wasm32"))]
+async fn bundle_modules(
+    specifiers: &[ModuleSpecifier],
+    sources_base: &Path,
+    options: &CompileOptions
+) -> Result<(Meta, Prompts, Validators), AnyError> {
+    let cache = SourceCache::default();
+    prefetch(&cache, specifiers, options).await;
+    let mut module_graph = ModuleGraph::namespaced().with_cache(cache);
 
-        let mut writer: Box<dyn Write> = if let Some(base) = output.as_ref() {
-            let prompts_path = Path::new(specifier.path()).with_extension(PROMPTS_EXT);
-            let output_path = base.as_ref().join(prompts_path.file_name().unwrap());
-            Box::new(std::fs::File::create(output_path)?)
-        } else {
-            Box::new(std::io::stdout())
+    let mut prompts: Vec<Prompt> = Vec::new();
+    let mut bundled: HashSet<(Option<PromptAstType>, String)> = HashSet::new();
+    let mut validators = Validators::default();
+    let mut sources = Vec::new();
+    let mut modules = Vec::new();
+    for specifier in specifiers {
+        modules.push(module_graph.load(specifier).await);
+    }
+    for (specifier, module) in specifiers.iter().zip(collect_results(specifiers, modules)?) {
+        sources.push(source_of(&mut module_graph, &module).await?);
+
+        let mut module_prompts: Vec<Prompt> = Vec::new();
+        let mut prompt_writer = PromptsWriter::new(&mut module_prompts, &module.comments);
+
+        event!(Level::INFO, "bundling {}", specifier);
+
+        compile_prompts_for_module(&mut module_graph, &module, options, &mut validators, &mut prompt_writer).await?;
+
+        // Declarations that modules share (e.g. the types they import from
+        // the same module) only appear once
+        for prompt in module_prompts {
+            if bundled.insert((prompt.ast_ty, prompt.id.0.clone())) {
+                prompts.push(prompt);
+            }
+        }
+    }
+
+    let mut meta = Meta::new(sources, Some(options.fingerprint()));
+    relativize_sources(&mut meta, sources_base)?;
+    Ok((meta, Prompts(prompts), validators))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+    use crate::inspect;
+
+    /// Compiles `agent.ts` among the modules `sources` (by file name).
+    async fn compile(sources: &[(&str, &str)], options: &CompileOptions) -> Result<Prompts, AnyError> {
+        let sources = sources.iter()
+            .map(|(name, source)| (ModuleSpecifier::parse(&format!("file:///{}", name)).unwrap(), source.to_string()))
+            .collect();
+        let mut module_graph = ModuleGraph::with_sources(sources);
+        let specifier = ModuleSpecifier::parse("file:///agent.ts").unwrap();
+        compile_module(&mut module_graph, &specifier, options).await.map(|(_, prompts, _)| prompts)
+    }
+
+    /// The prompts by their ids without syntax contexts (e.g.
+    /// `interface_decl.Item`).
+    fn by_id(prompts: &Prompts) -> BTreeMap<String, &Prompt> {
+        prompts.0.iter()
+            .map(|prompt| (format!("{}.{}", inspect::ast_ty_name(prompt), inspect::plain_id(&prompt.id)), prompt))
+            .collect()
+    }
+
+    /// The context of the prompt `id` (as in [`by_id`]), without syntax contexts.
+    fn context_of(prompts: &Prompts, id: &str) -> Vec<String> {
+        by_id(prompts)[id].context.iter().map(|context_id| inspect::plain_id(context_id)).collect()
+    }
+
+    #[tokio::test]
+    async fn emit_inherited_members() {
+        let base = "export interface Options { verbose: boolean }\nexport class BaseAgent {\n    @use() status(o: Options): string { return \"\" }\n}\n";
+        let agent = "import { BaseAgent } from \"./base.ts\";\nexport class Agent extends BaseAgent {\n    @use() run(n: number): void {}\n}\n";
+        let prompts = compile(&[("agent.ts", agent), ("base.ts", base)], &CompileOptions::default()).await.unwrap();
+
+        let prompts_by_id = by_id(&prompts);
+        assert_eq!(prompts_by_id["class_decl.Agent"].fmt.0, "class Agent extends BaseAgent {\n}");
+        assert!(prompts_by_id.contains_key("method_decl.Agent.run"));
+        assert_eq!(prompts_by_id["method_decl.Agent.status"].fmt.0, "status(o: Options): string;");
+        assert_eq!(context_of(&prompts, "class_decl.Agent"), ["class_decl.BaseAgent"]);
+        assert_eq!(context_of(&prompts, "method_decl.Agent.status"), ["interface_decl.Options"]);
+        assert!(prompts_by_id.contains_key("interface_decl.Options"));
+    }
+
+    #[tokio::test]
+    async fn resolve_default_imports() {
+        let base = "export default interface Base { id: string }\n";
+        let agent = "import Base from \"./base.ts\";\ninterface Item extends Base { name: string }\nexport function get(): Item { return null! }\n";
+        let prompts = compile(&[("agent.ts", agent), ("base.ts", base)], &CompileOptions::default()).await.unwrap();
+
+        assert_eq!(context_of(&prompts, "fn_decl.get"), ["interface_decl.Item", "interface_decl.Base"]);
+        assert_eq!(by_id(&prompts)["interface_decl.Base"].fmt.0, "interface Base {\n    id: string;\n}");
+    }
+
+    #[tokio::test]
+    async fn reject_colliding_declarations() {
+        let a = "export interface Options { verbose: boolean }\n";
+        let b = "export interface Options { quiet: boolean }\n";
+        let agent = "import { Options } from \"./a.ts\";\nimport { Options as Other } from \"./b.ts\";\nexport function f(a: Options, b: Other) {}\n";
+        let err = compile(&[("agent.ts", agent), ("a.ts", a), ("b.ts", b)], &CompileOptions::default()).await.err().unwrap();
+        assert!(err.to_string().contains("is declared by both"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn scope_type_params() {
+        let agent = "interface T { x: number }\nexport function first<T>(items: T[]): T { return items[0] }\nexport function other(t: T) {}\n";
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+
+        assert_eq!(context_of(&prompts, "fn_decl.first"), Vec::<String>::new());
+        assert_eq!(context_of(&prompts, "fn_decl.other"), ["interface_decl.T"]);
+    }
+
+    #[tokio::test]
+    async fn expand_generic_instances() {
+        let agent = "type Page<T> = { items: T[] };\ninterface Item { id: string }\nexport function list(page: Page<Item>) {}\n";
+
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+        assert_eq!(context_of(&prompts, "fn_decl.list"), ["interface_decl.Item", "type_alias_decl.Page"]);
+        assert!(!by_id(&prompts).contains_key("type_alias_decl.Page<Item>"));
+
+        let options = CompileOptions { expand_generics: true, ..Default::default() };
+        let prompts = compile(&[("agent.ts", agent)], &options).await.unwrap();
+        assert_eq!(
+            context_of(&prompts, "fn_decl.list"),
+            ["interface_decl.Item", "type_alias_decl.Page", "type_alias_decl.Page<Item>"]
+        );
+        assert_eq!(by_id(&prompts)["type_alias_decl.Page<Item>"].fmt.0, "type Page<Item> = {\n    items: Item[];\n};");
+        assert_eq!(context_of(&prompts, "type_alias_decl.Page<Item>"), ["interface_decl.Item"]);
+    }
+
+    #[tokio::test]
+    async fn resolve_qualified_names() {
+        let shapes = "export namespace Shapes {\n    export interface Circle { r: number }\n}\nexport interface Point { x: number }\n";
+        let agent = "import { Shapes } from \"./shapes.ts\";\nimport * as Geo from \"./shapes.ts\";\nnamespace Local {\n    export type Id = string;\n}\nexport function f(a: Shapes.Circle, b: Geo.Shapes.Circle, c: Geo.Point, d: Local.Id) {}\n";
+        let prompts = compile(&[("agent.ts", agent), ("shapes.ts", shapes)], &CompileOptions::default()).await.unwrap();
+
+        assert_eq!(
+            context_of(&prompts, "fn_decl.f"),
+            ["type_alias_decl.Local.Id", "interface_decl.Shapes.Circle", "interface_decl.Point"]
+        );
+        let prompts_by_id = by_id(&prompts);
+        assert_eq!(prompts_by_id["interface_decl.Shapes.Circle"].fmt.0, "interface Circle {\n    r: number;\n}");
+        assert!(prompts_by_id.contains_key("type_alias_decl.Local.Id"));
+    }
+
+    #[tokio::test]
+    async fn follow_typeof_queries() {
+        let agent = "interface LIMITS { other: string }\nconst LIMITS = { max: 10, tags: [\"a\", \"b\"] } as const;\nconst DEFAULTS = { max: 10, nested: { on: true } };\nexport function f(l: typeof LIMITS, d: typeof DEFAULTS) {}\n";
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+
+        // In the value namespace only, so not the interface
+        assert_eq!(context_of(&prompts, "fn_decl.f"), ["var_decl.DEFAULTS", "var_decl.LIMITS"]);
+        let prompts_by_id = by_id(&prompts);
+        assert_eq!(
+            prompts_by_id["var_decl.LIMITS"].fmt.0,
+            "const LIMITS: {\n    readonly max: 10;\n    readonly tags: readonly [\"a\", \"b\"];\n};"
+        );
+        assert_eq!(
+            prompts_by_id["var_decl.DEFAULTS"].fmt.0,
+            "const DEFAULTS: {\n    max: number;\n    nested: {\n        on: boolean;\n    };\n};"
+        );
+    }
+
+    /// The builtin functions among `prompts`.
+    fn builtins_of(prompts: &Prompts) -> Vec<String> {
+        by_id(prompts).into_keys().filter(|id| id.starts_with("fn_decl.builtins.")).collect()
+    }
+
+    #[tokio::test]
+    async fn emit_requested_builtins() {
+        let prompts = compile(&[("agent.ts", "export function f() {}\n")], &CompileOptions::default()).await.unwrap();
+        assert_eq!(builtins_of(&prompts), Vec::<String>::new());
+
+        let agent = "// kotto:builtins fetch, Deno.readTextFile\nexport function f() {}\n";
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+        assert_eq!(builtins_of(&prompts), ["fn_decl.builtins.Deno.readTextFile", "fn_decl.builtins.fetch"]);
+        assert_eq!(
+            by_id(&prompts)["fn_decl.builtins.Deno.readTextFile"].fmt.0,
+            "function builtins.Deno.readTextFile(path: string): Promise<string>;"
+        );
+
+        // A namespace stands for all of its functions
+        let agent = "// kotto:builtins Deno\nexport function f() {}\n";
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+        assert_eq!(builtins_of(&prompts).len(), 5);
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_builtins() {
+        let agent = "// kotto:builtins Deno.readTextFile Deno.readFile\nexport function f() {}\n";
+        let err = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "unknown builtin functions in kotto:builtins pragma of file:///agent.ts: Deno.readFile"
+        );
+    }
+
+    #[tokio::test]
+    async fn record_params() {
+        let agent = "export class Agent {\n    @use() search(query: string, { limit }: { limit?: number } = {}, ...tags: string[]) { return [] }\n}\nexport function ping() {}\n";
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+
+        let prompts_by_id = by_id(&prompts);
+        assert_eq!(
+            serde_json::to_value(&prompts_by_id["method_decl.Agent.search"].params).unwrap(),
+            serde_json::json!([
+                { "name": "query", "coerce": { "to": "string" } },
+                { "name": "arg1", "optional": true, "default": true, "coerce": { "to": "object", "props": { "limit": { "to": "number" } } } },
+                { "name": "tags", "optional": true, "rest": true, "coerce": { "to": "array", "elem": { "to": "string" } } }
+            ])
+        );
+        assert!(prompts_by_id["fn_decl.ping"].params.is_empty());
+    }
+
+    #[tokio::test]
+    async fn tree_shake() {
+        let agent = "type Id = string;\n\
+            type Page<T> = { items: T[]; next?: Id };\n\
+            interface Item { id: Id }\n\
+            interface Unused { x: number }\n\
+            function helper(i: Item) {}\n\
+            export function list(page: Page<Item>): Id[] { return [] }\n\
+            export class Agent {\n\
+                @use() get(id: Id): Item | undefined { return undefined }\n\
+                other(u: Unused) {}\n\
+            }\n\
+            class Idle {\n\
+                run(u: Unused) {}\n\
+            }\n";
+
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+        assert_eq!(by_id(&prompts).len(), 11);
+
+        // Only the exported functions, the methods exposed with `@use` and
+        // what they refer to
+        let options = CompileOptions { tree_shake: true, ..Default::default() };
+        let prompts = compile(&[("agent.ts", agent)], &options).await.unwrap();
+        assert_eq!(
+            by_id(&prompts).into_keys().collect::<Vec<_>>(),
+            [
+                "class_decl.Agent",
+                "fn_decl.list",
+                "interface_decl.Item",
+                "method_decl.Agent.get",
+                "type_alias_decl.Id",
+                "type_alias_decl.Page"
+            ]
+        );
+        assert_eq!(context_of(&prompts, "type_alias_decl.Page"), ["type_alias_decl.Id"]);
+    }
+
+    #[tokio::test]
+    async fn inline_aliases() {
+        let agent = "/** The user's id */\n\
+            type Id = string;\n\
+            type Ids = Id[];\n\
+            type Long = { a: string; b: number; c: boolean };\n\
+            export function f(id: Id, long: Long): Ids { return [] }\n\
+            export function g(id?: Id) {}\n";
+
+        let prompts = compile(&[("agent.ts", agent)], &CompileOptions::default()).await.unwrap();
+        assert_eq!(by_id(&prompts)["fn_decl.f"].fmt.0, "function f(id: Id, long: Long): Ids;");
+
+        // Aliases that are long or refer to other types stay, and the context
+        // keeps what the signature still refers to
+        let options = CompileOptions { inline_aliases: Some(10), ..Default::default() };
+        let prompts = compile(&[("agent.ts", agent)], &options).await.unwrap();
+        let prompts_by_id = by_id(&prompts);
+        assert_eq!(prompts_by_id["fn_decl.f"].fmt.0, "function f(id: string /* The user's id */, long: Long): Ids;");
+        assert_eq!(context_of(&prompts, "fn_decl.f"), ["type_alias_decl.Id", "type_alias_decl.Ids", "type_alias_decl.Long"]);
+        assert_eq!(prompts_by_id["fn_decl.g"].fmt.0, "function g(id?: string /* The user's id */);");
+        assert_eq!(context_of(&prompts, "fn_decl.g"), Vec::<String>::new());
+    }
+
+    #[tokio::test]
+    async fn bundle_modules_once() {
+        let dir = std::env::temp_dir().join(format!("kottoc-bundle-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shared.ts"), "export interface Item { id: string }\n").unwrap();
+        std::fs::write(
+            dir.join("a.ts"),
+            "// kotto:builtins fetch\nimport { Item } from \"./shared.ts\";\ninterface Options { a: number }\nexport function f(i: Item, o: Options) {}\n"
+        ).unwrap();
+        std::fs::write(
+            dir.join("b.ts"),
+            "import { Item } from \"./shared.ts\";\ninterface Options { b: string }\nexport function g(i: Item, o: Options) {}\n"
+        ).unwrap();
+        let specifiers: Vec<ModuleSpecifier> = ["a.ts", "b.ts"].iter()
+            .map(|name| ModuleSpecifier::from_file_path(dir.join(name)).unwrap())
+            .collect();
+
+        let options = CompileOptions { bundle: true, ..Default::default() };
+        let (meta, prompts, _) = bundle_modules(&specifiers, &dir, &options).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Declarations of different modules do not collide, and the ones
+        // they share appear once
+        let ids: Vec<&str> = prompts.0.iter()
+            .map(|prompt| &*prompt.id.0)
+            .filter(|id| id.contains('.') && !id.starts_with("builtins."))
+            .collect();
+        assert_eq!(ids, ["a.Options#2", "a.f#2", "shared.Item#2", "b.Options#2", "b.g#2"]);
+        assert_eq!(
+            prompts.0.iter().find(|prompt| prompt.id.0 == "b.g#2").unwrap().context.iter().map(|id| &*id.0).collect::<Vec<_>>(),
+            ["interface_decl.b.Options#2", "interface_decl.shared.Item#2"]
+        );
+
+        // Builtins are recorded by module
+        let sources: Vec<_> = meta.sources.iter()
+            .map(|source| (source.namespace.as_deref(), source.builtins.clone().unwrap_or_default()))
+            .collect();
+        assert_eq!(sources, [(Some("a"), vec!["fetch".to_string()]), (Some("b"), vec![])]);
+    }
+
+    #[test]
+    fn mirror_the_tree_under_the_root() {
+        let dir = std::env::temp_dir().join(format!("kottoc-root-{}", std::process::id()));
+        for sub in ["src/a", "src/b", "other"] {
+            std::fs::create_dir_all(dir.join(sub)).unwrap();
+            std::fs::write(dir.join(sub).join("agent.ts"), AGENT).unwrap();
+        }
+        let specifier = |path: &str| ModuleSpecifier::from_file_path(dir.join(path)).unwrap();
+        let specifiers = [specifier("src/a/agent.ts"), specifier("src/b/agent.ts")];
+
+        let colliding = output_paths(&specifiers, None).err().unwrap();
+        // The root and the modules compare canonicalized
+        let mirrored = output_paths(&specifiers, Some(&dir.join("other/../src")));
+        let outside = output_paths(&[specifier("other/agent.ts")], Some(&dir.join("src"))).err().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(colliding.to_string().contains("would both be written to agent.prompts.js"), "{}", colliding);
+        assert_eq!(mirrored.unwrap(), [PathBuf::from("a/agent.prompts.js"), PathBuf::from("b/agent.prompts.js")]);
+        assert!(outside.to_string().contains("is not under the root"), "{}", outside);
+
+        let remote = ModuleSpecifier::parse("https://deno.land:8080/x/kotto/agent.ts").unwrap();
+        assert_eq!(output_paths(&[remote], Some(Path::new("."))).unwrap(), [PathBuf::from("deno.land_8080/x/kotto/agent.prompts.js")]);
+    }
+
+    #[tokio::test]
+    async fn reject_root_with_bundle() {
+        let options = CompileOptions { bundle: true, root: Some(PathBuf::from(".")), ..Default::default() };
+        let specifier = ModuleSpecifier::parse("file:///agent.ts").unwrap();
+        let err = compile_prompts_for_specifiers(&[specifier], None::<PathBuf>, &options).await.err().unwrap();
+        assert_eq!(err.to_string(), "a bundle is a single file, it cannot mirror the tree under the root");
+    }
+
+    #[test]
+    fn relative_paths() {
+        assert_eq!(relative_path(Path::new("/project/prompts"), Path::new("/project/src/agent.ts")), "../src/agent.ts");
+        assert_eq!(relative_path(Path::new("/project"), Path::new("/project/src/agent.ts")), "src/agent.ts");
+        assert_eq!(relative_path(Path::new("/a/b/c"), Path::new("/d.ts")), "../../../d.ts");
+    }
+
+    #[test]
+    fn relativize_local_sources() {
+        let dir = std::env::temp_dir().join(format!("kottoc-relativize-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/agent.ts"), AGENT).unwrap();
+        let source = |specifier: String| Source { specifier, hash: String::new(), namespace: None, builtins: None };
+        let mut meta = Meta::new(vec![
+            source(ModuleSpecifier::from_file_path(dir.join("src/agent.ts")).unwrap().to_string()),
+            source("https://deno.land/x/kotto/agent.ts".to_string())
+        ], None);
+
+        // The output directory need not exist yet
+        let relativized = relativize_sources(&mut meta, &dir.join("prompts"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        relativized.unwrap();
+        let specifiers: Vec<&str> = meta.sources.iter().map(|source| &*source.specifier).collect();
+        assert_eq!(specifiers, ["../src/agent.ts", "https://deno.land/x/kotto/agent.ts"]);
+    }
+
+    const AGENT: &str = "export function greet(name: string): string {\n    return name\n}\n";
+
+    /// Builds `src/agent.ts` of a project in the directory `built` (with
+    /// `--root` if `root`), copies the project and its build to the directory
+    /// `copied`, and returns the files that checking the copy reports stale.
+    async fn check_copy(built: &Path, copied: &Path, root: bool, change: bool) -> Vec<PathBuf> {
+        let options = |dir: &Path, check: bool| CompileOptions {
+            root: root.then(|| dir.to_path_buf()),
+            check,
+            ..Default::default()
         };
+        let specifier = |dir: &Path| ModuleSpecifier::from_file_path(dir.join("src/agent.ts")).unwrap();
 
-        write!(&mut writer, "export const ast = ")?;
-        serde_json::to_writer_pretty(writer, &prompts)?;
+        std::fs::create_dir_all(built.join("src")).unwrap();
+        std::fs::write(built.join("src/agent.ts"), AGENT).unwrap();
+        compile_prompts_for_specifiers(&[specifier(built)], Some(built.join("prompts")), &options(built, false)).await.unwrap();
+
+        let prompts_dir = if root { "prompts/src" } else { "prompts" };
+        std::fs::create_dir_all(copied.join(prompts_dir)).unwrap();
+        std::fs::create_dir_all(copied.join("src")).unwrap();
+        let source = if change { AGENT.replace("name: string", "name?: string") } else { AGENT.to_string() };
+        std::fs::write(copied.join("src/agent.ts"), source).unwrap();
+        for file in ["agent.prompts.js", "agent.prompts.d.ts"] {
+            std::fs::copy(built.join(prompts_dir).join(file), copied.join(prompts_dir).join(file)).unwrap();
+        }
+
+        compile_prompts_for_specifiers(&[specifier(copied)], Some(copied.join("prompts")), &options(copied, true)).await.unwrap()
     }
 
-    Ok(())
-}
\ No newline at end of file
+    async fn check_copies(name: &str, root: bool) {
+        let dir = std::env::temp_dir().join(format!("kottoc-{}-{}", name, std::process::id()));
+        let stale = check_copy(&dir.join("built"), &dir.join("copied"), root, false).await;
+        let changed = check_copy(&dir.join("built"), &dir.join("changed"), root, true).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(stale, Vec::<PathBuf>::new());
+        assert_eq!(changed.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn check_copied_build() {
+        check_copies("check", false).await;
+    }
+
+    #[tokio::test]
+    async fn check_copied_build_with_root() {
+        check_copies("check-root", true).await;
+    }
+
+    /// The output of compiling `specifiers` `jobs` at a time.
+    async fn compile_with_jobs(specifiers: &[ModuleSpecifier], jobs: usize) -> Vec<String> {
+        let options = CompileOptions { jobs: Some(jobs), ..Default::default() };
+        compile_modules(specifiers, &options).await.unwrap()
+            .iter()
+            .map(|(meta, prompts, _)| format!("{}\n{}", serde_json::to_string(meta).unwrap(), serde_json::to_string(prompts).unwrap()))
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn jobs_keep_order() {
+        let dir = std::env::temp_dir().join(format!("kottoc-jobs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("shared.ts"), "export interface Item { id: string }\n").unwrap();
+        let specifiers: Vec<ModuleSpecifier> = (0..12)
+            .map(|i| {
+                // Of different sizes, for them to take different times
+                let params: Vec<String> = (0..i * 10).map(|j| format!("p{}: Item", j)).collect();
+                let source = format!(
+                    "import {{ Item }} from \"./shared.ts\";\nexport function f{}({}): Item {{\n    return p0\n}}\n",
+                    i,
+                    params.join(", ")
+                );
+                let path = dir.join(format!("m{}.ts", i));
+                std::fs::write(&path, source).unwrap();
+                ModuleSpecifier::from_file_path(path).unwrap()
+            })
+            .collect();
+
+        let sequential = compile_with_jobs(&specifiers, 1).await;
+        let concurrent = compile_with_jobs(&specifiers, 8).await;
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(sequential.len(), specifiers.len());
+        for (i, output) in sequential.iter().enumerate() {
+            assert!(output.contains(&format!("function f{}(", i)));
+        }
+        assert_eq!(sequential, concurrent);
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::rc::Rc;
+
+use deno_ast::ModuleSpecifier;
+
+use crate::{ast, builtins, emit};
+use crate::codegen::Node;
+use crate::common::DUMMY_SP;
+use crate::eval::TypeEvaluator;
+use crate::graph::{Module, Resolved};
+use crate::signature::Param;
+
+/// The combinators that validators are built from.
+const PRELUDE: &str = include_str!("prelude.js");
+
+/// Builtin types that are sent (as JSON) as strings.
+const STRING_BUILTINS: &[&str] = &["Date", "URL"];
+
+/// Generates, for the signatures of the prompts, JavaScript functions that
+/// check the arguments given by the model before the runtime calls them. Each
+/// function returns the list of errors (e.g. `order.items[2].quantity:
+/// expected a number, got "two"`) it finds.
+///
+/// The declarations that the parameter types refer to get a function of their
+/// own, which is how recursive types are checked.
+#[derive(Default)]
+pub struct Validators {
+    /// The validators of signatures, by prompt id
+    signatures: Vec<(String, String)>,
+    /// The checks of declarations, by function name
+    decls: Vec<(String, String)>,
+    names: HashMap<(ModuleSpecifier, ast::Id), String>,
+}
+
+impl Validators {
+    pub fn is_empty(&self) -> bool {
+        self.signatures.is_empty()
+    }
+
+    /// Adds the validator of the signature with the prompt id `id`.
+    pub fn add_signature(
+        &mut self,
+        evaluator: &TypeEvaluator,
+        module: &Rc<Module>,
+        id: String,
+        params: &[ast::Param]
+    ) {
+        // Modules bundled together may share signatures (e.g. builtins)
+        if self.signatures.iter().any(|(other, _)| other == &id) {
+            return
+        }
+
+        let mut required = 0;
+        let mut rest = None;
+        let mut checks = Vec::new();
+
+        for (i, param) in params.iter().map(Param::from_param).enumerate() {
+            let check = match &param.type_ann {
+                // The type of a rest parameter is that of the array of the
+                // remaining arguments
+                Some(type_ann) if param.rest => match evaluator.evaluate(module, type_ann) {
+                    ast::TsType::TsArrayType(array) => self.check_evaluated(evaluator, &array.elem_type),
+                    _ => "unchecked".to_string()
+                },
+                Some(type_ann) => self.check(evaluator, module, type_ann),
+                None => "unchecked".to_string()
+            };
+
+            if param.rest {
+                let name = param.name.unwrap_or_else(|| "rest".to_string());
+                rest = Some(format!("[{}, {}]", js_string(&name), check));
+                break
+            }
+
+            if !param.optional && required == checks.len() {
+                required += 1;
+            }
+            let name = param.name.unwrap_or_else(|| format!("args[{}]", i));
+            checks.push(format!("[{}, {}]", js_string(&name), check));
+        }
+
+        let mut validator = format!("signature({}, {}", required, rest.as_deref().unwrap_or("null"));
+        for check in checks {
+            validator.push_str(", ");
+            validator.push_str(&check);
+        }
+        validator.push(')');
+
+        self.signatures.push((id, validator));
+    }
+
+    /// Writes the validators as the `validators` export of a prompts module.
+    pub fn write(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        write!(writer, "export const validators = ")?;
+        self.write_expr(writer)?;
+        writeln!(writer, ";")
+    }
+
+    /// Writes the validators as an expression, evaluating to the validators by
+    /// prompt id.
+    pub fn write_expr(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        writeln!(writer, "(() => {{")?;
+        writer.write_all(PRELUDE.as_bytes())?;
+        for (name, check) in &self.decls {
+            writeln!(writer)?;
+            writeln!(writer, "  function {}(v, path) {{ return {}(v, path); }}", name, check)?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "  return {{")?;
+        for (id, validator) in &self.signatures {
+            writeln!(writer, "    {}: {},", js_string(id), validator)?;
+        }
+        writeln!(writer, "  }};")?;
+        write!(writer, "}})()")
+    }
+
+    fn check(&mut self, evaluator: &TypeEvaluator, module: &Rc<Module>, ty: &ast::TsType) -> String {
+        let ty = evaluator.evaluate(module, ty);
+        self.check_evaluated(evaluator, &ty)
+    }
+
+    fn check_evaluated(&mut self, evaluator: &TypeEvaluator, ty: &ast::TsType) -> String {
+        use ast::TsKeywordTypeKind::*;
+
+        match ty {
+            ast::TsType::TsKeywordType(keyword) => match keyword.kind {
+                TsStringKeyword => "string".to_string(),
+                TsNumberKeyword => "number".to_string(),
+                TsBooleanKeyword => "boolean".to_string(),
+                TsObjectKeyword => "object".to_string(),
+                TsNullKeyword => "literal(null)".to_string(),
+                TsNeverKeyword => "never".to_string(),
+                _ => "unchecked".to_string()
+            },
+            ast::TsType::TsLitType(lit_type) => match &lit_type.lit {
+                ast::TsLit::Str(str) => format!("literal({})", js_string(&str.value)),
+                ast::TsLit::Number(num) => format!("literal({})", num.value),
+                ast::TsLit::Bool(bool) => format!("literal({})", bool.value),
+                ast::TsLit::Tpl(_) => "string".to_string(),
+                ast::TsLit::BigInt(_) => "unchecked".to_string()
+            },
+            ast::TsType::TsParenthesizedType(parenthesized) => self.check_evaluated(evaluator, &parenthesized.type_ann),
+            ast::TsType::TsTypeOperator(ast::TsTypeOperator { op: ast::TsTypeOperatorOp::ReadOnly, type_ann, .. }) => {
+                self.check_evaluated(evaluator, type_ann)
+            }
+            ast::TsType::TsArrayType(array) => format!("array({})", self.check_evaluated(evaluator, &array.elem_type)),
+            ast::TsType::TsTupleType(tuple) => {
+                let elems: Vec<String> = tuple.elem_types.iter()
+                    .map(|elem| self.check_evaluated(evaluator, &elem.ty))
+                    .collect();
+                format!("tuple({})", elems.join(", "))
+            }
+            ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsUnionType(union_type)) => {
+                let mut alts = vec![js_string(&type_text(ty))];
+                alts.extend(union_type.types.iter().map(|ty| self.check_evaluated(evaluator, ty)));
+                format!("union({})", alts.join(", "))
+            }
+            ast::TsType::TsUnionOrIntersectionType(ast::TsUnionOrIntersectionType::TsIntersectionType(intersection)) => {
+                let checks: Vec<String> = intersection.types.iter()
+                    .map(|ty| self.check_evaluated(evaluator, ty))
+                    .collect();
+                format!("all({})", checks.join(", "))
+            }
+            ast::TsType::TsTypeLit(type_lit) => self.check_members(evaluator, &type_lit.members),
+            // What is left of a mapped type is e.g. `Record<string, T>`
+            ast::TsType::TsMappedType(mapped) => match mapped.type_ann.as_deref() {
+                Some(type_ann) => format!("record({})", self.check_evaluated(evaluator, type_ann)),
+                None => "record(unchecked)".to_string()
+            },
+            ast::TsType::TsTypeRef(type_ref) => {
+                let type_args = type_ref.type_params.as_ref().map(|type_params| type_params.params.as_slice()).unwrap_or_default();
+                if let (ast::TsEntityName::Ident(ident), [elem_type]) = (&type_ref.type_name, type_args) {
+                    if &*ident.sym == "Array" || &*ident.sym == "ReadonlyArray" {
+                        return format!("array({})", self.check_evaluated(evaluator, elem_type))
+                    }
+                }
+
+                match evaluator.resolve(type_ref) {
+                    Some(decl) => self.check_decl(evaluator, decl),
+                    None => "unchecked".to_string()
+                }
+            }
+            _ => "unchecked".to_string()
+        }
+    }
+
+    fn check_members(&mut self, evaluator: &TypeEvaluator, members: &[ast::TsTypeElement]) -> String {
+        let mut props = Vec::new();
+        let mut index = None;
+
+        for member in members {
+            match member {
+                ast::TsTypeElement::TsPropertySignature(property) if !property.computed => {
+                    let key = match &*property.key {
+                        ast::Expr::Ident(ident) => ident.sym.to_string(),
+                        ast::Expr::Lit(ast::Lit::Str(str)) => str.value.to_string(),
+                        ast::Expr::Lit(ast::Lit::Num(num)) => num.value.to_string(),
+                        _ => continue
+                    };
+                    let check = match &property.type_ann {
+                        Some(type_ann) => self.check_evaluated(evaluator, &type_ann.type_ann),
+                        None => "unchecked".to_string()
+                    };
+                    props.push(format!("{}: [{}, {}]", js_string(&key), property.optional, check));
+                }
+                ast::TsTypeElement::TsIndexSignature(index_signature) => {
+                    index = Some(match &index_signature.type_ann {
+                        Some(type_ann) => self.check_evaluated(evaluator, &type_ann.type_ann),
+                        None => "unchecked".to_string()
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let props = format!("props({{ {} }})", props.join(", "));
+        match index {
+            Some(index) => format!("all({}, record({}))", props, index),
+            None => props
+        }
+    }
+
+    /// The name of the function that checks the declaration `decl`.
+    fn check_decl(&mut self, evaluator: &TypeEvaluator, decl: Resolved) -> String {
+        let key = (decl.module.specifier.clone(), decl.id.clone());
+        if let Some(name) = self.names.get(&key) {
+            return name.clone()
+        }
+
+        if decl.module.specifier == builtins::types_specifier() && STRING_BUILTINS.contains(&&*decl.id.0) {
+            return "string".to_string()
+        }
+
+        let mut name: String = format!("${}", decl.id.0)
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '$' { c } else { '_' })
+            .collect();
+        if self.decls.iter().any(|(other, _)| other == &name) {
+            name = format!("{}_{}", name, self.decls.len());
+        }
+
+        // Registered before checking the declaration, which may refer to itself
+        self.names.insert(key, name.clone());
+        let index = self.decls.len();
+        self.decls.push((name.clone(), String::new()));
+
+        let filtered_module = &decl.module.filtered_module;
+        let check = if let Some(type_alias_decl) = filtered_module.type_alias_decls.get(&decl.id) {
+            if type_alias_decl.type_params.is_some() {
+                "unchecked".to_string()
+            } else {
+                self.check(evaluator, &decl.module, &type_alias_decl.type_ann)
+            }
+        } else if let Some(interface_decl) = filtered_module.interface_decls.get(&decl.id) {
+            let type_lit = ast::TsType::TsTypeLit(ast::TsTypeLit {
+                span: DUMMY_SP,
+                members: interface_decl.body.body.clone()
+            });
+            self.check(evaluator, &decl.module, &type_lit)
+        } else {
+            "unchecked".to_string()
+        };
+
+        self.decls[index].1 = check;
+        name
+    }
+}
+
+fn js_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap()
+}
+
+/// The type as written in TypeScript, for error messages.
+fn type_text(ty: &ast::TsType) -> String {
+    let mut buf = Vec::new();
+    let mut emitter = emit::Emitter::new(&mut buf);
+    ty.emit_with(&mut emitter).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::graph::ModuleGraph;
+    use crate::tasks::{self, CompileOptions};
+
+    /// The validators of the module `source`, after the prelude.
+    async fn validators_of(source: &str) -> String {
+        let specifier = ModuleSpecifier::parse("file:///agent.ts").unwrap();
+        let mut module_graph = ModuleGraph::with_sources(HashMap::from([(specifier.clone(), source.to_string())]));
+        let (_, _, validators) = tasks::compile_module(&mut module_graph, &specifier, &CompileOptions::default()).await.unwrap();
+
+        let mut buf = Vec::new();
+        validators.write_expr(&mut buf).unwrap();
+        let text = String::from_utf8(buf).unwrap();
+        text.strip_prefix("(() => {\n").unwrap().strip_prefix(PRELUDE).unwrap().trim().to_string()
+    }
+
+    #[tokio::test]
+    async fn check_signatures() {
+        assert_eq!(
+            validators_of("export function f(query: string, limit?: number, ...tags: string[]) {}\n").await,
+            "return {\n    \"f#2\": signature(1, [\"tags\", string], [\"query\", string], [\"limit\", number]),\n  };\n})()"
+        );
+    }
+
+    #[tokio::test]
+    async fn check_declarations() {
+        let source = "type Tree = { value: number; children: Tree[] };\n\
+            interface Order { id: string; total?: number; status: \"open\" | \"paid\" }\n\
+            export function f(order: Order, tree: Tree, at: Date) {}\n";
+        assert_eq!(
+            validators_of(source).await,
+            [
+                r#"function $Order(v, path) { return props({ "id": [false, string], "total": [true, number], "status": [false, union("\"open\" | \"paid\"", literal("open"), literal("paid"))] })(v, path); }"#,
+                "",
+                // Recursive types refer to their own function
+                r#"  function $Tree(v, path) { return props({ "value": [false, number], "children": [false, array($Tree)] })(v, path); }"#,
+                "",
+                "  return {",
+                r#"    "f#2": signature(3, null, ["order", $Order], ["tree", $Tree], ["at", string]),"#,
+                "  };",
+                "})()"
+            ].join("\n")
+        );
+    }
+}
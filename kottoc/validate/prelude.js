@@ -0,0 +1,66 @@
+  const describe = (v) =>
+    v === null ? "null" : Array.isArray(v) ? "an array" : typeof v === "object" ? "an object" : JSON.stringify(v);
+  const member = (path, key) => /^[A-Za-z_$][\w$]*$/.test(key) ? `${path}.${key}` : `${path}[${JSON.stringify(key)}]`;
+  const isObject = (v) => typeof v === "object" && v !== null && !Array.isArray(v);
+
+  const is = (expected, test) => (v, path) => test(v) ? [] : [`${path}: expected ${expected}, got ${describe(v)}`];
+  const unchecked = () => [];
+  const never = (v, path) => [`${path}: expected no value, got ${describe(v)}`];
+  const string = is("a string", (v) => typeof v === "string");
+  const number = is("a number", (v) => typeof v === "number");
+  const boolean = is("a boolean", (v) => typeof v === "boolean");
+  const object = is("an object", (v) => typeof v === "object" && v !== null);
+  const literal = (value) => is(JSON.stringify(value), (v) => v === value);
+
+  // Alternatives that only fail within the value (e.g. an object missing a
+  // property) tell what is wrong with it, so their errors follow the summary
+  const union = (expected, ...alts) => (v, path) => {
+    const failed = [];
+    for (const alt of alts) {
+      const errors = alt(v, path);
+      if (errors.length === 0) return [];
+      failed.push(errors);
+    }
+    const nested = failed.filter((errors) => errors.every((error) => !error.startsWith(`${path}: `)));
+    return [`${path}: expected ${expected}, got ${describe(v)}`, ...nested.flat().map((error) => `  ${error}`)];
+  };
+  const all = (...checks) => (v, path) => checks.flatMap((check) => check(v, path));
+
+  const array = (elem) => (v, path) =>
+    Array.isArray(v) ? v.flatMap((e, i) => elem(e, `${path}[${i}]`)) : [`${path}: expected an array, got ${describe(v)}`];
+  const tuple = (...elems) => (v, path) => {
+    if (!Array.isArray(v)) return [`${path}: expected an array, got ${describe(v)}`];
+    if (v.length !== elems.length) return [`${path}: expected ${elems.length} elements, got ${v.length}`];
+    return elems.flatMap((elem, i) => elem(v[i], `${path}[${i}]`));
+  };
+  const props = (props) => (v, path) => {
+    if (!isObject(v)) return [`${path}: expected an object, got ${describe(v)}`];
+    return Object.entries(props).flatMap(([key, [optional, check]]) =>
+      v[key] === undefined
+        ? optional ? [] : [`${member(path, key)}: missing required property`]
+        : check(v[key], member(path, key))
+    );
+  };
+  const record = (value) => (v, path) =>
+    isObject(v)
+      ? Object.entries(v).flatMap(([key, e]) => value(e, member(path, key)))
+      : [`${path}: expected an object, got ${describe(v)}`];
+
+  // `params` are `[name, check]` pairs, the first `required` of which must be given
+  const plural = (n) => `${n} argument${n === 1 ? "" : "s"}`;
+  const signature = (required, rest, ...params) => (args) => {
+    if (args.length < required) {
+      return [`expected at least ${plural(required)}, got ${args.length}`];
+    }
+    if (rest === null && args.length > params.length) {
+      return [`expected at most ${plural(params.length)}, got ${args.length}`];
+    }
+    const errors = params.flatMap(([name, check], i) =>
+      i >= required && args[i] === undefined ? [] : check(args[i], name)
+    );
+    if (rest !== null) {
+      const [name, check] = rest;
+      errors.push(...args.slice(params.length).flatMap((arg, i) => check(arg, `${name}[${i}]`)));
+    }
+    return errors;
+  };
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::task::{Context, Poll, Waker};
+
+use deno_ast::ModuleSpecifier;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{AnyError, anyhow};
+use crate::graph::ModuleGraph;
+use crate::tasks::{self, CompileOptions};
+
+#[derive(Deserialize)]
+struct CompileRequest {
+    specifier: ModuleSpecifier,
+    /// The sources of the module and of the modules it imports, by specifier
+    #[serde(default)]
+    sources: HashMap<ModuleSpecifier, String>,
+    #[serde(default)]
+    options: CompileOptions,
+}
+
+/// Runs `future` to completion, as long as it never waits: there is no
+/// runtime to wake it up. Compiling only waits on fetching modules, which the
+/// host provides up front (see [`ModuleGraph::with_sources`]): missing modules
+/// are errors rather than pending fetches, so a future returning `Pending` is
+/// a bug, reported as an error rather than left hanging.
+fn run_to_completion<F: Future>(future: F) -> Result<F::Output, AnyError> {
+    let mut future = std::pin::pin!(future);
+    match future.as_mut().poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => Ok(output),
+        Poll::Pending => Err(anyhow!("compiling waited on the host"))
+    }
+}
+
+fn compile_request(request: &str) -> Result<Value, AnyError> {
+    let request: CompileRequest = serde_json::from_str(request)
+        .map_err(|err| anyhow!("invalid request: {}", err))?;
+    let mut module_graph = ModuleGraph::with_sources(request.sources);
+    let compiled = run_to_completion(tasks::compile_to_string(&mut module_graph, &request.specifier, &request.options))?;
+
+    // Prompts compiled without some of the modules would lack declarations
+    let mut missing: Vec<String> = module_graph.missing().map(ToString::to_string).collect();
+    if !missing.is_empty() {
+        missing.sort();
+        return Ok(json!({ "missing": missing }))
+    }
+    Ok(json!({ "module": compiled? }))
+}
+
+/// Compiles a module without touching the network or the filesystem, for
+/// hosts that fetch modules themselves (e.g. Deno, running the WebAssembly
+/// build). Takes a JSON request `{ specifier, sources, options? }`, where
+/// `sources` holds the sources of the module and of the modules it imports by
+/// specifier, and returns either:
+///
+/// - `{ module }`, the text of the prompts module
+/// - `{ missing }`, the specifiers of the modules that are to be added to
+///   `sources` before trying again, as imports are only known once the modules
+///   importing them are compiled
+/// - `{ error }`
+pub fn compile(request: &str) -> String {
+    let response = compile_request(request).unwrap_or_else(|err| json!({ "error": err.to_string() }));
+    response.to_string()
+}
+
+/// Allocates `len` bytes, for the host to write a request to.
+#[no_mangle]
+pub extern "C" fn kottoc_alloc(len: usize) -> *mut u8 {
+    Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8
+}
+
+/// Frees the `len` bytes at `ptr`.
+///
+/// # Safety
+///
+/// `ptr` and `len` are those of a request allocated by [`kottoc_alloc`], or
+/// of a response returned by [`kottoc_compile`] (whose length includes its
+/// header).
+#[no_mangle]
+pub unsafe extern "C" fn kottoc_free(ptr: *mut u8, len: usize) {
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(ptr, len)));
+}
+
+/// Like [`compile`], with the request being the `len` bytes (of UTF-8) at
+/// `ptr`. The response is preceded by its length, as 4 little-endian bytes,
+/// and is to be freed with [`kottoc_free`].
+///
+/// # Safety
+///
+/// `ptr` and `len` are those of a request allocated by [`kottoc_alloc`].
+#[no_mangle]
+pub unsafe extern "C" fn kottoc_compile(ptr: *const u8, len: usize) -> *mut u8 {
+    let request = String::from_utf8_lossy(std::slice::from_raw_parts(ptr, len));
+    let response = compile(&request);
+
+    let mut buf = Vec::with_capacity(4 + response.len());
+    buf.extend_from_slice(&(response.len() as u32).to_le_bytes());
+    buf.extend_from_slice(response.as_bytes());
+    Box::into_raw(buf.into_boxed_slice()) as *mut u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_futures_that_never_wait() {
+        assert_eq!(run_to_completion(async { 42 }).unwrap(), 42);
+        assert!(run_to_completion(std::future::pending::<()>()).is_err());
+    }
+
+    fn request(sources: &[(&str, &str)]) -> String {
+        let sources: HashMap<&str, &str> = sources.iter().copied().collect();
+        json!({ "specifier": "file:///agent.ts", "sources": sources }).to_string()
+    }
+
+    #[test]
+    fn compile_requests() {
+        let agent = "import { Query } from \"./query.ts\";\nexport function search(query: Query): void {}\n";
+
+        // Imports are only known once the modules importing them are compiled
+        let response: Value = serde_json::from_str(&compile(&request(&[("file:///agent.ts", agent)]))).unwrap();
+        assert_eq!(response, json!({ "missing": ["file:///query.ts"] }));
+
+        let response: Value = serde_json::from_str(&compile(&request(&[
+            ("file:///agent.ts", agent),
+            ("file:///query.ts", "export type Query = string;\n")
+        ]))).unwrap();
+        let module = response["module"].as_str().unwrap();
+        assert!(module.contains("function search(query: Query): void;"));
+        assert!(module.contains("type Query = string;"));
+
+        let response: Value = serde_json::from_str(&compile("{ not json")).unwrap();
+        assert!(response["error"].as_str().unwrap().starts_with("invalid request"));
+        let response: Value = serde_json::from_str(&compile(&request(&[("file:///agent.ts", "export function (")]))).unwrap();
+        assert!(response["error"].is_string());
+    }
+
+    #[test]
+    fn call_exports() {
+        let request = request(&[("file:///agent.ts", "export interface A {}\n")]);
+        let response = unsafe {
+            let ptr = kottoc_alloc(request.len());
+            std::ptr::copy_nonoverlapping(request.as_ptr(), ptr, request.len());
+            let response = kottoc_compile(ptr, request.len());
+            kottoc_free(ptr, request.len());
+
+            // Preceded by its length
+            let len = u32::from_le_bytes(*(response as *const [u8; 4])) as usize;
+            let text = String::from_utf8(std::slice::from_raw_parts(response.add(4), len).to_vec()).unwrap();
+            kottoc_free(response, len + 4);
+            text
+        };
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert!(response["module"].as_str().unwrap().contains("interface A {"));
+    }
+}
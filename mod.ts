@@ -1,7 +1,15 @@
 import { toFileUrl, resolve } from "./deps.ts"
 
-import { Prompts, Scope } from "./prompts.ts";
-export { Prompts }
+import {
+  coerceArguments,
+  escapeRegExp,
+  positionalArguments,
+  type PromptNode,
+  Prompts,
+  Scope,
+  type Validator,
+} from "./prompts.ts";
+export { Compiler, Prompts, WasmCompiler } from "./prompts.ts";
 
 import { Naive, Template } from "./const.ts";
 
@@ -30,12 +38,13 @@ const logger = log.logger;
 export type FunctionCall = {
   name: string;
   reasoning?: string;
-  arguments: any[];
+  // Positional, or by parameter name
+  arguments: any[] | Record<string, any>;
 };
 
 type ExportDescriptor = {
   property_key: string;
-  adder: (scope: Scope) => void;
+  adder: (scope: Scope, agent: Agent) => void;
   description?: string;
 };
 
@@ -55,6 +64,22 @@ class ExportsMap {
   }
 }
 
+/**
+ * Converts what builtin functions return to the JSON values that their
+ * declarations (in kottoc) describe.
+ */
+async function toJSON(value: unknown): Promise<unknown> {
+  if (value instanceof Response) {
+    return {
+      status: value.status,
+      ok: value.ok,
+      headers: Object.fromEntries(value.headers),
+      body: await value.text(),
+    };
+  }
+  return value;
+}
+
 type ConstructorDecorator = <T extends { new (...args: any[]): {} }>(
   constructor: T,
 ) => any;
@@ -82,16 +107,20 @@ export const use: MethodDecorator = (
   property_key: string,
   _descriptor?: PropertyDescriptor,
 ) => {
-  if (target.exports === undefined) {
-    target.exports = new Map();
+  // Subclasses get their own exports, starting from the ones they inherit
+  if (!Object.prototype.hasOwnProperty.call(target, "exports")) {
+    target.exports = new Map(target.exports);
   }
   target.exports.set(property_key, {
     property_key,
-    adder: (scope: Scope) =>
-      scope.addFromId(
+    // Inherited methods are looked up under the scope of the agent's class
+    adder: (scope: Scope, agent: Agent) =>
+      scope.addMatching(
         "method_decl",
-        Scope.ident(target.constructor.name),
-        Scope.ident(property_key),
+        ...scope.qualify(
+          Scope.ident(agent.constructor.name),
+          Scope.ident(property_key),
+        ),
       ),
   });
 };
@@ -178,9 +207,21 @@ export class AgentController {
 
     this.exports.forEach(({ property_key, adder }) => {
       logger.trace("prompts", `adding '${property_key}' to scope`);
-      adder(scope);
+      adder(scope, this.agent);
     });
 
+    // Builtin functions that the module opted in to (with a
+    // `// kotto:builtins` pragma), only those of this agent's module in a
+    // bundle
+    const builtins = this.prompts.builtins();
+    if (builtins === undefined) {
+      scope.addMatching("fn_decl", "builtins", ".+");
+    } else {
+      builtins.forEach((name) => {
+        scope.addMatching("fn_decl", "builtins", ...name.split(".").map(escapeRegExp));
+      });
+    }
+
     if (this.opts.allow_exit ?? true) {
       scope.addNode({
         type: "ts",
@@ -193,18 +234,63 @@ export class AgentController {
     return this.template.renderContext(scope);
   }
 
-  handleBuiltin(action: Action) {
-    const builtin = action.call.name.split(".")[1];
+  async handleBuiltin(action: Action) {
+    const builtin = action.call.name.slice("builtins.".length);
 
     if (builtin === "exit") {
-      if (action.call.arguments.length === 0) {
+      const args = Array.isArray(action.call.arguments)
+        ? action.call.arguments
+        : Object.values(action.call.arguments);
+      if (args.length === 0) {
         throw new Exit();
       } else {
-        throw new Exit(action.call.arguments[0]);
+        throw new Exit(args[0]);
       }
-    } else {
+    }
+
+    const pat = action.call.name.split(".").map(escapeRegExp);
+    const node = this.prompts.nodeFor("fn_decl", ...pat);
+    const requested = this.prompts.builtins()?.includes(builtin) ?? true;
+    if (node === undefined || !requested) {
       throw new Internal(`unknown builtin '${builtin}'`);
     }
+
+    // e.g. `Deno.readTextFile`
+    const path = builtin.split(".");
+    const fn_name = path.pop()!;
+    const receiver = path.reduce((obj, key) => obj?.[key], globalThis as any);
+    if (typeof receiver?.[fn_name] !== "function") {
+      throw new TypeError(`${action.call.name} is not a function`);
+    }
+
+    const args = this.prepareArguments(action, node, this.prompts.validatorFor(...pat));
+
+    logger.calls(action.call.name, args);
+    const output = await toJSON(await receiver[fn_name](...args));
+    logger.returns(output);
+
+    action.output = output;
+    this.history.push(action);
+  }
+
+  /**
+   * Maps named arguments to positional ones and repairs them, before checking
+   * them against the signature of the called function.
+   */
+  prepareArguments(action: Action, node?: PromptNode, validator?: Validator): any[] {
+    const args = coerceArguments(
+      node?.params,
+      positionalArguments(node?.params, action.call.arguments),
+    );
+    action.call.arguments = args;
+
+    const errors = validator?.(args) ?? [];
+    if (errors.length > 0) {
+      throw new Feedback(
+        `invalid arguments for ${action.call.name}:\n${errors.join("\n")}`,
+      );
+    }
+    return args;
   }
 
   async doAction(action: Action) {
@@ -212,7 +298,7 @@ export class AgentController {
 
     // if action.call.name is a builtin, call handleBuiltin
     if (action.call.name.startsWith("builtins.")) {
-      return this.handleBuiltin(action);
+      return await this.handleBuiltin(action);
     }
 
     const export_descriptor = exports.get(action.call.name);
@@ -227,7 +313,12 @@ export class AgentController {
       throw new TypeError(`${action.call.name} is not a function`);
     }
 
-    const args = action.call.arguments;
+    const pat = this.prompts.qualify(Scope.ident(this.agent.constructor.name), Scope.ident(call_name));
+    const args = this.prepareArguments(
+      action,
+      this.prompts.nodeFor("method_decl", ...pat),
+      this.prompts.validatorFor(...pat),
+    );
 
     logger.calls(call_name, args);
     const output = await (this.agent[call_name])(...args);
@@ -376,6 +467,8 @@ try adding:
 
   const agent = await mod.default(opts.agent_options);
 
+  await opts.prompts.checkStale(opts.source_url);
+
   const model = new llm.OpenAIChatCompletion(opts.openai_key);
 
   return new AgentController(agent, opts.prompts, model, {
@@ -1,7 +1,9 @@
 import { join as joinPath, toFileUrl } from "./deps.ts";
 
-import { Internal } from "./errors.ts";
+import { Feedback, Internal } from "./errors.ts";
 import { logger } from "./log.ts";
+import type { Coercion, ContextId, Meta, PromptAstTy, PromptNode, PromptParam } from "./kottoc.d.ts";
+export type { ContextId, PromptNode } from "./kottoc.d.ts";
 
 export type RunParameters = {
   exec?: string;
@@ -37,39 +39,407 @@ type PromptOpts = {
   work_dir?: string;
 };
 
-interface PromptsModule {
-  ast: PromptNode[];
+/**
+ * Checks the arguments of a call, returning the errors found (if any)
+ */
+export type Validator = (args: unknown[]) => string[];
+
+/**
+ * The format of prompts modules that this runtime reads, see `kottoc/format.rs`
+ */
+export const PROMPTS_FORMAT = 2;
+
+/**
+ * The version of kottoc released along with this runtime, which prompts are
+ * checked to be built by. Bumped along with `kottoc/Cargo.toml`, for the runtime
+ * to tell the kottoc it runs apart from older ones (e.g. 0.1.0, without `serve`)
+ */
+export const KOTTOC_VERSION = "0.2.0";
+
+/**
+ * A prompts module, whose `.prompts.d.ts` narrows `Id` to the ids of its
+ * prompts
+ */
+export interface PromptsModule<Id extends string = string> {
+  // Missing from prompts modules of format 1
+  meta?: Meta;
+  ast: readonly PromptNode<Id>[];
+  validators?: Partial<Record<Id, Validator>>;
 }
 
-type PromptTy =
-    "ts"
-    | "plaintext";
-
-type PromptAstTy =
-    "method_decl"
-    | "class_decl"
-    | "type_alias_decl"
-    | "fn_decl";
-
-type PromptNode = {
-  "type": PromptTy;
-  ast_ty: PromptAstTy;
-  fmt: string;
-  id: string;
-  context?: string[];
+/**
+ * Repairs a value given by the model (e.g. `"42"` for a number) according to
+ * `coercion`. Values that cannot be repaired are left as they are, for
+ * validation to report.
+ */
+function coerce(value: any, coercion?: Coercion): any {
+  if (coercion === undefined || value === undefined || value === null) {
+    return value;
+  }
+
+  switch (coercion.to) {
+    case "number":
+      if (typeof value === "string" && value.trim() !== "" && !isNaN(Number(value))) {
+        return Number(value);
+      }
+      return value;
+    case "boolean":
+      if (typeof value === "string" && /^(true|false)$/i.test(value.trim())) {
+        return value.trim().toLowerCase() === "true";
+      }
+      return value;
+    case "string":
+      if (typeof value === "number" || typeof value === "boolean") {
+        return String(value);
+      }
+      return value;
+    case "date": {
+      if (typeof value !== "string" && typeof value !== "number") {
+        return value;
+      }
+      const date = new Date(value);
+      return isNaN(date.getTime()) ? value : date.toISOString();
+    }
+    case "enum":
+      if (typeof value === "string") {
+        const folded = value.trim().toLowerCase();
+        return coercion.values.find((v) => v.toLowerCase() === folded) ?? value;
+      }
+      return value;
+    case "array":
+      return (Array.isArray(value) ? value : [value]).map((elem) => coerce(elem, coercion.elem));
+    case "object":
+      if (typeof value !== "object" || Array.isArray(value)) {
+        return value;
+      }
+      return Object.fromEntries(
+        Object.entries(value).map(([key, v]) => [key, coerce(v, coercion.props[key])]),
+      );
+  }
+}
+
+/**
+ * Maps the arguments of a call given by name (e.g. `{ "query": "...", "limit": 10 }`)
+ * to the positional arguments of a function with the parameters `params`.
+ */
+export function positionalArguments(
+  params: PromptParam[] | undefined,
+  args: any[] | Record<string, any>,
+): any[] {
+  if (Array.isArray(args)) {
+    return args;
+  }
+  if (typeof args !== "object" || args === null) {
+    throw new Feedback("arguments must be given as an array");
+  }
+  // Functions without parameters have none listed
+  params ??= [];
+
+  const named = params.flatMap((param) => param.name === undefined ? [] : [param.name]);
+  const unknown = Object.keys(args).filter((name) => !named.includes(name));
+  if (unknown.length > 0) {
+    const expected = named.length > 0 ? `expected ${named.join(", ")}` : "expected none";
+    throw new Feedback(`unknown arguments: ${unknown.join(", ")} (${expected})`);
+  }
+
+  const positional: any[] = [];
+  for (const param of params) {
+    const value = param.name === undefined ? undefined : args[param.name];
+    if (param.rest) {
+      if (value !== undefined) {
+        positional.push(...(Array.isArray(value) ? value : [value]));
+      }
+    } else {
+      positional.push(value);
+    }
+  }
+
+  // Left out arguments at the end are not passed at all
+  while (positional.length > 0 && positional[positional.length - 1] === undefined) {
+    positional.pop();
+  }
+  return positional;
+}
+
+/**
+ * Repairs the arguments of a call to a function with the parameters `params`.
+ */
+export function coerceArguments(params: PromptParam[] | undefined, args: any[]): any[] {
+  if (params === undefined) {
+    return args;
+  }
+
+  return args.map((arg, i) => {
+    const param = params[Math.min(i, params.length - 1)];
+    if (i >= params.length && !param?.rest) {
+      return arg;
+    }
+    if (param?.rest) {
+      // The coercion of a rest parameter is that of the array of the remaining arguments
+      return coerce(arg, param.coerce?.to === "array" ? param.coerce.elem : undefined);
+    }
+    return coerce(arg, param?.coerce);
+  });
+}
+
+/**
+ * The end of the headers of a message framed as in the Language Server Protocol,
+ * or -1 if they are not all in `bytes` yet
+ */
+function headersEnd(bytes: Uint8Array): number {
+  for (let i = 0; i + 3 < bytes.length; i++) {
+    if (bytes[i] === 13 && bytes[i + 1] === 10 && bytes[i + 2] === 13 && bytes[i + 3] === 10) {
+      return i;
+    }
+  }
+  return -1;
+}
+
+/**
+ * The version of the kottoc at `exec` (e.g. `0.1.0`), or `undefined` if it
+ * is too old to tell (or cannot be run)
+ */
+export async function kottocVersion(exec?: string): Promise<string | undefined> {
+  try {
+    const { success, stdout } = await new Deno.Command(exec || "kottoc", {
+      args: ["--version"],
+      stdout: "piped",
+      stderr: "null",
+    }).output();
+    // e.g. `kottoc 0.1.0`
+    return success ? new TextDecoder().decode(stdout).trim().split(" ").pop() : undefined;
+  } catch {
+    return undefined;
+  }
+}
+
+/**
+ * Whether the version `version` is older than `than` (both `major.minor.patch`)
+ */
+export function isOlderVersion(version: string, than: string): boolean {
+  const [a, b] = [version, than].map((v) => v.split(".").map((n) => parseInt(n, 10) || 0));
+  for (let i = 0; i < Math.max(a.length, b.length); i++) {
+    if ((a[i] ?? 0) !== (b[i] ?? 0)) {
+      return (a[i] ?? 0) < (b[i] ?? 0);
+    }
+  }
+  return false;
+}
+
+/**
+ * A long-running `kottoc serve --stdio`, which compiles modules in memory and
+ * keeps their prompts until the modules change. Agents can then be rebuilt (e.g.
+ * on hot reloads) without spawning a compiler and writing to disk every time.
+ */
+export class Compiler {
+  readonly #proc: Deno.ChildProcess;
+  readonly #writer: WritableStreamDefaultWriter<Uint8Array>;
+  readonly #reader: ReadableStreamDefaultReader<Uint8Array>;
+  #buffered = new Uint8Array();
+  #next_id = 0;
+  // Requests are sent one at a time, so responses come in the same order
+  #pending: Promise<unknown> = Promise.resolve();
+
+  constructor(exec?: string) {
+    const cmd = new Deno.Command(exec || "kottoc", {
+      args: ["serve", "--stdio"],
+      stdin: "piped",
+      stdout: "piped",
+      stderr: "inherit",
+    });
+    this.#proc = cmd.spawn();
+    this.#writer = this.#proc.stdin.getWriter();
+    this.#reader = this.#proc.stdout.getReader();
+  }
+
+  async #fill(): Promise<void> {
+    const { value, done } = await this.#reader.read();
+    if (done) {
+      throw new Internal("kottoc exited");
+    }
+    const buffered = new Uint8Array(this.#buffered.length + value.length);
+    buffered.set(this.#buffered);
+    buffered.set(value, this.#buffered.length);
+    this.#buffered = buffered;
+  }
+
+  async #receive(): Promise<any> {
+    let end;
+    while ((end = headersEnd(this.#buffered)) < 0) {
+      await this.#fill();
+    }
+    const headers = new TextDecoder().decode(this.#buffered.subarray(0, end));
+    const length = Number(/content-length:\s*(\d+)/i.exec(headers)?.[1]);
+    const start = end + 4;
+    while (this.#buffered.length < start + length) {
+      await this.#fill();
+    }
+    const content = this.#buffered.slice(start, start + length);
+    this.#buffered = this.#buffered.slice(start + length);
+    return JSON.parse(new TextDecoder().decode(content));
+  }
+
+  #request(method: string, params: unknown): Promise<any> {
+    const id = this.#next_id++;
+    const response = this.#pending.then(async () => {
+      const content = new TextEncoder().encode(JSON.stringify({ jsonrpc: "2.0", id, method, params }));
+      await this.#writer.write(new TextEncoder().encode(`Content-Length: ${content.length}\r\n\r\n`));
+      await this.#writer.write(content);
+      const message = await this.#receive();
+      if (message.error !== undefined) {
+        throw new Internal(`failed to generate prompts: ${message.error.message}`);
+      }
+      return message.result;
+    });
+    this.#pending = response.catch(() => {});
+    return response;
+  }
+
+  /**
+   * Compiles the module at `source_url`, or `source` in its place (e.g. the
+   * unsaved contents of the module)
+   */
+  async compile(source_url: URL, source?: string): Promise<Prompts> {
+    const { module } = await this.#request("compile", { specifier: source_url.href, source });
+    logger.trace("prompts", `generated for ${source_url.toString()} in memory`);
+    return Prompts.fromModule(await import(`data:text/javascript,${encodeURIComponent(module)}`));
+  }
+
+  /**
+   * Drops the prompts kept for the module at `source_url`, or for all modules
+   */
+  async invalidate(source_url?: URL): Promise<void> {
+    await this.#request("invalidate", { specifier: source_url?.href });
+  }
+
+  async close(): Promise<void> {
+    await this.#writer.close();
+    this.#reader.releaseLock();
+    await this.#proc.stdout.cancel();
+    await this.#proc.status;
+  }
+}
+
+type WasmExports = {
+  memory: WebAssembly.Memory;
+  kottoc_alloc(len: number): number;
+  kottoc_free(ptr: number, len: number): void;
+  kottoc_compile(ptr: number, len: number): number;
 };
 
-export class Prompts {
-  readonly #mod: PromptsModule;
+async function sha256(text: string): Promise<string> {
+  const digest = await crypto.subtle.digest("SHA-256", new TextEncoder().encode(text));
+  return Array.from(new Uint8Array(digest), (byte) => byte.toString(16).padStart(2, "0")).join("");
+}
+
+async function fetchSource(url: URL): Promise<string> {
+  const response = await fetch(url);
+  if (!response.ok) {
+    throw new Internal(`could not retrieve ${url}: ${response.status}`);
+  }
+  return await response.text();
+}
+
+/**
+ * kottoc built for WebAssembly (`kottoc.wasm`, which `kotto upgrade` downloads from
+ * the latest release), for hosts without a Rust toolchain (e.g. Deno Deploy).
+ * The compiler does not touch the network or the filesystem: the modules it needs
+ * are fetched here, with `load`.
+ */
+export class WasmCompiler {
+  readonly #exports: WasmExports;
+  readonly #load: (url: URL) => Promise<string>;
+
+  constructor(instance: WebAssembly.Instance, load: (url: URL) => Promise<string> = fetchSource) {
+    this.#exports = instance.exports as unknown as WasmExports;
+    this.#load = load;
+  }
+
+  static async instantiate(wasm: URL | BufferSource, load?: (url: URL) => Promise<string>): Promise<WasmCompiler> {
+    const bytes = wasm instanceof URL ? await (await fetch(wasm)).arrayBuffer() : wasm;
+    const { instance } = await WebAssembly.instantiate(bytes);
+    return new WasmCompiler(instance, load);
+  }
+
+  #call(request: unknown): any {
+    const { memory, kottoc_alloc, kottoc_free, kottoc_compile } = this.#exports;
+    const bytes = new TextEncoder().encode(JSON.stringify(request));
+    const ptr = kottoc_alloc(bytes.length);
+    new Uint8Array(memory.buffer, ptr, bytes.length).set(bytes);
+    const response = kottoc_compile(ptr, bytes.length);
+    kottoc_free(ptr, bytes.length);
+
+    // The response is preceded by its length
+    const length = new DataView(memory.buffer).getUint32(response, true);
+    const text = new TextDecoder().decode(new Uint8Array(memory.buffer, response + 4, length));
+    kottoc_free(response, length + 4);
+    return JSON.parse(text);
+  }
+
+  /**
+   * Compiles the module at `source_url`, or `source` in its place, loading the
+   * modules it imports as the compiler asks for them
+   */
+  async compile(source_url: URL, source?: string): Promise<Prompts> {
+    const sources: Record<string, string> = {};
+    if (source !== undefined) {
+      sources[source_url.href] = source;
+    }
+
+    while (true) {
+      const response = this.#call({ specifier: source_url.href, sources });
+      if (response.error !== undefined) {
+        throw new Internal(`failed to generate prompts: ${response.error}`);
+      }
+      if (response.missing === undefined) {
+        logger.trace("prompts", `generated for ${source_url.toString()} in WebAssembly`);
+        return Prompts.fromModule(await import(`data:text/javascript,${encodeURIComponent(response.module)}`));
+      }
+      await Promise.all(response.missing.map(async (specifier: string) => {
+        sources[specifier] = await this.#load(new URL(specifier));
+      }));
+    }
+  }
+}
+
+export class Prompts<Id extends string = string> {
+  readonly #mod: PromptsModule<Id>;
+  readonly #namespace?: string;
+
+  constructor(mod: PromptsModule<Id>, namespace?: string) {
+    const format = mod.meta?.format ?? 1;
+    if (format > PROMPTS_FORMAT) {
+      throw new Internal(
+        `prompts built by kottoc ${mod.meta?.kottoc} are of format ${format}, newer than this runtime's (${PROMPTS_FORMAT}): upgrade kotto`,
+      );
+    } else if (format < PROMPTS_FORMAT) {
+      logger.eprint(
+        `prompts are of format ${format}, older than this runtime's (${PROMPTS_FORMAT}): rebuild them or run \`kottoc migrate\``,
+        "kotto",
+        "yellow",
+      );
+    }
 
-  constructor(mod: PromptsModule) {
     this.#mod = mod;
+    this.#namespace = namespace;
   }
 
-  static fromModule(mod: any): Prompts {
+  /**
+   * The prompts of `mod`, typed after its ids when imported along with its
+   * `.prompts.d.ts`
+   */
+  static fromModule<Id extends string = string>(mod: PromptsModule<Id>): Prompts<Id> {
     return new Prompts(mod);
   }
 
+  /**
+   * The prompts of the module `namespace` (e.g. `agent` for `agent.ts`) of a
+   * bundle built with `kottoc --bundle`
+   */
+  static fromBundle<Id extends string = string>(mod: PromptsModule<Id>, namespace: string): Prompts<Id> {
+    return new Prompts(mod, namespace);
+  }
+
   static async fromBuiltUrl(import_url: URL): Promise<Prompts> {
     return Prompts.fromModule(await import(import_url.href));
   }
@@ -111,14 +481,77 @@ export class Prompts {
     return Prompts.fromModule(await import(mod_url));
   }
 
-  newScope(): Scope {
-    return new Scope(this.#mod);
+  newScope(): Scope<Id> {
+    return new Scope(this.#mod, this.#namespace);
+  }
+
+  qualify(...pat: string[]): string[] {
+    return this.newScope().qualify(...pat);
+  }
+
+  nodeFor(ast_ty: PromptAstTy, ...pat: string[]): PromptNode<Id> | undefined {
+    return this.newScope().iterFor(ast_ty, ...pat)[0];
+  }
+
+  /**
+   * The builtin functions (e.g. `Deno.remove`) that the module opted in to,
+   * or `undefined` if the prompts do not record them
+   */
+  builtins(): string[] | undefined {
+    return this.#source()?.builtins ?? undefined;
+  }
+
+  /**
+   * Warns if the prompts are out of date: built by another version of kottoc
+   * than this runtime's, or from another source than that of the module at
+   * `source_url`. Returns whether they are.
+   */
+  async checkStale(source_url: URL): Promise<boolean> {
+    const meta = this.#mod.meta;
+    if (meta === undefined) {
+      // Already warned about, as of format 1
+      return false;
+    }
+
+    const reasons = [];
+    if (meta.kottoc !== KOTTOC_VERSION) {
+      reasons.push(`built by kottoc ${meta.kottoc}, not ${KOTTOC_VERSION}`);
+    }
+    const source = this.#source();
+    if (source !== undefined && source.hash !== await sha256(await fetchSource(source_url))) {
+      reasons.push(`${source_url} changed since they were built`);
+    }
+
+    if (reasons.length > 0) {
+      logger.eprint(`prompts are out of date (${reasons.join("; ")}): rebuild them`, "kotto", "yellow");
+    }
+    return reasons.length > 0;
+  }
+
+  // The source of the module, among those of a bundle
+  #source() {
+    const sources = this.#mod.meta?.sources ?? [];
+    return this.#namespace === undefined
+      ? (sources.length === 1 ? sources[0] : undefined)
+      : sources.find(({ namespace }) => namespace === this.#namespace);
+  }
+
+  validatorFor(...pat: string[]): Validator | undefined {
+    const id_regex = new RegExp(`^${pat.join("\\.")}$`);
+    const validators: Partial<Record<string, Validator>> = this.#mod.validators ?? {};
+    const id = Object.keys(validators).find((id) => id_regex.test(id));
+    return id === undefined ? undefined : validators[id];
   }
 }
 
-export class Scope {
-  #prompts: PromptsModule;
-  #current: Map<string, PromptNode>;
+export function escapeRegExp(str: string): string {
+  return str.replace(/[.*+?^${}()|[\]\\]/g, "\\$&");
+}
+
+export class Scope<Id extends string = string> {
+  #prompts: PromptsModule<Id>;
+  #namespace?: string;
+  #current: Map<string, PromptNode<Id>>;
 
   static child = Scope.ident("\\w+");
 
@@ -126,35 +559,60 @@ export class Scope {
     return `${pat}#\\d+`;
   }
 
-  constructor(prompts: PromptsModule) {
+  constructor(prompts: PromptsModule<Id>, namespace?: string) {
     this.#prompts = prompts;
+    this.#namespace = namespace;
     this.#current = new Map();
   }
 
-  iterFor(...pat: string[]): PromptNode[] {
+  /**
+   * Qualifies an id pattern by the namespace of the module, for prompts that
+   * are part of a bundle
+   */
+  qualify(...pat: string[]): string[] {
+    return this.#namespace === undefined ? pat : [escapeRegExp(this.#namespace), ...pat];
+  }
+
+  iterFor(...pat: string[]): PromptNode<Id>[] {
     const ast_ty_regex_str = `^${pat[0]}$`;
     const ast_ty_regex = new RegExp(ast_ty_regex_str);
     const id_regex_str = `^${pat.slice(1).join("\\.")}$`;
     const id_regex = new RegExp(id_regex_str);
-    return this.#prompts.ast.filter((node) => ast_ty_regex.test(node.ast_ty) && id_regex.test(node.id));
+    return this.#prompts.ast.filter((node) => ast_ty_regex.test(node.ast_ty ?? "") && id_regex.test(node.id));
   }
 
-  addFromId(...pat: string[]) {
+  /**
+   * Adds the nodes whose node type and id match the pattern `pat` (e.g.
+   * `"fn_decl", "builtins", ".+"`), along with their context
+   */
+  addMatching(...pat: string[]) {
     this.iterFor(...pat).forEach((node) => {
       this.#current.set(node.id, node);
-      node.context?.forEach((node_id) => {
-        if (!this.#current.has(node_id)) {
-          this.addFromId(...node_id.split("."));
+      node.context?.forEach((context_id) => {
+        const id = context_id.slice(context_id.indexOf(".") + 1);
+        if (!this.#current.has(id)) {
+          this.addFromId(context_id);
         }
       });
     });
   }
 
-  addNode(node: PromptNode) {
+  /**
+   * Adds the nodes `ids` (e.g. `method_decl.Agent#2.search#0`, one of the
+   * `ContextId`s of the module's `.prompts.d.ts`), along with their context
+   */
+  addFromId(...ids: ContextId<Id>[]) {
+    ids.forEach((context_id) => {
+      const [ast_ty, ...id] = context_id.split(".");
+      this.addMatching(ast_ty, ...id.map(escapeRegExp));
+    });
+  }
+
+  addNode(node: PromptNode<Id>) {
     this.#current.set(node.id, node);
   }
 
-  current(): PromptNode[] {
+  current(): PromptNode<Id>[] {
     return Array.from(this.#current.values());
   }
 }
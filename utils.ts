@@ -41,39 +41,66 @@ async function resolveLatestRelease(): Promise<URL> {
   );
 }
 
-async function donwloadLatestRelease() {
-  const github_bin_url = await resolveLatestRelease();
-
+/**
+ * Where `kotto upgrade` installs kottoc built for WebAssembly (see `WasmCompiler`)
+ */
+export function wasmPath(): string {
   const home = Deno.env.get("HOME");
 
   if (home === undefined) throw new Error("could not locate home");
 
-  const bin_target_path = join(home, ".local/bin/kottoc");
-  const bin_target_dir = dirname(bin_target_path);
+  return join(home, ".local/share/kotto/kottoc.wasm");
+}
 
-  await ensureDir(bin_target_dir);
+async function download(url: URL, target_path: string): Promise<boolean> {
+  await ensureDir(dirname(target_path));
 
   const resp = await fetch(
-    new Request(github_bin_url, {
+    new Request(url, {
       redirect: "follow",
     }),
   );
 
   if (resp.status != 200) {
-    error(`could not download kottoc: ${resp.statusText}`);
-    return;
+    error(`could not download ${url}: ${resp.statusText}`);
+    return false;
   }
 
-  info(`downloading ${github_bin_url}`);
+  info(`downloading ${url}`);
 
-  const f = await Deno.open(bin_target_path, {
+  const f = await Deno.open(target_path, {
     write: true,
     create: true,
+    truncate: true,
   });
 
   await resp.body?.pipeTo(f.writable);
 
-  await Deno.chmod(bin_target_path, 0o755);
+  return true;
+}
+
+async function donwloadLatestRelease() {
+  const github_bin_url = await resolveLatestRelease();
+
+  const home = Deno.env.get("HOME");
+
+  if (home === undefined) throw new Error("could not locate home");
+
+  const bin_target_path = join(home, ".local/bin/kottoc");
+
+  if (await download(github_bin_url, bin_target_path)) {
+    await Deno.chmod(bin_target_path, 0o755);
+  }
+}
+
+/**
+ * Downloads kottoc built for WebAssembly from the latest release to `wasmPath()`
+ */
+export async function downloadWasm() {
+  await download(
+    new URL(`${KOTTO_REPO}/releases/latest/download/kottoc.wasm`),
+    wasmPath(),
+  );
 }
 
 export async function runCargoInstall() {